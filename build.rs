@@ -5,9 +5,9 @@
 // https://opensource.org/licenses/MIT
 
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 fn main() {
     // Put the linker script somewhere the linker can find it
@@ -16,32 +16,104 @@ fn main() {
 
     has_fpu(&target);
 
+    // The hosted port links like any other Linux program
+    if target.contains("-linux-") {
+        println!("cargo:rustc-cfg=host");
+        println!("cargo:rerun-if-changed=build.rs");
+        return;
+    }
+
+    // AArch64 runs on QEMU virt and has a linker script of its own
+    if target.starts_with("aarch64-") {
+        println!("cargo:rustc-cfg=arm64");
+        println!("cargo:rustc-cfg=novm");
+
+        File::create(out.join("link.x"))
+            .unwrap()
+            .write_all(include_bytes!("linkers/link-arm64.x.in"))
+            .unwrap();
+        File::create(out.join("memory.x"))
+            .unwrap()
+            .write_all(include_bytes!("linkers/memory-qemu-virt-arm64.x.in"))
+            .unwrap();
+
+        println!("cargo:rustc-link-search={}", out.display());
+        println!("cargo:rerun-if-changed=build.rs");
+        println!("cargo:rerun-if-changed=linkers/link-arm64.x.in");
+        println!("cargo:rerun-if-changed=linkers/memory-qemu-virt-arm64.x.in");
+        return;
+    }
+
+    // RISC-V runs on QEMU virt, or sifive_e with the sifive-e feature
+    if target.starts_with("riscv32") {
+        println!("cargo:rustc-cfg=riscv");
+        println!("cargo:rustc-cfg=novm");
+
+        let memory_x: &[u8] = if env::var_os("CARGO_FEATURE_SIFIVE_E").is_some() {
+            include_bytes!("linkers/memory-sifive-e.x.in")
+        } else {
+            include_bytes!("linkers/memory-qemu-virt-riscv.x.in")
+        };
+
+        File::create(out.join("link.x"))
+            .unwrap()
+            .write_all(include_bytes!("linkers/link-riscv.x.in"))
+            .unwrap();
+        File::create(out.join("memory.x"))
+            .unwrap()
+            .write_all(memory_x)
+            .unwrap();
+
+        println!("cargo:rustc-link-search={}", out.display());
+        println!("cargo:rerun-if-changed=build.rs");
+        println!("cargo:rerun-if-changed=linkers/link-riscv.x.in");
+        println!("cargo:rerun-if-changed=linkers/memory-qemu-virt-riscv.x.in");
+        println!("cargo:rerun-if-changed=linkers/memory-sifive-e.x.in");
+        return;
+    }
+
+    assert!(
+        env::var_os("CARGO_FEATURE_SIFIVE_E").is_none(),
+        "the sifive-e feature needs a riscv32 target"
+    );
+
     // Put the linker script somewhere the linker can find it
     let kernel_ld = include_bytes!("linkers/link.x.in");
-    let mut f = if env::var_os("CARGO_FEATURE_DEVICE").is_some() {
-        let mut f = File::create(out.join("link.x")).unwrap();
-        f.write_all(kernel_ld).unwrap();
+    let device = env::var_os("CARGO_FEATURE_DEVICE").is_some();
+    let mut f = File::create(out.join("link.x")).unwrap();
+    f.write_all(kernel_ld).unwrap();
+    if device {
         // *IMPORTANT*: The weak aliases (i.e. `PROVIDED`) must come *after*
         // `EXTERN(__INTERRUPTS)`. Otherwise the linker will ignore user
         // defined interrupts and always populate the table with the weak aliases.
         writeln!(
             f,
             r#"
+/* Device specific interrupt handlers default to `default_handler`, under the
+   name svd2rust generated device crates use */
+PROVIDE(DefaultHandler = default_handler);
+
 /* Provides weak aliases (cf. PROVIDED) for device specific interrupt handlers */
 /* This will usually be provided by a device crate generated using svd2rust (see `device.ld`)*/
 INCLUDE device.ld"#).unwrap();
-        f
-    } else {
-        let mut f = File::create(out.join("link.x")).unwrap();
-        f.write_all(kernel_ld).unwrap();
-        f
-    };
+    }
 
-    let max_int_handlers = if target.starts_with("thumbv7m-") || target.starts_with("thumbv7em-") {
+    let max_int_handlers = if target.starts_with("thumbv6m-") {
+        println!("cargo:rustc-cfg=cortex_m");
+        println!("cargo:rustc-cfg=armv6m");
+        println!("cargo:rustc-cfg=novm");
+        32
+    } else if target.starts_with("thumbv7m-") || target.starts_with("thumbv7em-") {
         println!("cargo:rustc-cfg=cortex_m");
         println!("cargo:rustc-cfg=armv7m");
         println!("cargo:rustc-cfg=novm");
         240
+    } else if target.starts_with("thumbv8m.main-") {
+        println!("cargo:rustc-cfg=cortex_m");
+        println!("cargo:rustc-cfg=armv8m");
+        println!("cargo:rustc-cfg=armv8m_main");
+        println!("cargo:rustc-cfg=novm");
+        480
     } else {
         // Non ARM target. We assume you're just testing the syntax.
         // This value seems as soon as any
@@ -62,15 +134,143 @@ handlers.");
         max_int_handlers
     ).unwrap();
 
+    if device {
+        device_interrupts(&target, &out, max_int_handlers);
+    }
+
+    // The memory layout of the board QEMU emulates for this target
+    let memory_x: &[u8] = if target.starts_with("thumbv6m-") {
+        include_bytes!("linkers/memory-microbit.x.in")
+    } else if target.starts_with("thumbv8m.main-") {
+        if env::var_os("CARGO_FEATURE_TRUSTZONE").is_some() {
+            include_bytes!("linkers/memory-mps2-an505-s.x.in")
+        } else {
+            include_bytes!("linkers/memory-mps2-an505.x.in")
+        }
+    } else {
+        assert!(
+            env::var_os("CARGO_FEATURE_TRUSTZONE").is_none(),
+            "the trustzone feature needs a thumbv8m.main target"
+        );
+        include_bytes!("linkers/memory.x.in")
+    };
+
     File::create(out.join("memory.x"))
         .unwrap()
-        .write_all(include_bytes!("linkers/memory.x.in"))
+        .write_all(memory_x)
         .unwrap();
 
     println!("cargo:rustc-link-search={}", out.display());
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=linkers/link.x.in");
     println!("cargo:rerun-if-changed=linkers/memory.x.in");
+    println!("cargo:rerun-if-changed=linkers/memory-microbit.x.in");
+    println!("cargo:rerun-if-changed=linkers/memory-mps2-an505.x.in");
+    println!("cargo:rerun-if-changed=linkers/memory-mps2-an505-s.x.in");
+}
+
+/// Copy the interrupt names of the device to device.ld, and generate the
+/// `__INTERRUPTS` table and the `Interrupt` enum of src/arch/arm/cortex_m/start.rs
+/// from them
+///
+/// `PARTICLE_DEVICE_LD` names the file, the `device.x` of a svd2rust generated
+/// crate will do. thumbv7m defaults to the LM3S6965 QEMU emulates. Each
+/// `PROVIDE(NAME = DefaultHandler);` is the interrupt after the previous one,
+/// unless a comment after it gives its number: `PROVIDE(UART2 = DefaultHandler); /* 33 */`.
+fn device_interrupts(target: &str, out: &Path, max_int_handlers: usize) {
+    println!("cargo:rerun-if-env-changed=PARTICLE_DEVICE_LD");
+    let path = match env::var_os("PARTICLE_DEVICE_LD") {
+        Some(path) => PathBuf::from(path),
+        None if target.starts_with("thumbv7m-") => PathBuf::from("linkers/device-lm3s6965.ld"),
+        None => panic!("the device feature needs PARTICLE_DEVICE_LD on {}", target),
+    };
+    println!("cargo:rerun-if-changed={}", path.display());
+
+    let device_ld = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("can't read {}: {}", path.display(), e));
+    fs::write(out.join("device.ld"), &device_ld).unwrap();
+
+    let mut interrupts: Vec<(String, usize)> = Vec::new();
+    for line in device_ld.lines().map(str::trim) {
+        if !line.starts_with("PROVIDE(") {
+            continue;
+        }
+        let end = line.find(')').expect("unterminated PROVIDE in device.ld");
+        let mut alias = line["PROVIDE(".len()..end].split('=').map(str::trim);
+        let (name, handler) = (alias.next().unwrap(), alias.next().unwrap_or(""));
+        if handler != "DefaultHandler" {
+            continue;
+        }
+
+        let next = interrupts.last().map_or(0, |&(_, nr)| nr + 1);
+        let nr = match (line.find("/*"), line.find("*/")) {
+            (Some(start), Some(end)) => line[start + 2..end]
+                .trim()
+                .parse()
+                .unwrap_or_else(|_| panic!("bad interrupt number in device.ld: {}", line)),
+            _ => next,
+        };
+        assert!(nr >= next, "device.ld is not in vector order at {}", name);
+        interrupts.push((name.to_string(), nr));
+    }
+
+    assert!(
+        !interrupts.is_empty(),
+        "{} names no interrupts",
+        path.display()
+    );
+    let count = interrupts.last().map_or(0, |&(_, nr)| nr + 1);
+    assert!(
+        count <= max_int_handlers,
+        "device.ld has {} interrupts, the NVIC of {} at most {}",
+        count,
+        target,
+        max_int_handlers
+    );
+
+    let mut f = File::create(out.join("interrupts.rs")).unwrap();
+    writeln!(f, "/// Number of external interrupts of the device").unwrap();
+    writeln!(f, "pub const INTERRUPT_COUNT: usize = {};", count).unwrap();
+
+    writeln!(f, "\n/// The interrupts of the device, from device.ld").unwrap();
+    writeln!(f, "#[allow(dead_code, non_camel_case_types, missing_docs)]").unwrap();
+    writeln!(f, "#[derive(Clone, Copy, Debug, PartialEq, Eq)]").unwrap();
+    writeln!(f, "pub enum Interrupt {{").unwrap();
+    for (name, nr) in &interrupts {
+        writeln!(f, "    {} = {},", name, nr).unwrap();
+    }
+    writeln!(f, "}}").unwrap();
+
+    if count <= 256 {
+        writeln!(f, "\nunsafe impl cortex_m::interrupt::Nr for Interrupt {{").unwrap();
+        writeln!(f, "    fn nr(&self) -> u8 {{").unwrap();
+        writeln!(f, "        *self as u8").unwrap();
+        writeln!(f, "    }}").unwrap();
+        writeln!(f, "}}").unwrap();
+    }
+
+    // Handlers are bound by name, default_handler unless someone defines them
+    writeln!(f, "\n#[allow(non_snake_case)]\nextern \"C\" {{").unwrap();
+    for (name, _) in &interrupts {
+        writeln!(f, "    fn {}();", name).unwrap();
+    }
+    writeln!(f, "}}").unwrap();
+
+    writeln!(f, "\n#[doc(hidden)]").unwrap();
+    writeln!(f, "#[link_section = \".vector_table.interrupts\"]").unwrap();
+    writeln!(f, "#[no_mangle]").unwrap();
+    writeln!(f, "pub static __INTERRUPTS: [Vector; INTERRUPT_COUNT] = [").unwrap();
+    let mut interrupts = interrupts.iter().peekable();
+    for n in 0..count {
+        match interrupts.peek() {
+            Some(&&(ref name, nr)) if nr == n => {
+                writeln!(f, "    Vector {{ handler: {} }},", name).unwrap();
+                interrupts.next();
+            }
+            _ => writeln!(f, "    Vector {{ reserved: 0 }},").unwrap(),
+        }
+    }
+    writeln!(f, "];").unwrap();
 }
 
 fn has_fpu(target: &str) {
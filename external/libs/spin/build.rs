@@ -0,0 +1,12 @@
+use std::env;
+
+fn main() {
+    let target = env::var("TARGET").unwrap();
+
+    // No compare-and-swap on ARMv6-M, see src/cas.rs
+    if target.starts_with("thumbv6m-") {
+        println!("cargo:rustc-cfg=spin_critical_section");
+    }
+
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,13 @@
+//! The atomics the locks are built on
+//!
+//! Normally these are the ones from `core`. With the `model` feature they are
+//! the instrumented ones of `model::atomic`, so the schedule explorer can
+//! switch threads before every access.
+
+#[cfg(not(feature = "model"))]
+pub use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering, spin_loop_hint as cpu_relax};
+
+#[cfg(feature = "model")]
+pub use core::sync::atomic::Ordering;
+#[cfg(feature = "model")]
+pub use model::atomic::{AtomicBool, AtomicUsize, spin_loop_hint as cpu_relax};
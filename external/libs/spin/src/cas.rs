@@ -0,0 +1,113 @@
+//! Compare-and-swap for the lock implementations
+//!
+//! Some targets, like thumbv6m (Cortex-M0/M0+), can load and store atomically
+//! but have no compare-and-swap instruction. On those the build script sets
+//! `spin_critical_section` and the read-modify-write operations below run
+//! inside a critical section instead. The platform provides it through two
+//! functions:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub extern "C" fn spin_critical_section_enter() -> usize;
+//! #[no_mangle]
+//! pub extern "C" fn spin_critical_section_exit(state: usize);
+//! ```
+//!
+//! This only makes the locks correct on single core systems, where
+//! disabling interrupts is enough to keep everyone else out.
+
+use atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Read-modify-write operations used by `Mutex`, `RwLock` and `Once`
+pub trait Cas<T> {
+    /// Stores `new` if the current value is `current`, returns the previous value
+    fn cas(&self, current: T, new: T, order: Ordering) -> T;
+}
+
+/// Subtraction used by `RwLock` to drop readers
+pub trait Sub {
+    /// Subtracts `val` from the current value, returns the previous value
+    fn sub(&self, val: usize, order: Ordering) -> usize;
+}
+
+#[cfg(not(spin_critical_section))]
+impl Cas<bool> for AtomicBool {
+    #[inline(always)]
+    fn cas(&self, current: bool, new: bool, order: Ordering) -> bool {
+        self.compare_and_swap(current, new, order)
+    }
+}
+
+#[cfg(not(spin_critical_section))]
+impl Cas<usize> for AtomicUsize {
+    #[inline(always)]
+    fn cas(&self, current: usize, new: usize, order: Ordering) -> usize {
+        self.compare_and_swap(current, new, order)
+    }
+}
+
+#[cfg(not(spin_critical_section))]
+impl Sub for AtomicUsize {
+    #[inline(always)]
+    fn sub(&self, val: usize, order: Ordering) -> usize {
+        self.fetch_sub(val, order)
+    }
+}
+
+#[cfg(spin_critical_section)]
+extern "C" {
+    fn spin_critical_section_enter() -> usize;
+    fn spin_critical_section_exit(state: usize);
+}
+
+/// Runs `f` with interrupts disabled
+#[cfg(spin_critical_section)]
+#[inline(always)]
+fn critical_section<R, F: FnOnce() -> R>(f: F) -> R {
+    unsafe {
+        let state = spin_critical_section_enter();
+        let r = f();
+        spin_critical_section_exit(state);
+        r
+    }
+}
+
+#[cfg(spin_critical_section)]
+impl Cas<bool> for AtomicBool {
+    #[inline(always)]
+    fn cas(&self, current: bool, new: bool, _order: Ordering) -> bool {
+        critical_section(|| {
+            let old = self.load(Ordering::SeqCst);
+            if old == current {
+                self.store(new, Ordering::SeqCst);
+            }
+            old
+        })
+    }
+}
+
+#[cfg(spin_critical_section)]
+impl Cas<usize> for AtomicUsize {
+    #[inline(always)]
+    fn cas(&self, current: usize, new: usize, _order: Ordering) -> usize {
+        critical_section(|| {
+            let old = self.load(Ordering::SeqCst);
+            if old == current {
+                self.store(new, Ordering::SeqCst);
+            }
+            old
+        })
+    }
+}
+
+#[cfg(spin_critical_section)]
+impl Sub for AtomicUsize {
+    #[inline(always)]
+    fn sub(&self, val: usize, _order: Ordering) -> usize {
+        critical_section(|| {
+            let old = self.load(Ordering::SeqCst);
+            self.store(old.wrapping_sub(val), Ordering::SeqCst);
+            old
+        })
+    }
+}
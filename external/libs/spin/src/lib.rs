@@ -5,7 +5,7 @@
 
 #![no_std]
 
-#[cfg(test)]
+#[cfg(any(test, feature = "model"))]
 #[macro_use]
 extern crate std;
 
@@ -16,3 +16,8 @@ pub use once::*;
 mod mutex;
 mod rw_lock;
 mod once;
+mod cas;
+mod atomic;
+
+#[cfg(feature = "model")]
+pub mod model;
@@ -0,0 +1,83 @@
+//! Atomics that let the explorer switch threads before every access
+//!
+//! They have the subset of the `core::sync::atomic` interface the locks use.
+//! Every access is sequentially consistent, whatever its `Ordering`.
+
+use core::sync::atomic::{self, Ordering};
+
+/// Instrumented `core::sync::atomic::AtomicBool`
+#[derive(Debug, Default)]
+pub struct AtomicBool {
+    v: atomic::AtomicBool,
+}
+
+/// Instrumented `core::sync::atomic::AtomicUsize`
+#[derive(Debug, Default)]
+pub struct AtomicUsize {
+    v: atomic::AtomicUsize,
+}
+
+/// Instrumented `core::sync::atomic::spin_loop_hint`
+///
+/// Inside `check()` the thread stops until some thread writes, when nothing
+/// was written since its previous call. Spinning on memory that nobody is
+/// going to write is reported as a deadlock.
+pub fn spin_loop_hint() {
+    super::relax()
+}
+
+macro_rules! model_atomic {
+    ($atomic:ident, $t:ty) => {
+        impl $atomic {
+            /// Creates a new atomic
+            pub const fn new(v: $t) -> $atomic {
+                $atomic { v: atomic::$atomic::new(v) }
+            }
+
+            /// Loads the value
+            pub fn load(&self, _order: Ordering) -> $t {
+                super::access(|| (self.v.load(Ordering::SeqCst), false))
+            }
+
+            /// Stores `val`
+            pub fn store(&self, val: $t, _order: Ordering) {
+                super::access(|| (self.v.store(val, Ordering::SeqCst), true))
+            }
+
+            /// Stores `val`, returns the previous value
+            pub fn swap(&self, val: $t, _order: Ordering) -> $t {
+                super::access(|| (self.v.swap(val, Ordering::SeqCst), true))
+            }
+
+            /// Stores `new` if the value is `current`, returns the previous value
+            pub fn compare_and_swap(&self, current: $t, new: $t, _order: Ordering) -> $t {
+                super::access(|| {
+                    match self.v.compare_exchange(current, new, Ordering::SeqCst, Ordering::SeqCst) {
+                        Ok(old) => (old, true),
+                        Err(old) => (old, false),
+                    }
+                })
+            }
+
+            /// Consumes the atomic and returns the value
+            pub fn into_inner(self) -> $t {
+                self.v.into_inner()
+            }
+        }
+    }
+}
+
+model_atomic!(AtomicBool, bool);
+model_atomic!(AtomicUsize, usize);
+
+impl AtomicUsize {
+    /// Adds `val`, returns the previous value
+    pub fn fetch_add(&self, val: usize, _order: Ordering) -> usize {
+        super::access(|| (self.v.fetch_add(val, Ordering::SeqCst), true))
+    }
+
+    /// Subtracts `val`, returns the previous value
+    pub fn fetch_sub(&self, val: usize, _order: Ordering) -> usize {
+        super::access(|| (self.v.fetch_sub(val, Ordering::SeqCst), true))
+    }
+}
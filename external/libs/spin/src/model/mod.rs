@@ -0,0 +1,620 @@
+//! Schedule exploration for the locks
+//!
+//! With the `model` feature the locks are built on the atomics of
+//! `model::atomic`. Inside `check()` every access to them is a point where
+//! the explorer may switch to another thread, so a test closure runs once for
+//! every interleaving of its threads (up to a number of preemptions), or for
+//! a number of random ones. Each execution fails on
+//!
+//! - a panic of any thread, for example a failed assertion,
+//! - a deadlock or lost wakeup: every thread left is spinning on memory
+//!   nobody is going to write, or joining such a thread,
+//! - a livelock: the execution does not finish within `max_steps` accesses.
+//!
+//! The failure names the schedule that led to it, the thread that ran at each
+//! choice, which replays the execution with `Builder::schedule()` or
+//! `SPIN_MODEL_SCHEDULE`.
+//!
+//! ```ignore
+//! spin::model::check(|| {
+//!     let lock = Arc::new(spin::Mutex::new(0));
+//!     let other = lock.clone();
+//!     let t = spin::model::thread::spawn(move || *other.lock() += 1);
+//!     *lock.lock() += 1;
+//!     t.join();
+//!     assert_eq!(*lock.lock(), 2);
+//! });
+//! ```
+//!
+//! Threads are real threads, but only one of them runs at a time and every
+//! access is sequentially consistent: the explorer finds interleavings that
+//! break a lock, not missing memory orderings. State must be created inside
+//! the closure, a `static` lock is not reset between executions.
+//!
+//! Outside `check()` the model atomics behave like the ones of `core`.
+
+use std::any::Any;
+use std::boxed::Box;
+use std::cell::RefCell;
+use std::env;
+use std::fmt;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::string::{String, ToString};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread as os;
+use std::vec::Vec;
+
+pub mod atomic;
+pub mod thread;
+
+/// Default bound on the preemptions of one execution
+const MAX_PREEMPTIONS: usize = 2;
+/// Default bound on the accesses of one execution
+const MAX_STEPS: usize = 100_000;
+/// Default number of random executions
+const RANDOM_ITERATIONS: usize = 1000;
+
+/// Replays the given schedule instead of exploring
+const ENV_SCHEDULE: &str = "SPIN_MODEL_SCHEDULE";
+/// Explores random schedules starting from the given seed
+const ENV_SEED: &str = "SPIN_MODEL_SEED";
+
+/// Why an execution failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailureKind {
+    /// A thread panicked
+    Panic,
+    /// No thread can make progress
+    Deadlock,
+    /// The execution took more than `max_steps` accesses
+    Livelock,
+    /// The replayed schedule does not fit the execution
+    Schedule,
+}
+
+/// A failed execution
+#[derive(Clone, Debug)]
+pub struct Failure {
+    /// Why it failed
+    pub kind: FailureKind,
+    /// What went wrong, in words
+    pub message: String,
+    /// The schedule that led to the failure, see `Builder::schedule()`
+    pub schedule: String,
+    /// The number of executions before this one
+    pub iteration: usize,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "spin::model: {} in execution {}\n", self.message, self.iteration)?;
+        write!(f, "replay with {}={}", ENV_SCHEDULE, self.schedule)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Strategy {
+    Exhaustive,
+    Random { seed: u64, iterations: usize },
+    Replay(Vec<usize>),
+}
+
+/// Configures an exploration
+#[derive(Clone, Debug)]
+pub struct Builder {
+    max_preemptions: usize,
+    max_steps: usize,
+    strategy: Strategy,
+}
+
+impl Builder {
+    /// Explores every schedule with up to 2 preemptions
+    pub fn new() -> Builder {
+        Builder {
+            max_preemptions: MAX_PREEMPTIONS,
+            max_steps: MAX_STEPS,
+            strategy: Strategy::Exhaustive,
+        }
+    }
+
+    /// Bounds the number of times a thread is switched out while it could
+    /// have continued
+    pub fn max_preemptions(mut self, max: usize) -> Builder {
+        self.max_preemptions = max;
+        self
+    }
+
+    /// Bounds the number of accesses of one execution
+    pub fn max_steps(mut self, max: usize) -> Builder {
+        self.max_steps = max;
+        self
+    }
+
+    /// Runs `iterations` random schedules instead of all of them
+    pub fn random(mut self, seed: u64, iterations: usize) -> Builder {
+        self.strategy = Strategy::Random { seed: seed, iterations: iterations };
+        self
+    }
+
+    /// Runs the one schedule of a `Failure`, a comma separated list of threads
+    pub fn schedule(mut self, schedule: &str) -> Builder {
+        self.strategy = Strategy::Replay(parse_schedule(schedule));
+        self
+    }
+
+    /// Runs `f` under the configured schedules, panics on the first failure
+    pub fn check<F>(self, f: F)
+        where F: Fn() + Send + Sync + 'static
+    {
+        if let Err(failure) = self.explore(f) {
+            panic!("{}", failure);
+        }
+    }
+
+    /// Runs `f` under the configured schedules, returns the number of
+    /// executions or the first failure
+    ///
+    /// `SPIN_MODEL_SCHEDULE` and `SPIN_MODEL_SEED` in the environment take
+    /// precedence over the strategy of the builder.
+    pub fn explore<F>(mut self, f: F) -> Result<usize, Failure>
+        where F: Fn() + Send + Sync + 'static
+    {
+        if let Ok(schedule) = env::var(ENV_SCHEDULE) {
+            self.strategy = Strategy::Replay(parse_schedule(&schedule));
+        } else if let Ok(seed) = env::var(ENV_SEED) {
+            let seed = seed.trim().parse().expect("SPIN_MODEL_SEED is not a number");
+            let iterations = match self.strategy {
+                Strategy::Random { iterations, .. } => iterations,
+                _ => RANDOM_ITERATIONS,
+            };
+            self.strategy = Strategy::Random { seed: seed, iterations: iterations };
+        }
+
+        let f: Arc<dyn Fn() + Send + Sync> = Arc::new(f);
+        let mut chooser = match self.strategy {
+            Strategy::Exhaustive => Chooser::Exhaustive { path: Vec::new(), pos: 0 },
+            Strategy::Random { seed, .. } => Chooser::Random(Rng::new(seed)),
+            Strategy::Replay(ref schedule) => Chooser::Replay { schedule: schedule.clone(), pos: 0 },
+        };
+
+        let mut iteration = 0;
+        loop {
+            let (result, back) = execute(&self, chooser, f.clone());
+            chooser = back;
+            if let Err(mut failure) = result {
+                failure.iteration = iteration;
+                return Err(failure);
+            }
+            iteration += 1;
+
+            let more = match (&self.strategy, &mut chooser) {
+                (&Strategy::Exhaustive, &mut Chooser::Exhaustive { ref mut path, ref mut pos }) => {
+                    *pos = 0;
+                    backtrack(path)
+                }
+                (&Strategy::Random { iterations, .. }, _) => iteration < iterations,
+                _ => false,
+            };
+            if !more {
+                return Ok(iteration);
+            }
+        }
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
+/// Runs `f` under every schedule with up to 2 preemptions, panics on the
+/// first failure
+pub fn check<F>(f: F)
+    where F: Fn() + Send + Sync + 'static
+{
+    Builder::new().check(f)
+}
+
+fn parse_schedule(schedule: &str) -> Vec<usize> {
+    schedule.split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().expect("a schedule is a comma separated list of threads"))
+        .collect()
+}
+
+/// Moves to the next unexplored path, false once all have been explored
+fn backtrack(path: &mut Vec<Branch>) -> bool {
+    while let Some(last) = path.pop() {
+        if last.index + 1 < last.count {
+            path.push(Branch { index: last.index + 1, count: last.count });
+            return true;
+        }
+    }
+    false
+}
+
+/// xorshift64*, good enough to pick threads
+#[derive(Clone, Debug)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed ^ 0x9e37_79b9_7f4a_7c15 | 1)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 32) as usize % n
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Branch {
+    index: usize,
+    count: usize,
+}
+
+/// Picks the next thread at each choice
+enum Chooser {
+    Exhaustive { path: Vec<Branch>, pos: usize },
+    Random(Rng),
+    Replay { schedule: Vec<usize>, pos: usize },
+}
+
+impl Chooser {
+    fn choose(&mut self, candidates: &[usize]) -> Result<usize, String> {
+        match *self {
+            Chooser::Exhaustive { ref mut path, ref mut pos } => {
+                if *pos == path.len() {
+                    path.push(Branch { index: 0, count: candidates.len() });
+                }
+                let branch = path[*pos];
+                *pos += 1;
+                if branch.count != candidates.len() {
+                    return Err(format!("the test is not deterministic, choice {} had {} threads \
+                                        to pick from and now has {}",
+                                       *pos - 1, branch.count, candidates.len()));
+                }
+                Ok(candidates[branch.index])
+            }
+            Chooser::Random(ref mut rng) => Ok(candidates[rng.below(candidates.len())]),
+            Chooser::Replay { ref schedule, ref mut pos } => {
+                let id = match schedule.get(*pos) {
+                    Some(&id) => id,
+                    None => return Ok(candidates[0]),
+                };
+                *pos += 1;
+                if candidates.contains(&id) {
+                    Ok(id)
+                } else {
+                    Err(format!("choice {} of the schedule picks thread {}, but only {:?} can run",
+                                *pos - 1, id, candidates))
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Runnable,
+    /// In `cpu_relax()` without any write since its last one
+    Spinning,
+    Joining(usize),
+    Finished,
+}
+
+struct ModelThread {
+    state: State,
+    /// Write count at its last `cpu_relax()`
+    relaxed_at: usize,
+}
+
+/// Unwinds the threads of an execution that already failed
+struct Aborted;
+
+/// One run of the test closure
+struct Execution {
+    threads: Vec<ModelThread>,
+    /// The thread allowed to run
+    active: usize,
+    chooser: Chooser,
+    /// The thread picked at each choice
+    trace: Vec<usize>,
+    preemptions: usize,
+    max_preemptions: usize,
+    steps: usize,
+    max_steps: usize,
+    /// Number of writes so far
+    writes: usize,
+    failure: Option<Failure>,
+    aborted: bool,
+    done: bool,
+    handles: Vec<os::JoinHandle<()>>,
+}
+
+impl Execution {
+    fn schedule(&self) -> String {
+        let ids: Vec<String> = self.trace.iter().map(|id| id.to_string()).collect();
+        ids.join(",")
+    }
+
+    fn fail(&mut self, kind: FailureKind, message: String) {
+        if self.failure.is_none() {
+            self.failure = Some(Failure {
+                kind: kind,
+                message: message,
+                schedule: self.schedule(),
+                iteration: 0,
+            });
+        }
+        self.aborted = true;
+    }
+
+    fn blocked(&self) -> String {
+        let blocked: Vec<String> = self.threads.iter().enumerate().filter_map(|(id, t)| {
+            match t.state {
+                State::Spinning => Some(format!("thread {} spinning", id)),
+                State::Joining(other) => Some(format!("thread {} joining thread {}", id, other)),
+                _ => None,
+            }
+        }).collect();
+        blocked.join(", ")
+    }
+
+    /// A write lets every spinning thread look again
+    fn wrote(&mut self) {
+        self.writes += 1;
+        for t in self.threads.iter_mut() {
+            if t.state == State::Spinning {
+                t.state = State::Runnable;
+            }
+        }
+    }
+
+    /// Picks the thread to run after `me`, `None` if nobody can
+    ///
+    /// Switching away from `me` while it is runnable is a preemption, unless
+    /// `free` says it yields on its own.
+    fn next(&mut self, me: usize, free: bool) -> Option<usize> {
+        let runnable = self.threads[me].state == State::Runnable;
+        let mut candidates = Vec::new();
+        if runnable {
+            candidates.push(me);
+        }
+        if !runnable || free || self.preemptions < self.max_preemptions {
+            for (id, t) in self.threads.iter().enumerate() {
+                if id != me && t.state == State::Runnable {
+                    candidates.push(id);
+                }
+            }
+        }
+
+        match candidates.len() {
+            0 => None,
+            1 => Some(candidates[0]),
+            _ => match self.chooser.choose(&candidates) {
+                Ok(next) => {
+                    self.trace.push(next);
+                    if runnable && !free && next != me {
+                        self.preemptions += 1;
+                    }
+                    Some(next)
+                }
+                Err(message) => {
+                    self.fail(FailureKind::Schedule, message);
+                    None
+                }
+            },
+        }
+    }
+
+    /// Hands over to the next thread, or ends the execution
+    fn hand_over(&mut self, me: usize, free: bool) {
+        match self.next(me, free) {
+            Some(next) => self.active = next,
+            None if self.aborted => (),
+            None if self.threads.iter().all(|t| t.state == State::Finished) => self.done = true,
+            None => {
+                let message = format!("deadlock or lost wakeup: {}", self.blocked());
+                self.fail(FailureKind::Deadlock, message);
+            }
+        }
+    }
+}
+
+struct Shared {
+    execution: Mutex<Execution>,
+    turn: Condvar,
+}
+
+impl Shared {
+    fn lock<'a>(&'a self) -> MutexGuard<'a, Execution> {
+        self.execution.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Waits until `me` may run again
+    fn wait<'a>(&'a self, mut ex: MutexGuard<'a, Execution>, me: usize) -> MutexGuard<'a, Execution> {
+        self.turn.notify_all();
+        while ex.active != me && !ex.aborted {
+            ex = self.turn.wait(ex).unwrap_or_else(|e| e.into_inner());
+        }
+        if ex.aborted {
+            drop(ex);
+            resume_unwind(Box::new(Aborted));
+        }
+        ex
+    }
+
+    /// A point where another thread may take over from `me`
+    fn switch<'a>(&'a self, mut ex: MutexGuard<'a, Execution>, me: usize, free: bool)
+        -> MutexGuard<'a, Execution>
+    {
+        ex.steps += 1;
+        if ex.steps > ex.max_steps && !ex.aborted {
+            let message = format!("livelock: no end after {} steps", ex.max_steps);
+            ex.fail(FailureKind::Livelock, message);
+        }
+        if !ex.aborted {
+            ex.hand_over(me, free);
+        }
+        self.wait(ex, me)
+    }
+}
+
+struct Context {
+    shared: Arc<Shared>,
+    id: usize,
+}
+
+thread_local! {
+    static CONTEXT: RefCell<Option<Context>> = RefCell::new(None);
+}
+
+fn with_context<R, F: FnOnce(Option<&Context>) -> R>(f: F) -> R {
+    CONTEXT.with(|c| f(c.borrow().as_ref()))
+}
+
+/// Performs the access `op` of a model atomic, which says whether it wrote
+fn access<R, F: FnOnce() -> (R, bool)>(op: F) -> R {
+    let context = with_context(|c| c.map(|c| (c.shared.clone(), c.id)));
+    match context {
+        // Guards dropped while unwinding don't take part anymore
+        Some((ref shared, me)) if !os::panicking() => {
+            let ex = shared.lock();
+            let mut ex = shared.switch(ex, me, false);
+            let (r, wrote) = op();
+            if wrote {
+                ex.wrote();
+            }
+            r
+        }
+        _ => op().0,
+    }
+}
+
+/// Spins until some thread writes, unless one already did since the last call
+fn relax() {
+    let context = with_context(|c| c.map(|c| (c.shared.clone(), c.id)));
+    match context {
+        Some((ref shared, me)) if !os::panicking() => {
+            let mut ex = shared.lock();
+            if ex.writes == ex.threads[me].relaxed_at {
+                ex.threads[me].state = State::Spinning;
+                ex = shared.switch(ex, me, true);
+            }
+            ex.threads[me].relaxed_at = ex.writes;
+        }
+        _ => {
+            #[allow(deprecated)]
+            ::core::sync::atomic::spin_loop_hint()
+        }
+    }
+}
+
+/// Starts thread `id` of the execution, it runs once it gets its turn
+fn start(shared: &Arc<Shared>, ex: &mut Execution, body: Box<dyn FnMut() + Send>) -> usize {
+    let id = ex.threads.len();
+    ex.threads.push(ModelThread { state: State::Runnable, relaxed_at: ex.writes });
+
+    let shared = shared.clone();
+    let handle = os::Builder::new()
+        .name(format!("spin-model-{}", id))
+        .spawn(move || run(shared, id, body))
+        .expect("failed to spawn a model thread");
+    ex.handles.push(handle);
+    id
+}
+
+fn run(shared: Arc<Shared>, id: usize, mut body: Box<dyn FnMut() + Send>) {
+    CONTEXT.with(|c| *c.borrow_mut() = Some(Context { shared: shared.clone(), id: id }));
+
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        drop(shared.wait(shared.lock(), id));
+        body()
+    }));
+
+    let mut ex = shared.lock();
+    if let Err(payload) = result {
+        if !payload.is::<Aborted>() {
+            let message = format!("thread {} panicked: {}", id, panic_message(&payload));
+            ex.fail(FailureKind::Panic, message);
+        }
+    }
+
+    ex.threads[id].state = State::Finished;
+    for t in ex.threads.iter_mut() {
+        if t.state == State::Joining(id) {
+            t.state = State::Runnable;
+        }
+    }
+    if !ex.aborted {
+        ex.hand_over(id, true);
+    }
+    shared.turn.notify_all();
+
+    CONTEXT.with(|c| *c.borrow_mut() = None);
+}
+
+fn panic_message(payload: &Box<dyn Any + Send>) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "Box<Any>".to_string()
+    }
+}
+
+/// Runs the closure once, with thread 0 as its main thread
+fn execute(builder: &Builder, chooser: Chooser, f: Arc<dyn Fn() + Send + Sync>)
+    -> (Result<(), Failure>, Chooser)
+{
+    let shared = Arc::new(Shared {
+        execution: Mutex::new(Execution {
+            threads: Vec::new(),
+            active: 0,
+            chooser: chooser,
+            trace: Vec::new(),
+            preemptions: 0,
+            max_preemptions: builder.max_preemptions,
+            steps: 0,
+            max_steps: builder.max_steps,
+            writes: 0,
+            failure: None,
+            aborted: false,
+            done: false,
+            handles: Vec::new(),
+        }),
+        turn: Condvar::new(),
+    });
+
+    {
+        let mut ex = shared.lock();
+        start(&shared, &mut ex, Box::new(move || f()));
+        shared.turn.notify_all();
+        while !ex.done && !ex.aborted {
+            ex = shared.turn.wait(ex).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    // Let every thread finish, the aborted ones unwind
+    loop {
+        let handle = shared.lock().handles.pop();
+        match handle {
+            Some(handle) => { let _ = handle.join(); }
+            None => break,
+        }
+    }
+
+    let mut ex = shared.lock();
+    let chooser = ::std::mem::replace(&mut ex.chooser, Chooser::Random(Rng::new(0)));
+    match ex.failure.take() {
+        Some(failure) => (Err(failure), chooser),
+        None => (Ok(()), chooser),
+    }
+}
@@ -0,0 +1,61 @@
+//! Threads of an execution
+//!
+//! They only run inside `spin::model::check()`, where the explorer decides
+//! when each of them gets to run.
+
+use std::boxed::Box;
+use std::sync::{Arc, Mutex};
+
+use super::{start, with_context, Shared, State};
+
+/// Owns a thread of the execution, to wait for its result
+pub struct JoinHandle<T> {
+    id: usize,
+    result: Arc<Mutex<Option<T>>>,
+}
+
+fn current() -> (Arc<Shared>, usize) {
+    with_context(|c| c.map(|c| (c.shared.clone(), c.id)))
+        .expect("spin::model threads only run inside spin::model::check()")
+}
+
+/// Starts a thread running `f`, it may run before `spawn` returns
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+    where F: FnOnce() -> T + Send + 'static,
+          T: Send + 'static
+{
+    let (shared, me) = current();
+    let result = Arc::new(Mutex::new(None));
+
+    let slot = result.clone();
+    let mut f = Some(f);
+    let body = Box::new(move || {
+        let f = f.take().unwrap();
+        let r = f();
+        *slot.lock().unwrap() = Some(r);
+    });
+
+    let id = start(&shared, &mut shared.lock(), body);
+    drop(shared.switch(shared.lock(), me, false));
+
+    JoinHandle { id: id, result: result }
+}
+
+impl<T> JoinHandle<T> {
+    /// Waits for the thread to finish and returns its result
+    ///
+    /// A thread that panics fails the whole execution, so there is no error
+    /// to return.
+    pub fn join(self) -> T {
+        let (shared, me) = current();
+        let mut ex = shared.lock();
+        if ex.threads[self.id].state != State::Finished {
+            ex.threads[me].state = State::Joining(self.id);
+            ex = shared.switch(ex, me, true);
+        }
+        drop(ex);
+
+        let result = self.result.lock().unwrap().take();
+        result.expect("joined thread left no result")
+    }
+}
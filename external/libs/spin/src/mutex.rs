@@ -1,4 +1,4 @@
-use core::sync::atomic::{AtomicBool, Ordering, spin_loop_hint as cpu_relax};
+use atomic::{AtomicBool, Ordering, cpu_relax};
 use core::cell::UnsafeCell;
 use core::marker::Sync;
 use core::ops::{Drop, Deref, DerefMut};
@@ -6,6 +6,8 @@ use core::fmt;
 use core::option::Option::{self, None, Some};
 use core::default::Default;
 
+use cas::Cas;
+
 /// This type provides MUTual EXclusion based on spinning.
 ///
 /// # Description
@@ -128,7 +130,7 @@ impl<T: ?Sized> Mutex<T>
 {
     fn obtain_lock(&self)
     {
-        while self.lock.compare_and_swap(false, true, Ordering::Acquire) != false
+        while self.lock.cas(false, true, Ordering::Acquire) != false
         {
             // Wait until the lock looks unlocked before retrying
             while self.lock.load(Ordering::Relaxed)
@@ -178,7 +180,7 @@ impl<T: ?Sized> Mutex<T>
     /// a guard within Some.
     pub fn try_lock(&self) -> Option<MutexGuard<T>>
     {
-        if self.lock.compare_and_swap(false, true, Ordering::Acquire) == false
+        if self.lock.cas(false, true, Ordering::Acquire) == false
         {
             Some(
                 MutexGuard {
@@ -1,7 +1,9 @@
 use core::cell::UnsafeCell;
-use core::sync::atomic::{AtomicUsize, Ordering, spin_loop_hint as cpu_relax};
+use atomic::{AtomicUsize, Ordering, cpu_relax};
 use core::fmt;
 
+use cas::Cas;
+
 /// A synchronization primitive which can be used to run a one-time global
 /// initialization. Unlike its std equivalent, this is generalized so that the
 /// closure returns a value and it is stored. Once therefore acts something like
@@ -101,9 +103,7 @@ impl<T> Once<T> {
         let mut status = self.state.load(Ordering::SeqCst);
 
         if status == INCOMPLETE {
-            status = self.state.compare_and_swap(INCOMPLETE,
-                                                 RUNNING,
-                                                 Ordering::SeqCst);
+            status = self.state.cas(INCOMPLETE, RUNNING, Ordering::SeqCst);
             if status == INCOMPLETE { // We init
                 // We use a guard (Finish) to catch panics caused by builder
                 let mut finish = Finish { state: &self.state, panicked: true };
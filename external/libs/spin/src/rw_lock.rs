@@ -1,9 +1,11 @@
-use core::sync::atomic::{AtomicUsize, Ordering, spin_loop_hint as cpu_relax};
+use atomic::{AtomicUsize, Ordering, cpu_relax};
 use core::cell::UnsafeCell;
 use core::ops::{Deref, DerefMut};
 use core::fmt;
 use core::default::Default;
 
+use cas::{Cas, Sub};
+
 /// A reader-writer lock
 ///
 /// This type of lock allows a number of readers or at most one writer at any
@@ -158,7 +160,7 @@ impl<T: ?Sized> RwLock<T>
             let new = old + 1;
             debug_assert!(new != (!USIZE_MSB) & (!0));
 
-            self.lock.compare_and_swap(old, new, Ordering::SeqCst) != old
+            self.lock.cas(old, new, Ordering::SeqCst) != old
         } {
             cpu_relax();
         }
@@ -198,9 +200,7 @@ impl<T: ?Sized> RwLock<T>
 
         let new = old + 1;
         debug_assert!(new != (!USIZE_MSB) & (!0));
-        if self.lock.compare_and_swap(old,
-                                      new,
-                                      Ordering::SeqCst) == old
+        if self.lock.cas(old, new, Ordering::SeqCst) == old
         {
             Some(RwLockReadGuard {
                 lock: &self.lock,
@@ -219,7 +219,7 @@ impl<T: ?Sized> RwLock<T>
     /// RAII.
     pub unsafe fn force_read_decrement(&self) {
         debug_assert!(self.lock.load(Ordering::Relaxed) & (!USIZE_MSB) > 0);
-        self.lock.fetch_sub(1, Ordering::SeqCst);
+        self.lock.sub(1, Ordering::SeqCst);
     }
 
     /// Force unlock exclusive write access.
@@ -259,9 +259,7 @@ impl<T: ?Sized> RwLock<T>
             let old = (!USIZE_MSB) & self.lock.load(Ordering::Relaxed);
             // Old value, with write bit set.
             let new = USIZE_MSB | old;
-            if self.lock.compare_and_swap(old,
-                                          new,
-                                          Ordering::SeqCst) == old
+            if self.lock.cas(old, new, Ordering::SeqCst) == old
             {
                 // Wait for readers to go away, then lock is ours.
                 while self.lock.load(Ordering::Relaxed) != USIZE_MSB {
@@ -298,9 +296,7 @@ impl<T: ?Sized> RwLock<T>
     #[inline]
     pub fn try_write(&self) -> Option<RwLockWriteGuard<T>>
     {
-        if self.lock.compare_and_swap(0,
-                                      USIZE_MSB,
-                                      Ordering::SeqCst) == 0
+        if self.lock.cas(0, USIZE_MSB, Ordering::SeqCst) == 0
         {
             Some(RwLockWriteGuard {
                 lock: &self.lock,
@@ -351,7 +347,7 @@ impl<'rwlock, T: ?Sized> DerefMut for RwLockWriteGuard<'rwlock, T> {
 impl<'rwlock, T: ?Sized> Drop for RwLockReadGuard<'rwlock, T> {
     fn drop(&mut self) {
         debug_assert!(self.lock.load(Ordering::Relaxed) & (!USIZE_MSB) > 0);
-        self.lock.fetch_sub(1, Ordering::SeqCst);
+        self.lock.sub(1, Ordering::SeqCst);
     }
 }
 
@@ -0,0 +1,164 @@
+//! Schedule exploration of the locks, run with `cargo test --features model`
+
+#![cfg(feature = "model")]
+
+extern crate spin;
+
+use std::sync::Arc;
+
+use spin::model::{self, thread, Builder, FailureKind};
+use spin::model::atomic::{AtomicBool, AtomicUsize, spin_loop_hint};
+use std::sync::atomic::Ordering;
+
+#[test]
+fn mutex_counter() {
+    model::check(|| {
+        let counter = Arc::new(spin::Mutex::new(0));
+        let threads: Vec<_> = (0..2).map(|_| {
+            let counter = counter.clone();
+            thread::spawn(move || *counter.lock() += 1)
+        }).collect();
+        for t in threads {
+            t.join();
+        }
+        assert_eq!(*counter.lock(), 2);
+    });
+}
+
+#[test]
+fn rwlock_readers_see_whole_writes() {
+    model::check(|| {
+        let lock = Arc::new(spin::RwLock::new((0, 0)));
+        let writer = {
+            let lock = lock.clone();
+            thread::spawn(move || {
+                let mut pair = lock.write();
+                pair.0 += 1;
+                pair.1 += 1;
+            })
+        };
+        let reader = {
+            let lock = lock.clone();
+            thread::spawn(move || {
+                let pair = lock.read();
+                assert_eq!(pair.0, pair.1);
+            })
+        };
+        {
+            let pair = lock.read();
+            assert_eq!(pair.0, pair.1);
+        }
+        writer.join();
+        reader.join();
+        assert_eq!(*lock.read(), (1, 1));
+    });
+}
+
+#[test]
+fn once_runs_once() {
+    model::check(|| {
+        let once = Arc::new(spin::Once::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let threads: Vec<_> = (0..2).map(|_| {
+            let (once, calls) = (once.clone(), calls.clone());
+            thread::spawn(move || {
+                let v = *once.call_once(|| calls.fetch_add(1, Ordering::SeqCst) + 42);
+                assert_eq!(v, 42);
+            })
+        }).collect();
+        for t in threads {
+            t.join();
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    });
+}
+
+/// Checks the flag, then sets it: both threads can get in
+struct BrokenLock(AtomicBool);
+
+impl BrokenLock {
+    fn lock(&self) {
+        while self.0.load(Ordering::Acquire) {
+            spin_loop_hint();
+        }
+        self.0.store(true, Ordering::Release);
+    }
+
+    fn unlock(&self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+fn broken_lock() {
+    let lock = Arc::new(BrokenLock(AtomicBool::new(false)));
+    let inside = Arc::new(AtomicUsize::new(0));
+    let threads: Vec<_> = (0..2).map(|_| {
+        let (lock, inside) = (lock.clone(), inside.clone());
+        thread::spawn(move || {
+            lock.lock();
+            assert_eq!(inside.fetch_add(1, Ordering::SeqCst), 0, "two threads inside");
+            inside.fetch_sub(1, Ordering::SeqCst);
+            lock.unlock();
+        })
+    }).collect();
+    for t in threads {
+        t.join();
+    }
+}
+
+#[test]
+fn broken_lock_is_found_and_replayed() {
+    let failure = Builder::new().explore(broken_lock).unwrap_err();
+    assert_eq!(failure.kind, FailureKind::Panic);
+    assert!(failure.message.contains("two threads inside"));
+
+    let replay = Builder::new().schedule(&failure.schedule).explore(broken_lock).unwrap_err();
+    assert_eq!(replay.kind, FailureKind::Panic);
+    assert_eq!(replay.schedule, failure.schedule);
+}
+
+#[test]
+fn broken_lock_is_found_at_random() {
+    let failure = Builder::new().random(1, 1000).explore(broken_lock).unwrap_err();
+    assert_eq!(failure.kind, FailureKind::Panic);
+}
+
+#[test]
+fn lock_order_inversion_deadlocks() {
+    let failure = Builder::new().explore(|| {
+        let a = Arc::new(spin::Mutex::new(()));
+        let b = Arc::new(spin::Mutex::new(()));
+        let t = {
+            let (a, b) = (a.clone(), b.clone());
+            thread::spawn(move || {
+                let _b = b.lock();
+                let _a = a.lock();
+            })
+        };
+        {
+            let _a = a.lock();
+            let _b = b.lock();
+        }
+        t.join();
+    }).unwrap_err();
+    assert_eq!(failure.kind, FailureKind::Deadlock);
+}
+
+#[test]
+fn lost_wakeup_deadlocks() {
+    let failure = Builder::new().explore(|| {
+        let ready = Arc::new(AtomicBool::new(false));
+        let t = {
+            let ready = ready.clone();
+            thread::spawn(move || {
+                // Nobody ever sets it
+                while !ready.load(Ordering::Acquire) {
+                    spin_loop_hint();
+                }
+            })
+        };
+        t.join();
+    }).unwrap_err();
+    assert_eq!(failure.kind, FailureKind::Deadlock);
+    assert!(failure.message.contains("thread 1 spinning"));
+}
@@ -0,0 +1,468 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Attributes for the Cortex-M runtime of particle
+//!
+//! The vector table and the reset handler refer to handlers by symbol name
+//! (see linkers/link.x.in). These attributes check the signature of a handler
+//! and give it the symbol its slot expects:
+//!
+//! - `#[entry]` on `fn() -> !`, what the reset handler runs once RAM is set up
+//! - `#[pre_init]` on `unsafe fn()`, run before RAM is set up
+//! - `#[exception]` on `fn NAME()`, with NAME one of the Cortex-M
+//!   exceptions. `HardFault` is `fn(&ExceptionFrame) -> !` instead.
+//! - `#[interrupt]` on `fn NAME()`, with NAME an interrupt of the device
+//!   (see the `device` feature)
+//!
+//! Exception and interrupt handlers are renamed, so software can't call them.
+
+#![deny(warnings)]
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{parse2, FnArg, Ident, ItemFn, ReturnType, Type};
+
+/// The exceptions `#[exception]` accepts and the symbol of each. PendSV is
+/// not among them, the kernel switches contexts in it.
+const EXCEPTIONS: &[(&str, &str)] = &[
+    ("NMI", "nmi"),
+    ("HardFault", "hard_fault"),
+    ("MemoryManagement", "mem_manage"),
+    ("BusFault", "bus_fault"),
+    ("UsageFault", "usage_fault"),
+    ("SecureFault", "secure_fault"),
+    ("SVCall", "svc"),
+    ("DebugMonitor", "debug_monitor"),
+    ("SysTick", "systick"),
+];
+
+/// Marks the entry point of the kernel, `fn() -> !`
+#[proc_macro_attribute]
+pub fn entry(args: TokenStream, input: TokenStream) -> TokenStream {
+    expand(entry2(args.into(), input.into()))
+}
+
+/// Marks the function run before RAM is initialized, `unsafe fn()`
+///
+/// It must not touch `static`s, their initial values are not in place yet.
+#[proc_macro_attribute]
+pub fn pre_init(args: TokenStream, input: TokenStream) -> TokenStream {
+    expand(pre_init2(args.into(), input.into()))
+}
+
+/// Binds a Cortex-M exception handler by name
+#[proc_macro_attribute]
+pub fn exception(args: TokenStream, input: TokenStream) -> TokenStream {
+    expand(exception2(args.into(), input.into()))
+}
+
+/// Binds a device interrupt handler by name
+#[proc_macro_attribute]
+pub fn interrupt(args: TokenStream, input: TokenStream) -> TokenStream {
+    expand(interrupt2(args.into(), input.into()))
+}
+
+type Result<T> = std::result::Result<T, syn::Error>;
+
+fn expand(result: Result<TokenStream2>) -> TokenStream {
+    match result {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn error<T>(span: Span, message: &str) -> Result<T> {
+    Err(syn::Error::new(span, message))
+}
+
+/// Parses the function under an attribute without arguments
+fn parse(args: TokenStream2, input: TokenStream2) -> Result<ItemFn> {
+    if !args.is_empty() {
+        return error(args.span(), "this attribute takes no arguments");
+    }
+    let f: ItemFn = parse2(input)?;
+
+    let sig = &f.sig;
+    if sig.constness.is_some()
+        || sig.asyncness.is_some()
+        || sig.abi.is_some()
+        || sig.variadic.is_some()
+        || !sig.generics.params.is_empty()
+        || sig.generics.where_clause.is_some()
+    {
+        return error(
+            sig.span(),
+            "a handler can't be `const`, `async`, `extern`, variadic or generic",
+        );
+    }
+    Ok(f)
+}
+
+fn returns_never(output: &ReturnType) -> bool {
+    match output {
+        ReturnType::Type(_, ty) => match **ty {
+            Type::Never(_) => true,
+            _ => false,
+        },
+        ReturnType::Default => false,
+    }
+}
+
+fn returns_unit_or_never(output: &ReturnType) -> bool {
+    match output {
+        ReturnType::Default => true,
+        ReturnType::Type(_, ty) => match **ty {
+            Type::Tuple(ref tuple) => tuple.elems.is_empty(),
+            Type::Never(_) => true,
+            _ => false,
+        },
+    }
+}
+
+/// `[unsafe] fn()` or `[unsafe] fn() -> !`, checked for `what`
+fn check_handler(f: &ItemFn, what: &str) -> Result<()> {
+    if !f.sig.inputs.is_empty() || !returns_unit_or_never(&f.sig.output) {
+        return error(
+            f.sig.span(),
+            &format!("{} must have signature `[unsafe] fn() [-> !]`", what),
+        );
+    }
+    Ok(())
+}
+
+fn entry2(args: TokenStream2, input: TokenStream2) -> Result<TokenStream2> {
+    let f = parse(args, input)?;
+    if f.sig.unsafety.is_some() || !f.sig.inputs.is_empty() || !returns_never(&f.sig.output) {
+        return error(
+            f.sig.span(),
+            "`#[entry]` function must have signature `fn() -> !`",
+        );
+    }
+
+    let (attrs, vis, ident, block) = (&f.attrs, &f.vis, &f.sig.ident, &f.block);
+    Ok(quote! {
+        #[export_name = "main"]
+        #(#attrs)*
+        #vis fn #ident() -> ! #block
+    })
+}
+
+fn pre_init2(args: TokenStream2, input: TokenStream2) -> Result<TokenStream2> {
+    let f = parse(args, input)?;
+    let unit = match f.sig.output {
+        ReturnType::Default => true,
+        _ => false,
+    };
+    if f.sig.unsafety.is_none() || !f.sig.inputs.is_empty() || !unit {
+        return error(
+            f.sig.span(),
+            "`#[pre_init]` function must have signature `unsafe fn()`",
+        );
+    }
+
+    let (attrs, ident, block) = (&f.attrs, &f.sig.ident, &f.block);
+    Ok(quote! {
+        #[export_name = "__pre_init"]
+        #(#attrs)*
+        pub unsafe fn #ident() #block
+    })
+}
+
+fn exception2(args: TokenStream2, input: TokenStream2) -> Result<TokenStream2> {
+    let f = parse(args, input)?;
+    let name = f.sig.ident.to_string();
+    let symbol = match EXCEPTIONS.iter().find(|&&(n, _)| n == name) {
+        Some(&(_, symbol)) => symbol,
+        None => {
+            let names: Vec<&str> = EXCEPTIONS.iter().map(|&(n, _)| n).collect();
+            return error(
+                f.sig.ident.span(),
+                &format!(
+                    "`{}` is not an exception, expected one of {}",
+                    name,
+                    names.join(", ")
+                ),
+            );
+        }
+    };
+
+    let (attrs, unsafety, block) = (&f.attrs, &f.sig.unsafety, &f.block);
+    let hidden = Ident::new(&format!("__particle_{}", name), f.sig.ident.span());
+
+    if name == "HardFault" {
+        let arg =
+            match (f.sig.inputs.len(), f.sig.inputs.first()) {
+                (1, Some(FnArg::Typed(arg))) if is_exception_frame_ref(&arg.ty) => arg,
+                _ => return error(
+                    f.sig.span(),
+                    "`HardFault` handler must have signature `[unsafe] fn(&ExceptionFrame) -> !`",
+                ),
+            };
+        if !returns_never(&f.sig.output) {
+            return error(
+                f.sig.span(),
+                "`HardFault` handler must have signature `[unsafe] fn(&ExceptionFrame) -> !`",
+            );
+        }
+
+        // The hard fault trampoline passes the frame of the stack in use
+        return Ok(quote! {
+            #[export_name = #symbol]
+            #[allow(non_snake_case)]
+            #(#attrs)*
+            pub #unsafety extern "C" fn #hidden(#arg) -> ! #block
+
+            const _: unsafe extern "C" fn(&crate::arch::ExceptionFrame) -> ! = #hidden;
+        });
+    }
+
+    check_handler(&f, &format!("`{}` handler", name))?;
+    let output = &f.sig.output;
+    Ok(quote! {
+        #[export_name = #symbol]
+        #[allow(non_snake_case)]
+        #(#attrs)*
+        pub #unsafety extern "C" fn #hidden() #output #block
+    })
+}
+
+fn interrupt2(args: TokenStream2, input: TokenStream2) -> Result<TokenStream2> {
+    let f = parse(args, input)?;
+    check_handler(&f, "interrupt handler")?;
+
+    let (attrs, unsafety, ident, output, block) = (
+        &f.attrs,
+        &f.sig.unsafety,
+        &f.sig.ident,
+        &f.sig.output,
+        &f.block,
+    );
+    let symbol = ident.to_string();
+    let hidden = Ident::new(&format!("__particle_{}", symbol), ident.span());
+
+    // `crate::arch::Interrupt` only has the interrupts of device.ld, so a
+    // misspelled name doesn't compile
+    Ok(quote! {
+        #[export_name = #symbol]
+        #[allow(non_snake_case)]
+        #(#attrs)*
+        pub #unsafety extern "C" fn #hidden() #output #block
+
+        const _: crate::arch::Interrupt = crate::arch::Interrupt::#ident;
+    })
+}
+
+/// `&ExceptionFrame` or `&path::to::ExceptionFrame`
+fn is_exception_frame_ref(ty: &Type) -> bool {
+    match ty {
+        Type::Reference(r) if r.mutability.is_none() => match *r.elem {
+            Type::Path(ref p) if p.qself.is_none() => p.path.segments.last().map_or(false, |s| {
+                s.ident == "ExceptionFrame" && s.arguments.is_empty()
+            }),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ok(result: Result<TokenStream2>) -> String {
+        result.unwrap().to_string()
+    }
+
+    fn err(result: Result<TokenStream2>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn entry_exports_main() {
+        let out = ok(entry2(
+            quote!(),
+            quote!(
+                pub fn kmain() -> ! {
+                    loop {}
+                }
+            ),
+        ));
+        assert!(out.contains("export_name = \"main\""));
+        assert!(out.contains("fn kmain"));
+
+        let e = err(entry2(
+            quote!(),
+            quote!(
+                fn kmain() {}
+            ),
+        ));
+        assert!(e.contains("fn() -> !"));
+        assert!(err(entry2(
+            quote!(x),
+            quote!(
+                fn kmain() -> ! {
+                    loop {}
+                }
+            )
+        ))
+        .contains("no arguments"));
+    }
+
+    #[test]
+    fn pre_init_must_be_unsafe() {
+        let out = ok(pre_init2(
+            quote!(),
+            quote!(
+                unsafe fn early() {}
+            ),
+        ));
+        assert!(out.contains("export_name = \"__pre_init\""));
+        assert!(err(pre_init2(
+            quote!(),
+            quote!(
+                fn early() {}
+            )
+        ))
+        .contains("unsafe fn()"));
+    }
+
+    #[test]
+    fn exceptions_get_their_vector_symbol() {
+        let out = ok(exception2(
+            quote!(),
+            quote!(
+                fn SysTick() {
+                    tick();
+                }
+            ),
+        ));
+        assert!(out.contains("export_name = \"systick\""));
+        assert!(out.contains("__particle_SysTick"));
+
+        let out = ok(exception2(
+            quote!(),
+            quote!(
+                unsafe fn SVCall() -> ! {
+                    loop {}
+                }
+            ),
+        ));
+        assert!(out.contains("export_name = \"svc\""));
+    }
+
+    #[test]
+    fn pendsv_belongs_to_the_kernel() {
+        let e = err(exception2(
+            quote!(),
+            quote!(
+                fn PendSV() {}
+            ),
+        ));
+        assert!(e.contains("`PendSV` is not an exception"));
+    }
+
+    #[test]
+    fn misspelled_exceptions_are_rejected() {
+        let e = err(exception2(
+            quote!(),
+            quote!(
+                fn Systick() {}
+            ),
+        ));
+        assert!(e.contains("`Systick` is not an exception"));
+        assert!(e.contains("SysTick"));
+    }
+
+    #[test]
+    fn exception_signatures_are_checked() {
+        assert!(err(exception2(
+            quote!(),
+            quote!(
+                fn SysTick(x: u32) {}
+            )
+        ))
+        .contains("signature"));
+        assert!(err(exception2(
+            quote!(),
+            quote!(
+                fn SysTick() -> u32 {
+                    0
+                }
+            )
+        ))
+        .contains("signature"));
+        assert!(err(exception2(
+            quote!(),
+            quote!(
+                fn SysTick<T>() {}
+            )
+        ))
+        .contains("generic"));
+    }
+
+    #[test]
+    fn hard_fault_takes_the_frame() {
+        let out = ok(exception2(
+            quote!(),
+            quote!(
+                fn HardFault(ef: &ExceptionFrame) -> ! {
+                    loop {}
+                }
+            ),
+        ));
+        assert!(out.contains("export_name = \"hard_fault\""));
+        assert!(out.contains("crate :: arch :: ExceptionFrame"));
+
+        assert!(err(exception2(
+            quote!(),
+            quote!(
+                fn HardFault() -> ! {
+                    loop {}
+                }
+            )
+        ))
+        .contains("ExceptionFrame"));
+        assert!(err(exception2(
+            quote!(),
+            quote!(
+                fn HardFault(ef: &mut ExceptionFrame) -> ! {
+                    loop {}
+                }
+            )
+        ))
+        .contains("ExceptionFrame"));
+        assert!(err(exception2(
+            quote!(),
+            quote!(
+                fn HardFault(ef: &ExceptionFrame) {}
+            )
+        ))
+        .contains("-> !"));
+    }
+
+    #[test]
+    fn interrupts_are_checked_against_the_device() {
+        let out = ok(interrupt2(
+            quote!(),
+            quote!(
+                fn UART0() {}
+            ),
+        ));
+        assert!(out.contains("export_name = \"UART0\""));
+        assert!(out.contains("crate :: arch :: Interrupt :: UART0"));
+        assert!(err(interrupt2(
+            quote!(),
+            quote!(
+                fn UART0(x: u8) {}
+            )
+        ))
+        .contains("signature"));
+    }
+}
@@ -0,0 +1,48 @@
+/* The non-secure half of the MPS2 AN505, see src/arch/arm/cortex_m/tz.rs
+   in particle */
+MEMORY
+{
+  FLASH : ORIGIN = 0x00200000, LENGTH = 2M
+  RAM : ORIGIN = 0x28100000, LENGTH = 1M
+}
+
+ENTRY(reset);
+EXTERN(__RESET_VECTOR);
+
+SECTIONS
+{
+    .vector_table ORIGIN(FLASH) :
+    {
+        /* Initial Stack Pointer (SP) value */
+        LONG(ORIGIN(RAM) + LENGTH(RAM));
+
+        /* Reset vector */
+        KEEP(*(.vector_table.reset_vector));
+    } > FLASH
+
+    .text :
+    {
+        *(.text .text.*);
+    } > FLASH
+
+    .rodata : ALIGN(4)
+    {
+        *(.rodata .rodata.*);
+    } > FLASH
+
+    /* This image never initializes RAM, it must not have any data */
+    .bss (NOLOAD) : ALIGN(4)
+    {
+        *(.bss .bss.*);
+    } > RAM
+
+    /DISCARD/ :
+    {
+        *(.ARM.exidx);
+        *(.ARM.exidx.*);
+        *(.ARM.extab.*);
+    }
+}
+
+ASSERT(SIZEOF(.bss) == 0, "
+ERROR(particle-tz-nonsecure): the test image has no RAM initialization");
@@ -0,0 +1,72 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Non-secure test image
+//!
+//! particle built with the `trustzone` feature boots this image in the
+//! non-secure state. It calls every secure gateway and exits QEMU with the
+//! result, see scripts/tz-test.sh.
+
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use core::slice;
+
+use cortex_m_semihosting::{debug, hprintln};
+use particle_tz::{ns, API_VERSION, EFAULT};
+
+#[doc(hidden)]
+#[link_section = ".vector_table.reset_vector"]
+#[no_mangle]
+pub static __RESET_VECTOR: extern "C" fn() -> ! = reset;
+
+#[no_mangle]
+extern "C" fn reset() -> ! {
+    let status = match run() {
+        Ok(()) => {
+            hprintln!("ns: ok").unwrap();
+            debug::EXIT_SUCCESS
+        }
+        Err(msg) => {
+            hprintln!("ns: FAILED: {}", msg).unwrap();
+            debug::EXIT_FAILURE
+        }
+    };
+
+    debug::exit(status);
+    loop {}
+}
+
+fn run() -> Result<(), &'static str> {
+    if ns::version() != API_VERSION {
+        return Err("unexpected gateway version");
+    }
+
+    // The secure tick keeps running while the non-secure side does
+    let start = ns::ticks();
+    let mut spins = 0u32;
+    while ns::ticks() == start {
+        spins += 1;
+        if spins == 10_000_000 {
+            return Err("secure clock does not advance");
+        }
+    }
+
+    let hello = b"ns: hello from the non-secure world\n";
+    if ns::console_write(hello) != hello.len() as isize {
+        return Err("console write failed");
+    }
+
+    // The secure side must refuse to read secure memory on our behalf
+    let secret = unsafe { slice::from_raw_parts(0x3800_0000 as *const u8, 16) };
+    if ns::console_write(secret) != EFAULT {
+        return Err("secure memory leaked through the console gateway");
+    }
+
+    Ok(())
+}
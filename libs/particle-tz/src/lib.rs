@@ -0,0 +1,94 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! The secure gateway API of particle on TrustZone-M
+//!
+//! When particle is built with the `trustzone` feature it runs as the
+//! secure image and exports its services through secure gateway veneers in
+//! the non-secure callable `.gnu.sgstubs` section. The veneers sit at fixed
+//! addresses, one `Gateway` slot each, so that non-secure images only need
+//! this crate to call them. The secure side uses it to check that it put
+//! the veneers where this crate says they are.
+
+#![deny(missing_docs)]
+#![deny(warnings)]
+#![no_std]
+
+use core::mem;
+
+/// Address of the first veneer
+///
+/// Must match `_sgstubs` in particle's linkers/memory-mps2-an505-s.x.in
+pub const SGSTUBS_BASE: usize = 0x101f_f000;
+
+/// Size of one veneer, an `SG` followed by a `B.W` to the secure function
+pub const VENEER_SIZE: usize = 8;
+
+/// The services of the secure side, in veneer order
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(usize)]
+pub enum Gateway {
+    /// `fn() -> u32`, the gateway API version
+    Version = 0,
+    /// `fn() -> u64`, ticks of the secure kernel clock
+    Ticks = 1,
+    /// `fn(*const u8, usize) -> isize`, write to the secure console
+    ConsoleWrite = 2,
+}
+
+/// Number of `Gateway` slots
+pub const GATEWAY_COUNT: usize = 3;
+
+/// Version returned by `Gateway::Version`
+pub const API_VERSION: u32 = 1;
+
+/// Error returned by `Gateway::ConsoleWrite` when the buffer isn't
+/// readable by the non-secure caller
+pub const EFAULT: isize = -14;
+
+impl Gateway {
+    /// Address of the veneer of this service
+    pub fn veneer(self) -> usize {
+        SGSTUBS_BASE + self as usize * VENEER_SIZE
+    }
+
+    /// The veneer as a Thumb function pointer
+    unsafe fn entry<F: Copy>(self) -> F {
+        debug_assert_eq!(mem::size_of::<F>(), mem::size_of::<usize>());
+        let addr = self.veneer() | 1;
+        mem::transmute_copy(&addr)
+    }
+}
+
+/// Calls from the non-secure side
+pub mod ns {
+    use super::Gateway;
+
+    /// The gateway API version of the secure side
+    pub fn version() -> u32 {
+        unsafe {
+            let f: extern "C" fn() -> u32 = Gateway::Version.entry();
+            f()
+        }
+    }
+
+    /// Ticks of the secure kernel clock
+    pub fn ticks() -> u64 {
+        unsafe {
+            let f: extern "C" fn() -> u64 = Gateway::Ticks.entry();
+            f()
+        }
+    }
+
+    /// Write `buf` to the secure console, returns the number of bytes
+    /// written or a negative error
+    pub fn console_write(buf: &[u8]) -> isize {
+        unsafe {
+            let f: extern "C" fn(*const u8, usize) -> isize = Gateway::ConsoleWrite.entry();
+            f(buf.as_ptr(), buf.len())
+        }
+    }
+}
@@ -0,0 +1,28 @@
+#!/bin/sh
+#
+# Copyright 2019 The Particle Authors
+#
+# Use of this source code is governed by a MIT-style
+# license that can be found in the LICENSE file or at
+# https://opensource.org/licenses/MIT
+#
+# Run the kernel as a Linux process under `cargo test`, once per feature set.
+# The kernel has global state, so the tests run one at a time. Then explore
+# the schedules of the spin locks (see external/libs/spin/src/model).
+#
+# Usage: scripts/host-test.sh [cargo args...]
+#
+# With AddressSanitizer (nightly):
+#   RUSTFLAGS="-Z sanitizer=address" scripts/host-test.sh
+
+set -e
+
+host=$(rustc -vV | sed -n 's/^host: //p')
+
+for features in "" "tickless"; do
+    echo "==> host test features: ${features:-default}"
+    cargo test --target "$host" --features "$features" "$@" -- --test-threads=1
+done
+
+echo "==> spin schedule exploration"
+cargo test --manifest-path external/libs/spin/Cargo.toml --target "$host" --features model "$@"
@@ -0,0 +1,18 @@
+#!/bin/sh
+#
+# Copyright 2019 The Particle Authors
+#
+# Use of this source code is governed by a MIT-style
+# license that can be found in the LICENSE file or at
+# https://opensource.org/licenses/MIT
+#
+# Run the in-kernel test suite on QEMU, once per feature set.
+#
+# Usage: scripts/ktest.sh [cargo args...]
+
+set -e
+
+for features in "" "tickless"; do
+    echo "==> ktest features: ${features:-default}"
+    cargo run --features "ktest $features" "$@"
+done
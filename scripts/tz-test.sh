@@ -0,0 +1,23 @@
+#!/bin/sh
+#
+# Copyright 2019 The Particle Authors
+#
+# Use of this source code is governed by a MIT-style
+# license that can be found in the LICENSE file or at
+# https://opensource.org/licenses/MIT
+#
+# Build particle as the secure image and libs/particle-tz/nonsecure as the
+# non-secure image, and run both on the QEMU MPS2 AN505.
+
+set -e
+
+TARGET=thumbv8m.main-none-eabi
+ROOT=$(cd "$(dirname "$0")/.." && pwd)
+
+cargo build --target $TARGET --features trustzone --manifest-path "$ROOT/Cargo.toml"
+(cd "$ROOT/libs/particle-tz/nonsecure" && cargo build)
+
+qemu-system-arm -cpu cortex-m33 -machine mps2-an505 -nographic \
+    -semihosting-config enable=on,target=native \
+    -kernel "$ROOT/target/$TARGET/debug/particle" \
+    -device loader,file="$ROOT/libs/particle-tz/nonsecure/target/$TARGET/debug/particle-tz-nonsecure"
@@ -4,32 +4,33 @@
 // license that can be found in the LICENSE file or at
 // https://opensource.org/licenses/MIT
 
-use core::alloc::{AllocErr, GlobalAlloc, Layout};
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
 use miniheap::Heap;
-use spin::Mutex;
+
+use crate::kernel::irq::IrqLock;
 
 pub struct Allocator;
 
-static HEAP: Mutex<Option<Heap>> = Mutex::new(None);
+/// Threads allocate too, a spinning lock would deadlock against a thread
+/// preempted while holding it
+static HEAP: IrqLock<Heap> = IrqLock::new(Heap::empty());
 
 impl Allocator {
     pub unsafe fn init(heap_base: usize, heap_size: usize) {
-        *HEAP.lock() = Some(Heap::new(heap_base, heap_size));
+        HEAP.lock().init(heap_base, heap_size);
     }
 }
 
 unsafe impl GlobalAlloc for Allocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        loop {
-            let res = if let Some(ref mut heap) = *HEAP.lock() {
-                heap.allocate_first_fit(layout)
-            } else {
-                panic!("minheap_allocator: miniheap not initialized");
-            };
-            None
+        match HEAP.lock().allocate_first_fit(layout) {
+            Ok(ptr) => ptr.as_ptr(),
+            Err(_) => ptr::null_mut(),
         }
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        HEAP.lock().deallocate(NonNull::new_unchecked(ptr), layout);
     }
 }
@@ -0,0 +1,25 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+use cortex_m_semihosting::{debug, hprint};
+
+/// Write to the semihosting console of the debugger (or QEMU)
+pub fn console_write_str(s: &str) {
+    let _ = hprint!("{}", s);
+}
+
+/// Leave QEMU with an exit status telling whether the run succeeded
+///
+/// NOTE do not run this on hardware; it can corrupt OpenOCD state
+pub fn arch_exit(success: bool) -> ! {
+    debug::exit(if success {
+        debug::EXIT_SUCCESS
+    } else {
+        debug::EXIT_FAILURE
+    });
+
+    loop {}
+}
@@ -0,0 +1,86 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Hard fault report
+//!
+//! MemManage, BusFault and UsageFault are not enabled, so every fault ends
+//! up here. A thread that ran into its stack guard (see mpu.rs) or its stack
+//! limit (ARMv8-M) is reported as a stack overflow.
+
+#![deny(warnings)]
+
+use core::fmt::{self, Write};
+#[cfg(not(armv6m))]
+use core::ptr;
+
+use particle_macros::exception;
+
+use super::console::{arch_exit, console_write_str};
+use super::start::ExceptionFrame;
+use crate::thread;
+
+#[cfg(not(armv6m))]
+const CFSR: *const u32 = 0xe000_ed28 as *const u32;
+#[cfg(not(armv6m))]
+const MMFAR: *const u32 = 0xe000_ed34 as *const u32;
+
+/// MemManage fault while stacking for an exception entry
+#[cfg(not(armv6m))]
+const CFSR_MSTKERR: u32 = 1 << 4;
+/// MMFAR holds the faulting address
+#[cfg(not(armv6m))]
+const CFSR_MMARVALID: u32 = 1 << 7;
+/// The stack pointer went below its limit register
+#[cfg(armv8m)]
+const CFSR_STKOF: u32 = 1 << 20;
+
+/// Writes around the console lock, which the faulting code may hold
+struct FaultConsole;
+
+impl Write for FaultConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        console_write_str(s);
+        Ok(())
+    }
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    let _ = writeln!(
+        FaultConsole,
+        "\nhard fault: pc={:#010x} lr={:#010x} xpsr={:#010x}\n\
+         r0={:#010x} r1={:#010x} r2={:#010x} r3={:#010x} r12={:#010x}",
+        ef.pc, ef.lr, ef.xpsr, ef.r0, ef.r1, ef.r2, ef.r3, ef.r12
+    );
+
+    let (addr, overflow) = fault_cause();
+    let _ = thread::fault_report(&mut FaultConsole, addr, overflow);
+
+    arch_exit(false)
+}
+
+/// The faulting data address if known, and whether the stack overflowed
+#[cfg(not(armv6m))]
+fn fault_cause() -> (Option<usize>, bool) {
+    let cfsr = unsafe { ptr::read_volatile(CFSR) };
+    let addr = if cfsr & CFSR_MMARVALID != 0 {
+        Some(unsafe { ptr::read_volatile(MMFAR) } as usize)
+    } else {
+        None
+    };
+
+    #[cfg(armv8m)]
+    let overflow = cfsr & (CFSR_MSTKERR | CFSR_STKOF) != 0;
+    #[cfg(not(armv8m))]
+    let overflow = cfsr & CFSR_MSTKERR != 0;
+    (addr, overflow)
+}
+
+/// ARMv6-M has no fault status registers
+#[cfg(armv6m)]
+fn fault_cause() -> (Option<usize>, bool) {
+    (None, false)
+}
@@ -0,0 +1,51 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+use cortex_m::interrupt;
+use cortex_m::register::primask;
+
+/// Disable interrupts and return the previous interrupt state
+///
+/// The returned value must be handed back to `irq_restore()`.
+#[inline]
+pub fn irq_save() -> usize {
+    let state = primask::read().is_active() as usize;
+    interrupt::disable();
+    state
+}
+
+/// Restore the interrupt state returned by `irq_save()`
+#[inline]
+pub fn irq_restore(state: usize) {
+    if state != 0 {
+        unsafe { interrupt::enable() }
+    }
+}
+
+/// Wait for an interrupt
+///
+/// The core wakes up on a pending interrupt even when interrupts are
+/// masked by PRIMASK, so this may be called inside `irq_save()`.
+#[inline]
+pub fn arch_idle() {
+    cortex_m::asm::wfi();
+}
+
+/// Critical section used by the `spin` locks on targets without
+/// compare-and-swap, see external/libs/spin/src/cas.rs
+#[cfg(armv6m)]
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn spin_critical_section_enter() -> usize {
+    irq_save()
+}
+
+#[cfg(armv6m)]
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn spin_critical_section_exit(state: usize) {
+    irq_restore(state)
+}
@@ -6,8 +6,38 @@
 
 pub mod start;
 
+pub mod mpu;
+
+#[cfg(all(armv8m, feature = "trustzone"))]
+pub mod tz;
+
+mod console;
+mod fault;
+mod irq;
+mod switch;
+mod systick;
+
+pub use self::console::{arch_exit, console_write_str};
+pub use self::irq::{arch_idle, irq_restore, irq_save};
+pub use self::mpu::{arch_stack_guard, STACK_GUARD_SIZE};
+pub use self::start::ExceptionFrame;
+pub use self::switch::{arch_context_switch, arch_init_context, arch_request_preempt};
+
+#[cfg(feature = "device")]
+pub use self::start::{Interrupt, INTERRUPT_COUNT};
+
+#[cfg(feature = "tickless")]
+pub use self::systick::systick_sleep as timer_sleep;
+
 pub const PAGE_SIZE: u32 = 4096;
 pub const PAGE_SIZE_SHIFT: u32 = 12;
 
 pub fn arch_early_init() {
+    switch::switch_init();
+    mpu::stack_guard_init();
+}
+
+/// Start the periodic kernel tick
+pub fn timer_init(tick_hz: u32) {
+    systick::systick_init(tick_hz);
 }
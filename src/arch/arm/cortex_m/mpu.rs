@@ -0,0 +1,229 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Memory Protection Unit
+//!
+//! ARMv7-M describes a region by its base and a power of two size (RBAR and
+//! RASR), ARMv8-M by its first and last 32 byte granule (RBAR and RLAR) with
+//! the memory type taken from MAIR. Both are driven through the same
+//! `mpu_set_region()` call, the checks for what each can express live here.
+
+#![deny(warnings)]
+
+use core::ptr;
+
+const MPU_TYPE: *mut u32 = 0xe000_ed90 as *mut u32;
+const MPU_CTRL: *mut u32 = 0xe000_ed94 as *mut u32;
+const MPU_RNR: *mut u32 = 0xe000_ed98 as *mut u32;
+const MPU_RBAR: *mut u32 = 0xe000_ed9c as *mut u32;
+#[cfg(not(armv8m))]
+const MPU_RASR: *mut u32 = 0xe000_eda0 as *mut u32;
+#[cfg(armv8m)]
+const MPU_RLAR: *mut u32 = 0xe000_eda0 as *mut u32;
+#[cfg(armv8m)]
+const MPU_MAIR0: *mut u32 = 0xe000_edc0 as *mut u32;
+
+const MPU_CTRL_ENABLE: u32 = 1 << 0;
+const MPU_CTRL_PRIVDEFENA: u32 = 1 << 2;
+
+/// Who may access a region and how
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MpuAccess {
+    /// Any access faults, used for stack guards
+    NoAccess,
+    /// Privileged read/write, unprivileged no access
+    PrivReadWrite,
+    /// Read/write for everyone
+    ReadWrite,
+    /// Read only for everyone
+    ReadOnly,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MpuError {
+    /// The region index is beyond what the MPU implements
+    BadIndex,
+    /// Base or size can't be expressed by this MPU
+    BadGeometry,
+    /// The access permissions can't be expressed by this MPU
+    Unsupported,
+}
+
+/// Number of regions this MPU implements, 0 without an MPU
+pub fn mpu_regions() -> u32 {
+    unsafe { (ptr::read_volatile(MPU_TYPE) >> 8) & 0xff }
+}
+
+/// Turn the MPU on. Privileged code keeps the default memory map for
+/// addresses no region covers.
+pub fn mpu_enable() {
+    unsafe {
+        ptr::write_volatile(MPU_CTRL, MPU_CTRL_PRIVDEFENA | MPU_CTRL_ENABLE);
+    }
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+}
+
+pub fn mpu_disable() {
+    cortex_m::asm::dmb();
+    unsafe {
+        ptr::write_volatile(MPU_CTRL, 0);
+    }
+}
+
+/// Disable region `index`
+pub fn mpu_clear_region(index: u32) -> Result<(), MpuError> {
+    if index >= mpu_regions() {
+        return Err(MpuError::BadIndex);
+    }
+
+    unsafe {
+        ptr::write_volatile(MPU_RNR, index);
+        #[cfg(not(armv8m))]
+        ptr::write_volatile(MPU_RASR, 0);
+        #[cfg(armv8m)]
+        ptr::write_volatile(MPU_RLAR, 0);
+    }
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+    Ok(())
+}
+
+/// Program region `index` to cover `[base, base + size)`
+///
+/// On ARMv7-M `size` must be a power of two of at least 32 bytes and `base`
+/// aligned to it. On ARMv8-M both must be multiples of 32 bytes.
+pub fn mpu_set_region(
+    index: u32,
+    base: usize,
+    size: usize,
+    access: MpuAccess,
+    execute: bool,
+) -> Result<(), MpuError> {
+    if index >= mpu_regions() {
+        return Err(MpuError::BadIndex);
+    }
+
+    let (rbar, attr) = region_encode(base, size, access, execute)?;
+    unsafe {
+        ptr::write_volatile(MPU_RNR, index);
+        ptr::write_volatile(MPU_RBAR, rbar);
+        #[cfg(not(armv8m))]
+        ptr::write_volatile(MPU_RASR, attr);
+        #[cfg(armv8m)]
+        ptr::write_volatile(MPU_RLAR, attr);
+    }
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+    Ok(())
+}
+
+/// Encode RBAR and RASR for ARMv7-M
+#[cfg(not(armv8m))]
+fn region_encode(
+    base: usize,
+    size: usize,
+    access: MpuAccess,
+    execute: bool,
+) -> Result<(u32, u32), MpuError> {
+    if size < 32 || !size.is_power_of_two() || base & (size - 1) != 0 {
+        return Err(MpuError::BadGeometry);
+    }
+
+    let ap = match access {
+        MpuAccess::NoAccess => 0b000,
+        MpuAccess::PrivReadWrite => 0b001,
+        MpuAccess::ReadWrite => 0b011,
+        MpuAccess::ReadOnly => 0b110,
+    };
+    let xn = if execute { 0 } else { 1 };
+    // SIZE encodes 2^(SIZE + 1) bytes
+    let size_field = size.trailing_zeros() - 1;
+    // Normal memory, write-back, write and read allocate (TEX=1, C=1, B=1)
+    let memattr = (0b001 << 19) | (1 << 17) | (1 << 16);
+
+    let rasr = (xn << 28) | (ap << 24) | memattr | (size_field << 1) | 1;
+    Ok((base as u32, rasr))
+}
+
+/// Encode RBAR and RLAR for ARMv8-M
+#[cfg(armv8m)]
+fn region_encode(
+    base: usize,
+    size: usize,
+    access: MpuAccess,
+    execute: bool,
+) -> Result<(u32, u32), MpuError> {
+    if size == 0 || base & 31 != 0 || size & 31 != 0 {
+        return Err(MpuError::BadGeometry);
+    }
+
+    // ARMv8-M can't deny privileged access, stacks are guarded with
+    // PSPLIM/MSPLIM there instead.
+    let ap = match access {
+        MpuAccess::NoAccess => return Err(MpuError::Unsupported),
+        MpuAccess::PrivReadWrite => 0b00,
+        MpuAccess::ReadWrite => 0b01,
+        MpuAccess::ReadOnly => 0b11,
+    };
+    let xn = if execute { 0 } else { 1 };
+
+    // Attribute 0: normal memory, write-back non-transient, read and write
+    // allocate for both inner and outer
+    unsafe {
+        let mair = ptr::read_volatile(MPU_MAIR0);
+        ptr::write_volatile(MPU_MAIR0, (mair & !0xff) | 0xff);
+    }
+
+    let limit = base + size - 1;
+    // Non-shareable, AttrIndx 0
+    let rbar = (base as u32 & !31) | (ap << 1) | xn;
+    let rlar = (limit as u32 & !31) | 1;
+    Ok((rbar, rlar))
+}
+
+/// Bytes at the bottom of every thread stack the guard may cover. A 32 byte
+/// region aligned to its size fits in the first 64 bytes of any stack.
+/// ARMv8-M guards stacks with PSPLIM, see switch.rs.
+#[cfg(not(armv8m))]
+pub const STACK_GUARD_SIZE: usize = 64;
+#[cfg(armv8m)]
+pub const STACK_GUARD_SIZE: usize = 0;
+
+/// The guard takes the last region, which wins where regions overlap
+#[cfg(not(armv8m))]
+fn stack_guard_region() -> Option<u32> {
+    mpu_regions().checked_sub(1)
+}
+
+/// Turn the MPU on for the stack guards, if there is one
+pub fn stack_guard_init() {
+    #[cfg(not(armv8m))]
+    {
+        if stack_guard_region().is_some() {
+            mpu_enable();
+        }
+    }
+}
+
+/// Make the guard of the stack at `base` fault on access, for the thread
+/// about to run. A `base` of 0 removes the guard.
+pub fn arch_stack_guard(base: usize) {
+    #[cfg(not(armv8m))]
+    {
+        let index = match stack_guard_region() {
+            Some(index) => index,
+            None => return,
+        };
+        let _ = if base == 0 {
+            mpu_clear_region(index)
+        } else {
+            mpu_set_region(index, (base + 31) & !31, 32, MpuAccess::NoAccess, false)
+        };
+    }
+    #[cfg(armv8m)]
+    let _ = base;
+}
@@ -11,6 +11,9 @@
 //! (cf. [`#[entry]`]) using the `main` symbol so you may also find that symbol
 //! in your program; if you do, `main` will contain your application code. Some
 //! other times `main` gets inlined into `reset` so you won't find it.
+//!
+//! Handlers are bound with the attributes of libs/particle-macros:
+//! `#[exception]`, `#[interrupt]` (with the `device` feature) and `#[pre_init]`.
 
 #![deny(missing_docs)]
 #![deny(warnings)]
@@ -38,7 +41,11 @@ pub unsafe extern "C" fn reset() -> ! {
     }
 
     extern "Rust" {
+        // The `#[pre_init]` function, `default_pre_init` otherwise
         fn __pre_init();
+
+        // The `#[entry]` function
+        fn main() -> !;
     }
 
     __pre_init();
@@ -47,8 +54,16 @@ pub unsafe extern "C" fn reset() -> ! {
     rrt0::zero_bss(&mut __sbss, &mut __ebss);
     rrt0::init_data(&mut __sdata, &mut __edata, &__sidata);
 
-    #[cfg(not(has_fpu))]
-    crate::kmain();
+    #[cfg(has_fpu)]
+    {
+        // Give CP10 and CP11 (the FPU) full access before any FP code runs
+        const SCB_CPACR: *mut u32 = 0xe000_ed88 as *mut u32;
+        core::ptr::write_volatile(SCB_CPACR, core::ptr::read_volatile(SCB_CPACR) | (0xf << 20));
+        cortex_m::asm::dsb();
+        cortex_m::asm::isb();
+    }
+
+    main()
 }
 
 #[doc(hidden)]
@@ -68,22 +83,24 @@ pub static __EXCEPTIONS: [Vector; 14] = [
     // Exception 2: Non Maskable Interrupt.
     Vector { handler: nmi },
     // Exception 3: Hard Fault Interrupt.
-    Vector { handler: hard_fault },
+    Vector {
+        handler: hard_fault_trampoline,
+    },
     // Exception 4: Memory Management Interrupt
     #[cfg(not(armv6m))]
     Vector { handler: mem_manage },
     #[cfg(armv6m)]
-    Vector { resrved: 0 },
+    Vector { reserved: 0 },
     // Exception 5: Bus Fault Interrupt
     #[cfg(not(armv6m))]
     Vector { handler: bus_fault },
     #[cfg(armv6m)]
-    Vector { resrved: 0 },
+    Vector { reserved: 0 },
     // Exception 6: Usage Fault Interrupt
     #[cfg(not(armv6m))]
     Vector { handler: usage_fault },
     #[cfg(armv6m)]
-    Vector { resrved: 0 },
+    Vector { reserved: 0 },
     // Exception 7: Secure Fault Interrupt [only on Armv8-M].
     #[cfg(armv8m)]
     Vector { handler: secure_fault },
@@ -113,7 +130,7 @@ pub static __EXCEPTIONS: [Vector; 14] = [
 extern "C" {
     fn nmi();
 
-    fn hard_fault();
+    fn hard_fault_trampoline();
 
     #[cfg(not(armv6m))]
     fn mem_manage();
@@ -137,6 +154,54 @@ extern "C" {
     fn systick();
 }
 
+/// Registers the core stacks on exception entry, which a `HardFault` handler
+/// receives
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ExceptionFrame {
+    /// (General purpose) Register 0
+    pub r0: u32,
+    /// (General purpose) Register 1
+    pub r1: u32,
+    /// (General purpose) Register 2
+    pub r2: u32,
+    /// (General purpose) Register 3
+    pub r3: u32,
+    /// (General purpose) Register 12
+    pub r12: u32,
+    /// Linker Register
+    pub lr: u32,
+    /// Program Counter
+    pub pc: u32,
+    /// Program Status Register
+    pub xpsr: u32,
+}
+
+// Hands the frame of the stack the fault happened on (bit 2 of EXC_RETURN) to
+// `hard_fault`, which is `default_handler` unless there is an `#[exception]
+// fn HardFault`
+global_asm!(
+    r#"
+    .syntax unified
+    .section .hard_fault_trampoline, "ax"
+    .global hard_fault_trampoline
+    .type hard_fault_trampoline,%function
+    .thumb_func
+hard_fault_trampoline:
+    mov r0, lr
+    movs r1, #4
+    tst r0, r1
+    bne 0f
+    mrs r0, msp
+    ldr r1, =hard_fault
+    bx r1
+0:
+    mrs r0, psp
+    ldr r1, =hard_fault
+    bx r1
+"#
+);
+
 #[doc(hidden)]
 #[no_mangle]
 pub unsafe extern "C" fn default_handler() -> ! {
@@ -145,13 +210,28 @@ pub unsafe extern "C" fn default_handler() -> ! {
     }
 }
 
+/// Number of external interrupts the NVIC supports
+#[cfg(all(armv6m, not(feature = "device")))]
+const INTERRUPT_COUNT: usize = 32;
+#[cfg(all(armv7m, not(feature = "device")))]
+const INTERRUPT_COUNT: usize = 240;
+#[cfg(all(armv8m, not(feature = "device")))]
+const INTERRUPT_COUNT: usize = 480;
+
+#[cfg(not(feature = "device"))]
 #[doc(hidden)]
 #[link_section = ".vector_table.interrupts"]
 #[no_mangle]
-pub static __INTERRUPTS: [unsafe extern "C" fn(); 240] = [{
+pub static __INTERRUPTS: [unsafe extern "C" fn(); INTERRUPT_COUNT] = [{
     extern "C" {
         fn default_handler();
     }
 
     default_handler
-}; 240];
+}; INTERRUPT_COUNT];
+
+// With the device feature the table has an entry per interrupt of the device,
+// which is bound by name: a `#[no_mangle] extern "C" fn UART0()` replaces the
+// weak alias of device.ld. build.rs generates it from device.ld.
+#[cfg(feature = "device")]
+include!(concat!(env!("OUT_DIR"), "/interrupts.rs"));
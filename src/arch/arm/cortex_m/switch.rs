@@ -0,0 +1,353 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Context switch through PendSV
+//!
+//! Threads run in thread mode on PSP, the kernel entry code and exceptions
+//! on MSP. `arch_context_switch()` only records which context to save and
+//! which one to resume, and pends PendSV. PendSV runs at the lowest
+//! priority, so the switch happens once every other exception returned:
+//! right away when called from a thread with interrupts enabled, on return
+//! from the exception otherwise. Interrupt handlers that made a more urgent
+//! thread ready pend PendSV too, which then lets the scheduler switch.
+//!
+//! PendSV stacks r4-r11 and EXC_RETURN below the frame the core pushed on
+//! PSP, and s16-s31 above them when the outgoing thread has an FP context
+//! (bit 4 of EXC_RETURN clear). The ARMv6-M variant can only store the low
+//! registers, so r8-r11 go through r4-r7.
+//!
+//! On ARMv8-M Mainline the frame also holds PSPLIM, so every thread runs
+//! with the hardware stack limit of its own stack and an overflow raises a
+//! UsageFault (STKOF) instead of corrupting memory below the stack.
+
+#![deny(warnings)]
+
+use core::mem::size_of;
+use core::ptr;
+
+use super::irq::{irq_restore, irq_save};
+use super::start::ExceptionFrame;
+use crate::thread;
+
+/// Stack of the exceptions, and of the code that runs before the threads
+const EXCEPTION_STACK_SIZE: usize = 2048;
+
+static mut EXCEPTION_STACK: [u64; EXCEPTION_STACK_SIZE / 8] = [0; EXCEPTION_STACK_SIZE / 8];
+
+/// Where PendSV saves the outgoing context, null when no switch is pending
+static mut SWITCH_FROM: *mut usize = ptr::null_mut();
+/// The context PendSV resumes
+static mut SWITCH_TO: usize = 0;
+
+const SCB_ICSR: *mut u32 = 0xe000_ed04 as *mut u32;
+const SCB_ICSR_PENDSVSET: u32 = 1 << 28;
+const SCB_ICSR_PENDSVCLR: u32 = 1 << 27;
+/// Priorities of PendSV (bits 16-23) and SysTick (bits 24-31)
+const SCB_SHPR3: *mut u32 = 0xe000_ed20 as *mut u32;
+
+/// Return to thread mode on PSP, without FP context
+const EXC_RETURN_THREAD_PSP: usize = 0xffff_fffd;
+
+/// The registers PendSV saves below the exception frame, lowest address
+/// first. s16-s31 follow when bit 4 of `exc_return` is clear.
+#[repr(C)]
+struct SwitchFrame {
+    #[cfg(armv8m)]
+    psplim: usize,
+    r4: usize,
+    r5: usize,
+    r6: usize,
+    r7: usize,
+    r8: usize,
+    r9: usize,
+    r10: usize,
+    r11: usize,
+    exc_return: usize,
+}
+
+#[cfg(all(armv7m, not(has_fpu)))]
+global_asm!(
+    r#"
+    .syntax unified
+    .section .text.pendsv
+    .global pendsv
+    .type pendsv,%function
+    .thumb_func
+pendsv:
+    cpsid i
+    mrs r0, psp
+    stmdb r0!, {r4-r11, lr}
+    bl pendsv_switch
+    ldmia r0!, {r4-r11, lr}
+    msr psp, r0
+    cpsie i
+    bx lr
+"#
+);
+
+#[cfg(all(armv7m, has_fpu))]
+global_asm!(
+    r#"
+    .syntax unified
+    .fpu fpv4-sp-d16
+    .section .text.pendsv
+    .global pendsv
+    .type pendsv,%function
+    .thumb_func
+pendsv:
+    cpsid i
+    mrs r0, psp
+    tst lr, #0x10
+    it eq
+    vstmdbeq r0!, {s16-s31}
+    stmdb r0!, {r4-r11, lr}
+    bl pendsv_switch
+    ldmia r0!, {r4-r11, lr}
+    tst lr, #0x10
+    it eq
+    vldmiaeq r0!, {s16-s31}
+    msr psp, r0
+    cpsie i
+    bx lr
+"#
+);
+
+#[cfg(all(armv8m, not(has_fpu)))]
+global_asm!(
+    r#"
+    .syntax unified
+    .section .text.pendsv
+    .global pendsv
+    .type pendsv,%function
+    .thumb_func
+pendsv:
+    cpsid i
+    mrs r0, psp
+    mrs r2, psplim
+    stmdb r0!, {r2, r4-r11, lr}
+    bl pendsv_switch
+    ldmia r0!, {r2, r4-r11, lr}
+    msr psplim, r2
+    msr psp, r0
+    cpsie i
+    bx lr
+"#
+);
+
+#[cfg(all(armv8m, has_fpu))]
+global_asm!(
+    r#"
+    .syntax unified
+    .fpu fpv5-sp-d16
+    .section .text.pendsv
+    .global pendsv
+    .type pendsv,%function
+    .thumb_func
+pendsv:
+    cpsid i
+    mrs r0, psp
+    tst lr, #0x10
+    it eq
+    vstmdbeq r0!, {s16-s31}
+    mrs r2, psplim
+    stmdb r0!, {r2, r4-r11, lr}
+    bl pendsv_switch
+    ldmia r0!, {r2, r4-r11, lr}
+    tst lr, #0x10
+    it eq
+    vldmiaeq r0!, {s16-s31}
+    msr psplim, r2
+    msr psp, r0
+    cpsie i
+    bx lr
+"#
+);
+
+#[cfg(armv6m)]
+global_asm!(
+    r#"
+    .syntax unified
+    .section .text.pendsv
+    .global pendsv
+    .type pendsv,%function
+    .thumb_func
+pendsv:
+    cpsid i
+    mrs r0, psp
+    subs r0, #36
+    stmia r0!, {r4-r7}
+    mov r4, r8
+    mov r5, r9
+    mov r6, r10
+    mov r7, r11
+    stmia r0!, {r4-r7}
+    mov r1, lr
+    str r1, [r0]
+    subs r0, #32
+    bl pendsv_switch
+    adds r0, #16
+    ldmia r0!, {r4-r7}
+    mov r8, r4
+    mov r9, r5
+    mov r10, r6
+    mov r11, r7
+    ldr r1, [r0]
+    mov lr, r1
+    subs r0, #32
+    ldmia r0!, {r4-r7}
+    adds r0, #20
+    msr psp, r0
+    cpsie i
+    bx lr
+"#
+);
+
+// Move the running code from MSP to PSP, then point MSP at the top of the
+// exception stack in r0
+global_asm!(
+    r#"
+    .syntax unified
+    .section .text.switch_to_psp
+    .global switch_to_psp
+    .type switch_to_psp,%function
+    .thumb_func
+switch_to_psp:
+    mrs r2, msp
+    msr psp, r2
+    mrs r2, control
+    movs r3, #2
+    orrs r2, r3
+    msr control, r2
+    isb
+    msr msp, r0
+    bx lr
+"#
+);
+
+extern "C" {
+    fn switch_to_psp(msp_top: usize);
+}
+
+/// Run the current code on PSP and the exceptions on their own stack, with
+/// PendSV below every other exception
+pub fn switch_init() {
+    unsafe {
+        let base = EXCEPTION_STACK.as_ptr() as usize;
+        switch_to_psp(base + EXCEPTION_STACK_SIZE);
+        #[cfg(armv8m)]
+        cortex_m::register::msplim::write(base as u32);
+
+        // SHPR3 is only word accessible on ARMv6-M
+        let shpr3 = ptr::read_volatile(SCB_SHPR3);
+        ptr::write_volatile(SCB_SHPR3, shpr3 | (0xff << 16));
+    }
+}
+
+/// Save the current context, store its stack pointer to `old_sp` and
+/// resume the context saved at `new_sp`
+///
+/// The switch happens in PendSV. Until then another call may change where
+/// it goes: the target is replaced, and switching back to the context that
+/// still runs cancels the switch.
+///
+/// # Unsafety
+///
+/// `new_sp` must come from `arch_init_context()` or from an earlier switch
+/// away from that context.
+pub unsafe fn arch_context_switch(old_sp: *mut usize, new_sp: usize) {
+    let state = irq_save();
+
+    if SWITCH_FROM.is_null() {
+        SWITCH_FROM = old_sp;
+        SWITCH_TO = new_sp;
+    } else if new_sp == *SWITCH_FROM {
+        // The context PendSV was about to save still runs
+        SWITCH_FROM = ptr::null_mut();
+    } else {
+        SWITCH_TO = new_sp;
+    }
+
+    if !SWITCH_FROM.is_null() {
+        ptr::write_volatile(SCB_ICSR, SCB_ICSR_PENDSVSET);
+        cortex_m::asm::dsb();
+        cortex_m::asm::isb();
+    }
+
+    irq_restore(state);
+}
+
+/// Have PendSV call `thread::thread_preempt()`
+pub fn arch_request_preempt() {
+    unsafe {
+        ptr::write_volatile(SCB_ICSR, SCB_ICSR_PENDSVSET);
+    }
+}
+
+/// Called by PendSV with the stack pointer of the outgoing context, returns
+/// the one of the context to resume
+#[no_mangle]
+unsafe extern "C" fn pendsv_switch(sp: usize) -> usize {
+    thread::thread_preempt();
+    // A switch the scheduler asked for just now happens on this run
+    ptr::write_volatile(SCB_ICSR, SCB_ICSR_PENDSVCLR);
+
+    if SWITCH_FROM.is_null() {
+        return sp;
+    }
+
+    *SWITCH_FROM = sp;
+    SWITCH_FROM = ptr::null_mut();
+    SWITCH_TO
+}
+
+/// Build the initial frame of a context that calls `entry(arg)` the first
+/// time it is switched to. Returns the stack pointer to hand to
+/// `arch_context_switch()`.
+///
+/// # Unsafety
+///
+/// `[stack_base, stack_top)` must be a stack large enough for `entry`,
+/// which stays valid for as long as the context exists.
+pub unsafe fn arch_init_context(
+    stack_base: usize,
+    stack_top: usize,
+    entry: extern "C" fn(usize) -> !,
+    arg: usize,
+) -> usize {
+    // The core expects an 8 byte aligned exception frame
+    let frame = ((stack_top & !7) - size_of::<ExceptionFrame>()) as *mut ExceptionFrame;
+    let sp = frame as usize - size_of::<SwitchFrame>();
+    debug_assert!(sp > stack_base);
+
+    frame.write(ExceptionFrame {
+        r0: arg as u32,
+        r1: 0,
+        r2: 0,
+        r3: 0,
+        r12: 0,
+        lr: 0,
+        // Exception return ignores bit 0 of the PC, but the Thumb bit of
+        // xPSR must be set
+        pc: entry as u32 & !1,
+        xpsr: 1 << 24,
+    });
+    (sp as *mut SwitchFrame).write(SwitchFrame {
+        // PSPLIM ignores the low 3 bits
+        #[cfg(armv8m)]
+        psplim: (stack_base + 7) & !7,
+        r4: 0,
+        r5: 0,
+        r6: 0,
+        r7: 0,
+        r8: 0,
+        r9: 0,
+        r10: 0,
+        r11: 0,
+        exc_return: EXC_RETURN_THREAD_PSP,
+    });
+
+    sp
+}
@@ -0,0 +1,153 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! SysTick driver
+//!
+//! SysTick is the tick source of the kernel. It normally runs periodic at
+//! the kernel tick rate. With the `tickless` feature the idle path may
+//! reprogram it for a single long interval with `systick_sleep()`.
+
+use core::ptr;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use particle_macros::exception;
+
+use crate::kernel::timer;
+
+/// Frequency of the processor clock feeding SysTick
+///
+/// These are the values QEMU uses for the nRF51822 (micro:bit), the
+/// LM3S6965 and the MPS2 AN505.
+#[cfg(armv6m)]
+pub const SYSTICK_CLOCK_HZ: u32 = 16_000_000;
+#[cfg(armv7m)]
+pub const SYSTICK_CLOCK_HZ: u32 = 12_000_000;
+#[cfg(armv8m)]
+pub const SYSTICK_CLOCK_HZ: u32 = 20_000_000;
+
+/// The SysTick counter is 24 bits wide
+const SYST_MAX_RELOAD: u32 = 0x00ff_ffff;
+
+const SYST_CSR: *mut u32 = 0xe000_e010 as *mut u32;
+const SYST_RVR: *mut u32 = 0xe000_e014 as *mut u32;
+const SYST_CVR: *mut u32 = 0xe000_e018 as *mut u32;
+
+const SYST_CSR_ENABLE: u32 = 1 << 0;
+const SYST_CSR_TICKINT: u32 = 1 << 1;
+const SYST_CSR_CLKSOURCE: u32 = 1 << 2;
+const SYST_CSR_COUNTFLAG: u32 = 1 << 16;
+
+const SCB_ICSR: *mut u32 = 0xe000_ed04 as *mut u32;
+const SCB_ICSR_PENDSTSET: u32 = 1 << 26;
+
+/// Number of processor cycles in one kernel tick
+static CYCLES_PER_TICK: AtomicU32 = AtomicU32::new(0);
+
+/// Start SysTick, firing `tick_hz` times per second
+pub fn systick_init(tick_hz: u32) {
+    let cycles = SYSTICK_CLOCK_HZ / tick_hz;
+    assert!(cycles > 0 && cycles - 1 <= SYST_MAX_RELOAD);
+
+    CYCLES_PER_TICK.store(cycles, Ordering::Relaxed);
+
+    unsafe {
+        ptr::write_volatile(SYST_CSR, 0);
+        ptr::write_volatile(SYST_RVR, cycles - 1);
+        ptr::write_volatile(SYST_CVR, 0);
+        ptr::write_volatile(
+            SYST_CSR,
+            SYST_CSR_CLKSOURCE | SYST_CSR_TICKINT | SYST_CSR_ENABLE,
+        );
+    }
+}
+
+/// Restart the counter so that it wraps after `cycles` and then continues
+/// with the periodic reload value.
+///
+/// Must be called with the counter stopped. A reload value of 0 would stop
+/// the counter, so at least 2 cycles are counted.
+unsafe fn systick_restart(cycles: u32, period: u32) {
+    let cycles = if cycles < 2 { 2 } else { cycles };
+    ptr::write_volatile(SYST_RVR, cycles - 1);
+    ptr::write_volatile(SYST_CVR, 0);
+    ptr::write_volatile(
+        SYST_CSR,
+        SYST_CSR_CLKSOURCE | SYST_CSR_TICKINT | SYST_CSR_ENABLE,
+    );
+    // RVR is only sampled when the counter reloads, so wait until the
+    // first reload happened before writing the periodic value back.
+    while ptr::read_volatile(SYST_CVR) == 0 {}
+    ptr::write_volatile(SYST_RVR, period - 1);
+}
+
+/// Sleep for up to `ticks` kernel ticks without taking a tick interrupt
+///
+/// Reprograms SysTick for a single interval ending `ticks` tick boundaries
+/// from now, waits for an interrupt and returns the number of whole ticks
+/// that passed. The caller must add them to the monotonic clock.
+///
+/// If the interval ran to completion the SysTick exception is left pending
+/// and accounts for the last tick itself, so it is not included in the
+/// returned value.
+///
+/// Must be called with interrupts disabled.
+#[cfg(feature = "tickless")]
+pub fn systick_sleep(ticks: u64) -> u64 {
+    let period = CYCLES_PER_TICK.load(Ordering::Relaxed);
+
+    unsafe {
+        // A tick that is already pending has to be handled first
+        if ptr::read_volatile(SCB_ICSR) & SCB_ICSR_PENDSTSET != 0 {
+            return 0;
+        }
+
+        // Cycles left until the end of the current tick
+        let remaining = ptr::read_volatile(SYST_CVR) + 1;
+        let max_ticks = ((SYST_MAX_RELOAD + 1 - remaining) / period) as u64 + 1;
+        let ticks = if ticks > max_ticks { max_ticks } else { ticks };
+        if ticks <= 1 {
+            super::arch_idle();
+            return 0;
+        }
+
+        let reload = remaining + (ticks as u32 - 1) * period;
+        ptr::write_volatile(SYST_CSR, 0);
+        systick_restart(reload, period);
+
+        super::arch_idle();
+
+        // Stop the counter before looking at it, so that the interval
+        // can't end while we are working out how much time passed.
+        ptr::write_volatile(SYST_CSR, SYST_CSR_CLKSOURCE | SYST_CSR_TICKINT);
+        if ptr::read_volatile(SYST_CSR) & SYST_CSR_COUNTFLAG != 0
+            || ptr::read_volatile(SCB_ICSR) & SCB_ICSR_PENDSTSET != 0
+        {
+            // The whole interval passed and the counter already runs with
+            // the periodic reload value. The pending SysTick exception
+            // counts the last tick.
+            ptr::write_volatile(
+                SYST_CSR,
+                SYST_CSR_CLKSOURCE | SYST_CSR_TICKINT | SYST_CSR_ENABLE,
+            );
+            return ticks - 1;
+        }
+
+        // Woken early by another interrupt. The interval ends `current + 1`
+        // cycles from now and tick boundaries fall every `period` cycles
+        // before that, keep the phase of the current tick.
+        let current = ptr::read_volatile(SYST_CVR);
+        let elapsed = ticks - 1 - (current / period) as u64;
+        let left = (current + 1) % period;
+        systick_restart(if left == 0 { period } else { left }, period);
+
+        elapsed
+    }
+}
+
+#[exception]
+fn SysTick() {
+    timer::timer_tick();
+}
@@ -0,0 +1,272 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! TrustZone-M secure side
+//!
+//! With the `trustzone` feature particle is the secure image on the MPS2
+//! AN505. It splits the memory with the SAU (and the board's MPCs), exports
+//! its services through the secure gateway veneers in `.gnu.sgstubs` and
+//! finally hands the core to the non-secure image.
+//!
+//! Memory map, secure aliases in parentheses:
+//!
+//! - `0x10000000..0x101fffff` secure code, veneers in the last 4K (NSC)
+//! - `0x00200000..0x003fffff` non-secure code (`0x10200000`)
+//! - `0x38000000..0x380fffff` secure RAM
+//! - `0x28100000..0x281fffff` non-secure RAM (`0x38100000`)
+
+#![deny(warnings)]
+
+use core::{ptr, slice};
+
+use cortex_m_semihosting::hio;
+use particle_tz::{Gateway, API_VERSION, EFAULT, GATEWAY_COUNT, SGSTUBS_BASE, VENEER_SIZE};
+
+use crate::kernel::timer;
+
+/// Where the non-secure image keeps its vector table
+pub const NS_IMAGE_BASE: usize = 0x0020_0000;
+
+const NS_CODE: (usize, usize) = (0x0020_0000, 0x003f_ffff);
+const NS_RAM: (usize, usize) = (0x2810_0000, 0x281f_ffff);
+/// Non-secure aliases of the peripherals
+const NS_PERIPHERALS: (usize, usize) = (0x4000_0000, 0x4fff_ffff);
+
+const SAU_CTRL: *mut u32 = 0xe000_edd0 as *mut u32;
+const SAU_TYPE: *mut u32 = 0xe000_edd4 as *mut u32;
+const SAU_RNR: *mut u32 = 0xe000_edd8 as *mut u32;
+const SAU_RBAR: *mut u32 = 0xe000_eddc as *mut u32;
+const SAU_RLAR: *mut u32 = 0xe000_ede0 as *mut u32;
+
+const SAU_CTRL_ENABLE: u32 = 1 << 0;
+const SAU_RLAR_ENABLE: u32 = 1 << 0;
+const SAU_RLAR_NSC: u32 = 1 << 1;
+
+/// Non-secure vector table offset register
+const SCB_NS_VTOR: *mut u32 = 0xe002_ed08 as *mut u32;
+
+/// Memory protection controllers of the SSRAMs, they gate non-secure
+/// accesses behind the SAU
+const MPC_SSRAM1: usize = 0x5800_7000;
+const MPC_SSRAM2: usize = 0x5800_8000;
+
+const MPC_BLK_MAX: usize = 0x10;
+const MPC_BLK_CFG: usize = 0x14;
+const MPC_BLK_IDX: usize = 0x18;
+const MPC_BLK_LUT: usize = 0x1c;
+
+/// TT response: the address is secure
+const TT_S: u32 = 1 << 22;
+/// TT response: the address is readable from the non-secure state
+const TT_NSR: u32 = 1 << 20;
+/// TT response: the SAU region number is valid
+const TT_SRVALID: u32 = 1 << 17;
+
+extern "C" {
+    static __sgstubs_start: u32;
+    static __sgstubs_end: u32;
+
+    /// `TTA` on `addr`, the security attributes as seen from the
+    /// non-secure state
+    fn arch_tt_alt(addr: usize) -> u32;
+
+    fn arch_tz_enter_ns(msp: usize, entry: usize) -> !;
+}
+
+// The veneers, in `Gateway` order. Every slot is an SG followed by a
+// branch to the secure side of the call.
+global_asm!(
+    r#"
+    .syntax unified
+    .section .gnu.sgstubs.particle, "ax", %progbits
+    .align 5
+    .global particle_sg_version
+    .thumb_func
+particle_sg_version:
+    sg
+    b.w particle_nsc_version
+    .global particle_sg_ticks
+    .thumb_func
+particle_sg_ticks:
+    sg
+    b.w particle_nsc_ticks
+    .global particle_sg_console_write
+    .thumb_func
+particle_sg_console_write:
+    sg
+    b.w particle_nsc_console_write
+"#
+);
+
+// The secure side of each call. SG cleared bit 0 of LR, so BXNS returns to
+// the non-secure caller. Scratch registers that don't carry a result are
+// scrubbed so no secure values leak.
+global_asm!(
+    r#"
+    .syntax unified
+    .section .text.particle_nsc, "ax", %progbits
+    .thumb_func
+particle_nsc_version:
+    push {r4, lr}
+    bl tz_version
+    pop {r4, lr}
+    movs r1, #0
+    b particle_nsc_return
+    .thumb_func
+particle_nsc_ticks:
+    push {r4, lr}
+    bl tz_ticks
+    pop {r4, lr}
+    b particle_nsc_return
+    .thumb_func
+particle_nsc_console_write:
+    push {r4, lr}
+    bl tz_console_write
+    pop {r4, lr}
+    movs r1, #0
+    b particle_nsc_return
+    .thumb_func
+particle_nsc_return:
+    mov r2, lr
+    mov r3, lr
+    mov r12, lr
+    msr APSR_nzcvq, lr
+    bxns lr
+
+    .section .text.arch_tt_alt, "ax", %progbits
+    .global arch_tt_alt
+    .thumb_func
+arch_tt_alt:
+    tta r0, r0
+    bx lr
+
+    .section .text.arch_tz_enter_ns, "ax", %progbits
+    .global arch_tz_enter_ns
+    .thumb_func
+arch_tz_enter_ns:
+    msr msp_ns, r0
+    bic r1, r1, #1
+    mov r0, r1
+    mov r2, r1
+    mov r3, r1
+    mov r12, r1
+    blxns r1
+1:
+    b 1b
+"#
+);
+
+#[no_mangle]
+extern "C" fn tz_version() -> u32 {
+    API_VERSION
+}
+
+#[no_mangle]
+extern "C" fn tz_ticks() -> u64 {
+    timer::current_ticks()
+}
+
+#[no_mangle]
+extern "C" fn tz_console_write(buf: *const u8, len: usize) -> isize {
+    if !ns_readable(buf as usize, len) {
+        return EFAULT;
+    }
+
+    let buf = unsafe { slice::from_raw_parts(buf, len) };
+    match hio::hstdout().and_then(|mut out| out.write_all(buf)) {
+        Ok(()) => len as isize,
+        Err(()) => -1,
+    }
+}
+
+/// Whether the non-secure side may read `[addr, addr + len)`
+///
+/// Both ends have to be non-secure, readable and in the same SAU region,
+/// so the range can't straddle into secure memory.
+fn ns_readable(addr: usize, len: usize) -> bool {
+    if len == 0 {
+        return true;
+    }
+    let last = match addr.checked_add(len - 1) {
+        Some(last) => last,
+        None => return false,
+    };
+
+    let (first, last) = unsafe { (arch_tt_alt(addr), arch_tt_alt(last)) };
+    let ok = |tt: u32| tt & TT_S == 0 && tt & TT_NSR != 0 && tt & TT_SRVALID != 0;
+    ok(first) && ok(last) && (first >> 8) & 0xff == (last >> 8) & 0xff
+}
+
+/// Program SAU region `index` to cover `[base, limit]`
+unsafe fn sau_set_region(index: u32, base: usize, limit: usize, nsc: bool) {
+    let nsc = if nsc { SAU_RLAR_NSC } else { 0 };
+    ptr::write_volatile(SAU_RNR, index);
+    ptr::write_volatile(SAU_RBAR, base as u32 & !31);
+    ptr::write_volatile(SAU_RLAR, (limit as u32 & !31) | nsc | SAU_RLAR_ENABLE);
+}
+
+/// Mark `[base, limit]` (offsets into the SSRAM behind `mpc`) non-secure
+unsafe fn mpc_set_nonsecure(mpc: usize, base: usize, limit: usize) {
+    let reg = |offset: usize| (mpc + offset) as *mut u32;
+
+    let block_size = 32usize << ptr::read_volatile(reg(MPC_BLK_CFG));
+    let max_index = ptr::read_volatile(reg(MPC_BLK_MAX)) as usize;
+
+    // Every LUT word covers 32 blocks
+    for block in base / block_size..=limit / block_size {
+        let (index, bit) = (block / 32, block % 32);
+        if index > max_index {
+            break;
+        }
+        ptr::write_volatile(reg(MPC_BLK_IDX), index as u32);
+        let lut = ptr::read_volatile(reg(MPC_BLK_LUT));
+        ptr::write_volatile(reg(MPC_BLK_LUT), lut | (1 << bit));
+    }
+}
+
+/// Split the memory between the secure and the non-secure world
+pub fn tz_init() {
+    unsafe {
+        let start = &__sgstubs_start as *const u32 as usize;
+        let end = &__sgstubs_end as *const u32 as usize;
+        assert_eq!(start, SGSTUBS_BASE, "veneers are not where particle-tz expects them");
+        assert_eq!(end - start, GATEWAY_COUNT * VENEER_SIZE);
+        assert_eq!(Gateway::ConsoleWrite.veneer(), SGSTUBS_BASE + 2 * VENEER_SIZE);
+
+        assert!(ptr::read_volatile(SAU_TYPE) & 0xff >= 4, "not enough SAU regions");
+
+        sau_set_region(0, NS_CODE.0, NS_CODE.1, false);
+        sau_set_region(1, SGSTUBS_BASE, SGSTUBS_BASE + 0xfff, true);
+        sau_set_region(2, NS_RAM.0, NS_RAM.1, false);
+        sau_set_region(3, NS_PERIPHERALS.0, NS_PERIPHERALS.1, false);
+        ptr::write_volatile(SAU_CTRL, SAU_CTRL_ENABLE);
+
+        // SSRAM1 holds the code, SSRAM2 the RAM
+        mpc_set_nonsecure(MPC_SSRAM1, NS_CODE.0, NS_CODE.1);
+        mpc_set_nonsecure(MPC_SSRAM2, NS_RAM.0 - 0x2800_0000, NS_RAM.1 - 0x2800_0000);
+    }
+
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+}
+
+/// Start the non-secure image at `NS_IMAGE_BASE`
+///
+/// # Unsafety
+///
+/// A valid non-secure image must have been loaded there and `tz_init()`
+/// must have been called.
+pub unsafe fn tz_boot_nonsecure() -> ! {
+    let vectors = NS_IMAGE_BASE as *const usize;
+    let msp = ptr::read_volatile(vectors);
+    let reset = ptr::read_volatile(vectors.offset(1));
+
+    ptr::write_volatile(SCB_NS_VTOR, NS_IMAGE_BASE as u32);
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+
+    arch_tz_enter_ns(msp, reset)
+}
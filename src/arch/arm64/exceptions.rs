@@ -0,0 +1,191 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Exception vectors
+//!
+//! Every vector saves the full register state, including the FP/SIMD
+//! registers Rust code may use, into an `ExceptionFrame` on the current
+//! stack and calls `arm64_exception_handler`. IRQs go to the GIC, anything
+//! else is fatal for now. The frame stays on the stack of the interrupted
+//! thread, so the handler may switch to another thread when an IRQ made one
+//! ready.
+
+#![deny(warnings)]
+
+use super::gic;
+use super::sysreg;
+use crate::thread;
+
+/// The register state saved on exception entry
+#[allow(dead_code)]
+#[repr(C)]
+pub struct ExceptionFrame {
+    pub x: [u64; 31],
+    pub elr: u64,
+    pub spsr: u64,
+    _pad: u64,
+    pub q: [u128; 32],
+    pub fpsr: u64,
+    pub fpcr: u64,
+}
+
+/// Which of the 16 vectors was taken, the low two bits give the type
+const VECTOR_IRQ: u64 = 1;
+
+global_asm!(
+    r#"
+    .macro vector kind
+    .balign 0x80
+    sub sp, sp, #0x320
+    stp x0, x1, [sp, #0x00]
+    mov x1, #\kind
+    b arm64_exception_entry
+    .endm
+
+    .section .text.arm64_vectors, "ax", %progbits
+    .balign 0x800
+    .global arm64_vectors
+arm64_vectors:
+    // Current EL with SP0
+    vector 0
+    vector 1
+    vector 2
+    vector 3
+    // Current EL with SPx
+    vector 4
+    vector 5
+    vector 6
+    vector 7
+    // Lower EL using AArch64
+    vector 8
+    vector 9
+    vector 10
+    vector 11
+    // Lower EL using AArch32
+    vector 12
+    vector 13
+    vector 14
+    vector 15
+
+arm64_exception_entry:
+    stp x2, x3, [sp, #0x10]
+    stp x4, x5, [sp, #0x20]
+    stp x6, x7, [sp, #0x30]
+    stp x8, x9, [sp, #0x40]
+    stp x10, x11, [sp, #0x50]
+    stp x12, x13, [sp, #0x60]
+    stp x14, x15, [sp, #0x70]
+    stp x16, x17, [sp, #0x80]
+    stp x18, x19, [sp, #0x90]
+    stp x20, x21, [sp, #0xa0]
+    stp x22, x23, [sp, #0xb0]
+    stp x24, x25, [sp, #0xc0]
+    stp x26, x27, [sp, #0xd0]
+    stp x28, x29, [sp, #0xe0]
+    mrs x2, elr_el1
+    mrs x3, spsr_el1
+    stp x30, x2, [sp, #0xf0]
+    str x3, [sp, #0x100]
+
+    add x2, sp, #0x110
+    stp q0, q1, [x2, #0x000]
+    stp q2, q3, [x2, #0x020]
+    stp q4, q5, [x2, #0x040]
+    stp q6, q7, [x2, #0x060]
+    stp q8, q9, [x2, #0x080]
+    stp q10, q11, [x2, #0x0a0]
+    stp q12, q13, [x2, #0x0c0]
+    stp q14, q15, [x2, #0x0e0]
+    stp q16, q17, [x2, #0x100]
+    stp q18, q19, [x2, #0x120]
+    stp q20, q21, [x2, #0x140]
+    stp q22, q23, [x2, #0x160]
+    stp q24, q25, [x2, #0x180]
+    stp q26, q27, [x2, #0x1a0]
+    stp q28, q29, [x2, #0x1c0]
+    stp q30, q31, [x2, #0x1e0]
+    mrs x3, fpsr
+    mrs x4, fpcr
+    str x3, [x2, #0x200]
+    str x4, [x2, #0x208]
+
+    mov x0, sp
+    bl arm64_exception_handler
+
+    add x2, sp, #0x110
+    ldr x3, [x2, #0x200]
+    ldr x4, [x2, #0x208]
+    msr fpsr, x3
+    msr fpcr, x4
+    ldp q0, q1, [x2, #0x000]
+    ldp q2, q3, [x2, #0x020]
+    ldp q4, q5, [x2, #0x040]
+    ldp q6, q7, [x2, #0x060]
+    ldp q8, q9, [x2, #0x080]
+    ldp q10, q11, [x2, #0x0a0]
+    ldp q12, q13, [x2, #0x0c0]
+    ldp q14, q15, [x2, #0x0e0]
+    ldp q16, q17, [x2, #0x100]
+    ldp q18, q19, [x2, #0x120]
+    ldp q20, q21, [x2, #0x140]
+    ldp q22, q23, [x2, #0x160]
+    ldp q24, q25, [x2, #0x180]
+    ldp q26, q27, [x2, #0x1a0]
+    ldp q28, q29, [x2, #0x1c0]
+    ldp q30, q31, [x2, #0x1e0]
+
+    ldr x3, [sp, #0x100]
+    ldp x30, x2, [sp, #0xf0]
+    msr elr_el1, x2
+    msr spsr_el1, x3
+    ldp x28, x29, [sp, #0xe0]
+    ldp x26, x27, [sp, #0xd0]
+    ldp x24, x25, [sp, #0xc0]
+    ldp x22, x23, [sp, #0xb0]
+    ldp x20, x21, [sp, #0xa0]
+    ldp x18, x19, [sp, #0x90]
+    ldp x16, x17, [sp, #0x80]
+    ldp x14, x15, [sp, #0x70]
+    ldp x12, x13, [sp, #0x60]
+    ldp x10, x11, [sp, #0x50]
+    ldp x8, x9, [sp, #0x40]
+    ldp x6, x7, [sp, #0x30]
+    ldp x4, x5, [sp, #0x20]
+    ldp x2, x3, [sp, #0x10]
+    ldp x0, x1, [sp, #0x00]
+    add sp, sp, #0x320
+    eret
+"#
+);
+
+extern "C" {
+    static arm64_vectors: u8;
+}
+
+/// Point VBAR_EL1 at the vector table
+pub fn exceptions_init() {
+    unsafe {
+        sysreg::arm64_set_vbar(&arm64_vectors as *const u8 as usize);
+    }
+}
+
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn arm64_exception_handler(frame: &mut ExceptionFrame, vector: u64) {
+    if vector & 3 == VECTOR_IRQ {
+        gic::gic_handle_irq();
+        thread::thread_preempt();
+        return;
+    }
+
+    panic!(
+        "unhandled exception: vector {} esr {:#x} far {:#x} elr {:#x}",
+        vector,
+        sysreg::arm64_read_esr(),
+        sysreg::arm64_read_far(),
+        frame.elr
+    );
+}
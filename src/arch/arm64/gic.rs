@@ -0,0 +1,198 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Generic Interrupt Controller, version 2 and 3
+//!
+//! QEMU virt puts the distributor at the same address for both versions
+//! (`-machine virt,gic-version=2|3`), the version is read from GICD_PIDR2.
+//! GICv2 has a memory mapped CPU interface, GICv3 a redistributor per core
+//! plus the ICC system registers. Only the boot core is brought up.
+
+#![deny(warnings)]
+
+use core::ptr;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::sysreg;
+use crate::kernel::irq::IrqLock;
+
+const GICD_BASE: usize = 0x0800_0000;
+/// GICv2 CPU interface
+const GICC_BASE: usize = 0x0801_0000;
+/// GICv3 redistributor of core 0, and its SGI/PPI frame
+const GICR_BASE: usize = 0x080a_0000;
+const GICR_SGI_BASE: usize = GICR_BASE + 0x1_0000;
+
+const GICD_CTLR: usize = 0x000;
+const GICD_TYPER: usize = 0x004;
+const GICD_IGROUPR: usize = 0x080;
+const GICD_ISENABLER: usize = 0x100;
+const GICD_ICENABLER: usize = 0x180;
+const GICD_IPRIORITYR: usize = 0x400;
+const GICD_ITARGETSR: usize = 0x800;
+const GICD_PIDR2: usize = 0xffe8;
+
+const GICC_CTLR: usize = 0x000;
+const GICC_PMR: usize = 0x004;
+const GICC_IAR: usize = 0x00c;
+const GICC_EOIR: usize = 0x010;
+
+const GICR_WAKER: usize = 0x014;
+const GICR_IGROUPR0: usize = 0x080;
+const GICR_ISENABLER0: usize = 0x100;
+const GICR_ICENABLER0: usize = 0x180;
+
+const GICR_WAKER_PROCESSOR_SLEEP: u32 = 1 << 1;
+const GICR_WAKER_CHILDREN_ASLEEP: u32 = 1 << 2;
+
+/// GICD_CTLR with a single security state: group 0, group 1 and affinity
+/// routing (GICv3)
+const GICD_CTLR_ENABLE_GRP0: u32 = 1 << 0;
+const GICD_CTLR_ENABLE_GRP1: u32 = 1 << 1;
+const GICD_CTLR_ARE: u32 = 1 << 4;
+
+/// INTIDs from here on are special (1023 is "spurious")
+const INTID_SPECIAL: u32 = 1020;
+
+/// Number of private interrupts (SGIs and PPIs) per core
+const PRIVATE_IRQS: u32 = 32;
+
+/// The interrupt IDs particle can route to a handler
+pub const MAX_IRQS: usize = 256;
+
+/// Default priority of every interrupt, lower is more urgent
+const IRQ_PRIORITY: u8 = 0xa0;
+
+/// The architecture version, 0 until `gic_init()`
+static GIC_VERSION: AtomicU32 = AtomicU32::new(0);
+
+static HANDLERS: IrqLock<[Option<fn()>; MAX_IRQS]> = IrqLock::new([None; MAX_IRQS]);
+
+fn reg(base: usize, offset: usize) -> *mut u32 {
+    (base + offset) as *mut u32
+}
+
+unsafe fn read(base: usize, offset: usize) -> u32 {
+    ptr::read_volatile(reg(base, offset))
+}
+
+unsafe fn write(base: usize, offset: usize, val: u32) {
+    ptr::write_volatile(reg(base, offset), val)
+}
+
+fn is_v3() -> bool {
+    GIC_VERSION.load(Ordering::Relaxed) >= 3
+}
+
+/// Bring up the distributor and the interface of the boot core, with every
+/// interrupt disabled
+pub fn gic_init() {
+    unsafe {
+        let version = (read(GICD_BASE, GICD_PIDR2) >> 4) & 0xf;
+        GIC_VERSION.store(version, Ordering::Relaxed);
+
+        let lines = ((read(GICD_BASE, GICD_TYPER) & 0x1f) + 1) * 32;
+
+        // Group 1 is signalled as IRQ on GICv3, GICv2 sends group 0 as IRQ
+        let group = if version >= 3 { !0 } else { 0 };
+
+        write(GICD_BASE, GICD_CTLR, 0);
+        for n in (PRIVATE_IRQS..lines).step_by(32) {
+            let word = (n / 32) as usize * 4;
+            write(GICD_BASE, GICD_ICENABLER + word, !0);
+            write(GICD_BASE, GICD_IGROUPR + word, group);
+        }
+
+        if version >= 3 {
+            write(
+                GICD_BASE,
+                GICD_CTLR,
+                GICD_CTLR_ARE | GICD_CTLR_ENABLE_GRP1 | GICD_CTLR_ENABLE_GRP0,
+            );
+
+            // Wake up the redistributor of this core
+            let waker = read(GICR_BASE, GICR_WAKER);
+            write(GICR_BASE, GICR_WAKER, waker & !GICR_WAKER_PROCESSOR_SLEEP);
+            while read(GICR_BASE, GICR_WAKER) & GICR_WAKER_CHILDREN_ASLEEP != 0 {}
+
+            write(GICR_SGI_BASE, GICR_ICENABLER0, !0);
+            write(GICR_SGI_BASE, GICR_IGROUPR0, !0);
+
+            sysreg::arm64_write_icc_sre(0x7);
+            sysreg::arm64_write_icc_pmr(0xff);
+            sysreg::arm64_write_icc_igrpen1(1);
+        } else {
+            write(GICD_BASE, GICD_CTLR, GICD_CTLR_ENABLE_GRP0);
+            write(GICC_BASE, GICC_PMR, 0xff);
+            write(GICC_BASE, GICC_CTLR, 1);
+        }
+    }
+}
+
+/// Route `irq` to `handler` and unmask it
+pub fn gic_register(irq: u32, handler: fn()) {
+    assert!((irq as usize) < MAX_IRQS);
+
+    HANDLERS.lock()[irq as usize] = Some(handler);
+
+    unsafe {
+        set_priority(irq, IRQ_PRIORITY);
+        let (word, bit) = ((irq / 32) as usize * 4, 1 << (irq % 32));
+        if irq < PRIVATE_IRQS && is_v3() {
+            write(GICR_SGI_BASE, GICR_ISENABLER0, bit);
+        } else {
+            if !is_v3() && irq >= PRIVATE_IRQS {
+                // Deliver to the boot core
+                let target = reg(GICD_BASE, GICD_ITARGETSR + irq as usize) as *mut u8;
+                ptr::write_volatile(target, 1);
+            }
+            write(GICD_BASE, GICD_ISENABLER + word, bit);
+        }
+    }
+}
+
+/// GICR_IPRIORITYR of the SGI frame has the same offset as GICD_IPRIORITYR
+unsafe fn set_priority(irq: u32, priority: u8) {
+    let base = if irq < PRIVATE_IRQS && is_v3() {
+        GICR_SGI_BASE
+    } else {
+        GICD_BASE
+    };
+    let prio = reg(base, GICD_IPRIORITYR + irq as usize) as *mut u8;
+    ptr::write_volatile(prio, priority);
+}
+
+/// Acknowledge the pending interrupt, run its handler and signal its end
+pub fn gic_handle_irq() {
+    let intid = unsafe {
+        if is_v3() {
+            sysreg::arm64_read_icc_iar1() as u32 & 0xff_ffff
+        } else {
+            read(GICC_BASE, GICC_IAR) & 0x3ff
+        }
+    };
+
+    if intid >= INTID_SPECIAL {
+        return;
+    }
+
+    let handler = if (intid as usize) < MAX_IRQS {
+        HANDLERS.lock()[intid as usize]
+    } else {
+        None
+    };
+    if let Some(handler) = handler {
+        handler();
+    }
+
+    unsafe {
+        if is_v3() {
+            sysreg::arm64_write_icc_eoir1(intid as u64);
+        } else {
+            write(GICC_BASE, GICC_EOIR, intid);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! AArch64 on QEMU virt
+//!
+//! particle runs at EL1 with the MMU off, so `novm` applies. The board
+//! provides the GIC (version 2 or 3), the generic timer and a PL011 UART.
+
+pub mod start;
+
+pub mod semihosting;
+
+mod exceptions;
+mod gic;
+mod pl011;
+mod switch;
+mod sysreg;
+mod timer;
+
+pub use self::pl011::console_write_str;
+pub use self::switch::{arch_context_switch, arch_init_context};
+
+#[cfg(feature = "tickless")]
+pub use self::timer::generic_timer_sleep as timer_sleep;
+
+pub const PAGE_SIZE: u32 = 4096;
+pub const PAGE_SIZE_SHIFT: u32 = 12;
+
+pub fn arch_early_init() {
+    exceptions::exceptions_init();
+    gic::gic_init();
+    unsafe { sysreg::arm64_irq_enable() };
+}
+
+/// Start the periodic kernel tick
+pub fn timer_init(tick_hz: u32) {
+    timer::generic_timer_init(tick_hz);
+}
+
+/// Disable IRQs, returns the previous state for `irq_restore()`
+pub fn irq_save() -> usize {
+    unsafe { sysreg::arm64_irq_save() as usize }
+}
+
+pub fn irq_restore(state: usize) {
+    unsafe { sysreg::arm64_irq_restore(state as u64) }
+}
+
+/// Nothing to do, the exception handler calls `thread::thread_preempt()`
+/// after every IRQ
+pub fn arch_request_preempt() {}
+
+/// No stack guard, overflows are caught by the canary check when a thread
+/// is switched out
+pub const STACK_GUARD_SIZE: usize = 0;
+
+pub fn arch_stack_guard(_base: usize) {}
+
+/// Wait for the next interrupt
+pub fn arch_idle() {
+    unsafe { sysreg::arm64_wfi() }
+}
+
+/// Leave QEMU with an exit status telling whether the run succeeded
+pub fn arch_exit(success: bool) -> ! {
+    semihosting::sys_exit(if success { 0 } else { 1 })
+}
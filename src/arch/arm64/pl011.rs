@@ -0,0 +1,39 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! PL011 UART, the console of QEMU virt
+//!
+//! QEMU has the UART enabled and configured at reset, so only the transmit
+//! path is driven here, by polling.
+
+#![deny(warnings)]
+
+use core::ptr;
+
+const UART_BASE: usize = 0x0900_0000;
+
+const UART_DR: *mut u32 = UART_BASE as *mut u32;
+const UART_FR: *mut u32 = (UART_BASE + 0x18) as *mut u32;
+
+/// Transmit FIFO full
+const UART_FR_TXFF: u32 = 1 << 5;
+
+fn putc(c: u8) {
+    unsafe {
+        while ptr::read_volatile(UART_FR) & UART_FR_TXFF != 0 {}
+        ptr::write_volatile(UART_DR, c as u32);
+    }
+}
+
+/// Write `s` to the UART, with `\n` turned into `\r\n`
+pub fn console_write_str(s: &str) {
+    for c in s.bytes() {
+        if c == b'\n' {
+            putc(b'\r');
+        }
+        putc(c);
+    }
+}
@@ -0,0 +1,49 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! The few semihosting calls particle needs on AArch64
+//!
+//! A call is `HLT #0xf000` with the operation in w0 and a pointer to its
+//! arguments in x1. QEMU needs `-semihosting-config enable=on`.
+
+#![deny(warnings)]
+
+#[cfg(feature = "ktest")]
+const SYS_CLOCK: u64 = 0x10;
+const SYS_EXIT: u64 = 0x18;
+
+/// ADP_Stopped_ApplicationExit
+const ADP_STOPPED_APPLICATION_EXIT: u64 = 0x20026;
+
+global_asm!(
+    r#"
+    .section .text.arm64_semihosting, "ax", %progbits
+    .global arm64_semihosting
+arm64_semihosting:
+    hlt #0xf000
+    ret
+"#
+);
+
+extern "C" {
+    fn arm64_semihosting(op: u64, args: usize) -> u64;
+}
+
+/// Centiseconds since the program started, as the host sees it
+#[cfg(feature = "ktest")]
+pub fn sys_clock() -> u64 {
+    unsafe { arm64_semihosting(SYS_CLOCK, 0) }
+}
+
+/// Stop QEMU with exit `code`
+pub fn sys_exit(code: u64) -> ! {
+    let args = [ADP_STOPPED_APPLICATION_EXIT, code];
+    unsafe {
+        arm64_semihosting(SYS_EXIT, args.as_ptr() as usize);
+    }
+
+    loop {}
+}
@@ -0,0 +1,75 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Boot code for AArch64
+//!
+//! - `_start`. The first instruction QEMU runs. Secondary cores are parked,
+//! the boot core drops from EL2 to EL1 if needed, gets a stack and FP/SIMD
+//! access, and continues in `arm64_reset`, which initializes RAM and calls
+//! kmain().
+
+#![deny(warnings)]
+
+global_asm!(
+    r#"
+    .section .text.boot, "ax", %progbits
+    .global _start
+_start:
+    mrs x0, mpidr_el1
+    and x0, x0, #0xff
+    cbz x0, 2f
+1:
+    wfe
+    b 1b
+
+2:
+    mrs x0, CurrentEL
+    lsr x0, x0, #2
+    cmp x0, #2
+    b.ne 3f
+
+    // EL2: run EL1 in AArch64 with access to the counters and timers
+    mov x0, #(1 << 31)
+    msr hcr_el2, x0
+    mov x0, #3
+    msr cnthctl_el2, x0
+    msr cntvoff_el2, xzr
+    mov x0, #0x3c5
+    msr spsr_el2, x0
+    adr x0, 3f
+    msr elr_el2, x0
+    eret
+
+3:
+    ldr x0, =_stack_start
+    mov sp, x0
+
+    // No traps on FP/SIMD, Rust code uses them freely
+    mov x0, #(3 << 20)
+    msr cpacr_el1, x0
+    isb
+
+    bl arm64_reset
+4:
+    wfe
+    b 4b
+"#
+);
+
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn arm64_reset() -> ! {
+    extern "C" {
+        // These symbols come from `link-arm64.x`
+        static mut __sbss: u64;
+        static mut __ebss: u64;
+    }
+
+    // QEMU loads .data in place, only .bss needs to be cleared
+    rrt0::zero_bss(&mut __sbss, &mut __ebss);
+
+    crate::kmain();
+}
@@ -0,0 +1,130 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Cooperative context switch
+//!
+//! Same contract as on Cortex-M: `arch_context_switch()` saves the callee
+//! saved registers (x19-x30 and the low halves of v8-v15) on the current
+//! stack, stores the stack pointer and resumes the context saved on the
+//! other stack.
+
+#![deny(warnings)]
+
+use core::mem::size_of;
+
+/// The registers saved by `arch_context_switch()`, lowest address first
+#[repr(C)]
+struct SwitchFrame {
+    x19: usize,
+    x20: usize,
+    x21: usize,
+    x22: usize,
+    x23: usize,
+    x24: usize,
+    x25: usize,
+    x26: usize,
+    x27: usize,
+    x28: usize,
+    x29: usize,
+    x30: usize,
+    d8_d15: [u64; 8],
+}
+
+global_asm!(
+    r#"
+    .section .text.arch_context_switch, "ax", %progbits
+    .global arch_context_switch
+    .type arch_context_switch,%function
+arch_context_switch:
+    sub sp, sp, #160
+    stp x19, x20, [sp, #0x00]
+    stp x21, x22, [sp, #0x10]
+    stp x23, x24, [sp, #0x20]
+    stp x25, x26, [sp, #0x30]
+    stp x27, x28, [sp, #0x40]
+    stp x29, x30, [sp, #0x50]
+    stp d8, d9, [sp, #0x60]
+    stp d10, d11, [sp, #0x70]
+    stp d12, d13, [sp, #0x80]
+    stp d14, d15, [sp, #0x90]
+    mov x2, sp
+    str x2, [x0]
+    mov sp, x1
+    ldp x19, x20, [sp, #0x00]
+    ldp x21, x22, [sp, #0x10]
+    ldp x23, x24, [sp, #0x20]
+    ldp x25, x26, [sp, #0x30]
+    ldp x27, x28, [sp, #0x40]
+    ldp x29, x30, [sp, #0x50]
+    ldp d8, d9, [sp, #0x60]
+    ldp d10, d11, [sp, #0x70]
+    ldp d12, d13, [sp, #0x80]
+    ldp d14, d15, [sp, #0x90]
+    add sp, sp, #160
+    ret
+"#
+);
+
+// A new context starts here with the argument in x19 and the entry point in
+// x20. It may be first switched to from the IRQ handler, so it unmasks
+// IRQs.
+global_asm!(
+    r#"
+    .section .text.arch_context_start, "ax", %progbits
+    .global arch_context_start
+    .type arch_context_start,%function
+arch_context_start:
+    msr daifclr, #2
+    mov x0, x19
+    br x20
+"#
+);
+
+extern "C" {
+    /// Save the current context, store its stack pointer to `old_sp` and
+    /// resume the context saved at `new_sp`
+    pub fn arch_context_switch(old_sp: *mut usize, new_sp: usize);
+
+    fn arch_context_start();
+}
+
+/// Build the initial frame of a context that calls `entry(arg)` the first
+/// time it is switched to. Returns the stack pointer to hand to
+/// `arch_context_switch()`.
+///
+/// # Unsafety
+///
+/// `[stack_base, stack_top)` must be a stack large enough for `entry`,
+/// which stays valid for as long as the context exists.
+pub unsafe fn arch_init_context(
+    stack_base: usize,
+    stack_top: usize,
+    entry: extern "C" fn(usize) -> !,
+    arg: usize,
+) -> usize {
+    // SP must stay 16 byte aligned
+    let sp = (stack_top & !15) - size_of::<SwitchFrame>();
+    let frame = sp as *mut SwitchFrame;
+    debug_assert!(sp > stack_base);
+
+    frame.write(SwitchFrame {
+        x19: arg,
+        x20: entry as usize,
+        x21: 0,
+        x22: 0,
+        x23: 0,
+        x24: 0,
+        x25: 0,
+        x26: 0,
+        x27: 0,
+        x28: 0,
+        x29: 0,
+        x30: arch_context_start as usize,
+        d8_d15: [0; 8],
+    });
+
+    sp
+}
@@ -0,0 +1,131 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! System register accessors
+//!
+//! One small assembly function per register, so the rest of the port can
+//! stay in plain Rust.
+
+#![deny(warnings)]
+
+global_asm!(
+    r#"
+    .section .text.arm64_sysreg, "ax", %progbits
+
+    .global arm64_irq_save
+arm64_irq_save:
+    mrs x0, daif
+    msr daifset, #2
+    ret
+
+    .global arm64_irq_restore
+arm64_irq_restore:
+    msr daif, x0
+    ret
+
+    .global arm64_irq_enable
+arm64_irq_enable:
+    msr daifclr, #2
+    ret
+
+    .global arm64_wfi
+arm64_wfi:
+    dsb sy
+    wfi
+    ret
+
+    .global arm64_set_vbar
+arm64_set_vbar:
+    msr vbar_el1, x0
+    isb
+    ret
+
+    .global arm64_read_esr
+arm64_read_esr:
+    mrs x0, esr_el1
+    ret
+
+    .global arm64_read_far
+arm64_read_far:
+    mrs x0, far_el1
+    ret
+
+    .global arm64_read_cntfrq
+arm64_read_cntfrq:
+    mrs x0, cntfrq_el0
+    ret
+
+    .global arm64_read_cntvct
+arm64_read_cntvct:
+    isb
+    mrs x0, cntvct_el0
+    ret
+
+    .global arm64_write_cntv_cval
+arm64_write_cntv_cval:
+    msr cntv_cval_el0, x0
+    isb
+    ret
+
+    .global arm64_write_cntv_ctl
+arm64_write_cntv_ctl:
+    msr cntv_ctl_el0, x0
+    isb
+    ret
+
+    .global arm64_write_icc_sre
+arm64_write_icc_sre:
+    msr S3_0_C12_C12_5, x0
+    isb
+    ret
+
+    .global arm64_write_icc_pmr
+arm64_write_icc_pmr:
+    msr S3_0_C4_C6_0, x0
+    ret
+
+    .global arm64_write_icc_igrpen1
+arm64_write_icc_igrpen1:
+    msr S3_0_C12_C12_7, x0
+    isb
+    ret
+
+    .global arm64_read_icc_iar1
+arm64_read_icc_iar1:
+    mrs x0, S3_0_C12_C12_0
+    dsb sy
+    ret
+
+    .global arm64_write_icc_eoir1
+arm64_write_icc_eoir1:
+    msr S3_0_C12_C12_1, x0
+    isb
+    ret
+"#
+);
+
+extern "C" {
+    /// Mask IRQs, returns the previous DAIF
+    pub fn arm64_irq_save() -> u64;
+    pub fn arm64_irq_restore(daif: u64);
+    pub fn arm64_irq_enable();
+    pub fn arm64_wfi();
+    pub fn arm64_set_vbar(vbar: usize);
+    pub fn arm64_read_esr() -> u64;
+    pub fn arm64_read_far() -> u64;
+
+    pub fn arm64_read_cntfrq() -> u64;
+    pub fn arm64_read_cntvct() -> u64;
+    pub fn arm64_write_cntv_cval(cval: u64);
+    pub fn arm64_write_cntv_ctl(ctl: u64);
+
+    // GICv3 CPU interface, by encoding as not every assembler knows them
+    pub fn arm64_write_icc_sre(sre: u64);
+    pub fn arm64_write_icc_pmr(pmr: u64);
+    pub fn arm64_write_icc_igrpen1(en: u64);
+    pub fn arm64_read_icc_iar1() -> u64;
+    pub fn arm64_write_icc_eoir1(intid: u64);
+}
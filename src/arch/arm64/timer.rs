@@ -0,0 +1,103 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Generic timer driver
+//!
+//! The EL1 virtual timer is the tick source. It is a compare register
+//! against the 64 bit system counter, so every tick programs the next
+//! deadline and a late interrupt catches up on all the ticks it missed.
+//! With the `tickless` feature `timer_sleep()` programs one far deadline
+//! instead.
+
+#![deny(warnings)]
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::gic;
+use super::sysreg;
+use crate::kernel::timer;
+
+/// PPI of the EL1 virtual timer
+const TIMER_IRQ: u32 = 27;
+
+const CNTV_CTL_ENABLE: u64 = 1 << 0;
+
+/// Counter cycles in one kernel tick
+static CYCLES_PER_TICK: AtomicU64 = AtomicU64::new(0);
+/// Counter value of the next kernel tick
+static NEXT_TICK: AtomicU64 = AtomicU64::new(0);
+
+/// Start the virtual timer, firing `tick_hz` times per second
+pub fn generic_timer_init(tick_hz: u32) {
+    let cycles = unsafe { sysreg::arm64_read_cntfrq() } / tick_hz as u64;
+    assert!(cycles > 0);
+
+    CYCLES_PER_TICK.store(cycles, Ordering::Relaxed);
+
+    unsafe {
+        let next = sysreg::arm64_read_cntvct() + cycles;
+        NEXT_TICK.store(next, Ordering::Relaxed);
+        sysreg::arm64_write_cntv_cval(next);
+        sysreg::arm64_write_cntv_ctl(CNTV_CTL_ENABLE);
+    }
+
+    gic::gic_register(TIMER_IRQ, generic_timer_irq);
+}
+
+/// Count the ticks that passed up to `now` and move the deadline past it
+fn catch_up(now: u64) -> u64 {
+    let cycles = CYCLES_PER_TICK.load(Ordering::Relaxed);
+    let mut next = NEXT_TICK.load(Ordering::Relaxed);
+    let mut ticks = 0;
+    while now >= next {
+        next += cycles;
+        ticks += 1;
+    }
+
+    NEXT_TICK.store(next, Ordering::Relaxed);
+    unsafe { sysreg::arm64_write_cntv_cval(next) };
+    ticks
+}
+
+fn generic_timer_irq() {
+    let ticks = catch_up(unsafe { sysreg::arm64_read_cntvct() });
+    if ticks > 0 {
+        timer::timer_advance(ticks);
+    }
+}
+
+/// Sleep for up to `ticks` kernel ticks or until an interrupt, whichever
+/// comes first. Called with interrupts disabled. Returns the ticks that
+/// passed, the regular tick deadline is back in place afterwards.
+///
+/// The timer interrupt stays pending once the deadline is hit, so it runs
+/// when the caller re-enables interrupts and accounts for the last tick
+/// itself, which is why it isn't counted here.
+#[cfg(feature = "tickless")]
+pub fn generic_timer_sleep(ticks: u64) -> u64 {
+    let cycles = CYCLES_PER_TICK.load(Ordering::Relaxed);
+    let next = NEXT_TICK.load(Ordering::Relaxed);
+
+    // The first tick of the sleep is the one already programmed
+    let deadline = next.saturating_add(cycles.saturating_mul(ticks - 1));
+    unsafe {
+        sysreg::arm64_write_cntv_cval(deadline);
+        sysreg::arm64_wfi();
+    }
+
+    let now = unsafe { sysreg::arm64_read_cntvct() };
+    if now < next {
+        unsafe { sysreg::arm64_write_cntv_cval(next) };
+        return 0;
+    }
+
+    // Leave the tick at `now` (if any) to the pending interrupt
+    let elapsed = catch_up(now) - 1;
+    let last = NEXT_TICK.load(Ordering::Relaxed) - cycles;
+    NEXT_TICK.store(last, Ordering::Relaxed);
+    unsafe { sysreg::arm64_write_cntv_cval(last) };
+    elapsed
+}
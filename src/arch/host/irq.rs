@@ -0,0 +1,98 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Interrupts as signals
+//!
+//! Every interrupt source has a signal, its handler is the interrupt
+//! handler. Handlers run with all interrupt signals blocked, so they don't
+//! nest, just like on the single priority level the kernel uses elsewhere.
+//! The signal frame is on the stack of the interrupted thread, so a handler
+//! may switch to another thread.
+
+#![deny(warnings)]
+
+use core::mem;
+use core::ptr;
+
+use libc::{c_int, sigset_t};
+
+use super::{sim, timer};
+use crate::thread;
+
+/// The signal of the tick timer
+pub const SIGNAL_TIMER: c_int = libc::SIGALRM;
+/// The signal of interrupts injected by a simulation
+pub const SIGNAL_SIM: c_int = libc::SIGUSR1;
+
+/// The signals that act as interrupts
+fn irq_signals() -> sigset_t {
+    unsafe {
+        let mut set: sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, SIGNAL_TIMER);
+        libc::sigaddset(&mut set, SIGNAL_SIM);
+        set
+    }
+}
+
+extern "C" fn irq_handler(signal: c_int) {
+    match signal {
+        SIGNAL_TIMER => timer::host_timer_irq(),
+        SIGNAL_SIM => sim::sim_irq(),
+        _ => {}
+    }
+    thread::thread_preempt();
+}
+
+/// Install the interrupt handlers
+pub fn irq_init() {
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = irq_handler as extern "C" fn(c_int) as usize;
+        action.sa_mask = irq_signals();
+        action.sa_flags = libc::SA_RESTART;
+        for &signal in &[SIGNAL_TIMER, SIGNAL_SIM] {
+            let ret = libc::sigaction(signal, &action, ptr::null_mut());
+            assert_eq!(ret, 0, "sigaction failed");
+        }
+    }
+}
+
+/// Block the interrupt signals, returns 1 if they already were blocked
+pub fn irq_save() -> usize {
+    unsafe {
+        let mut old: sigset_t = mem::zeroed();
+        libc::pthread_sigmask(libc::SIG_BLOCK, &irq_signals(), &mut old);
+        (libc::sigismember(&old, SIGNAL_TIMER) == 1) as usize
+    }
+}
+
+pub fn irq_restore(state: usize) {
+    if state == 0 {
+        unsafe {
+            libc::pthread_sigmask(libc::SIG_UNBLOCK, &irq_signals(), ptr::null_mut());
+        }
+    }
+}
+
+/// Wait until an interrupt is pending
+///
+/// Like WFI this doesn't run the handler, the interrupt stays pending until
+/// interrupts are enabled.
+pub fn arch_idle() {
+    if sim::sim_idle() {
+        return;
+    }
+
+    let state = irq_save();
+    unsafe {
+        let mut signal = 0;
+        libc::sigwait(&irq_signals(), &mut signal);
+        // sigwait() consumed it, make it pending again
+        libc::pthread_kill(libc::pthread_self(), signal);
+    }
+    irq_restore(state);
+}
@@ -0,0 +1,60 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Hosted port, particle as a Linux process
+//!
+//! The kernel runs on one thread of the process. Interrupts are signals
+//! delivered to that thread, disabling interrupts blocks them. The tick
+//! comes from a POSIX timer, contexts are ucontexts on stacks the kernel
+//! provides and stdout is the console. This lets the kernel run under
+//! `cargo test` (see scripts/host-test.sh), on the host clock or on the
+//! virtual one of `sim`.
+
+pub mod sim;
+
+mod irq;
+mod switch;
+mod timer;
+
+use std::io::Write;
+
+pub use self::irq::{arch_idle, irq_restore, irq_save};
+pub use self::switch::{arch_context_switch, arch_init_context};
+
+#[cfg(feature = "tickless")]
+pub use self::timer::host_timer_sleep as timer_sleep;
+
+pub const PAGE_SIZE: u32 = 4096;
+pub const PAGE_SIZE_SHIFT: u32 = 12;
+
+/// Nothing to do, the signal handler calls `thread::thread_preempt()` after
+/// every interrupt
+pub fn arch_request_preempt() {}
+
+/// No stack guard, overflows are caught by the canary check when a thread
+/// is switched out
+pub const STACK_GUARD_SIZE: usize = 0;
+
+pub fn arch_stack_guard(_base: usize) {}
+
+pub fn arch_early_init() {
+    irq::irq_init();
+}
+
+/// Start the periodic kernel tick
+pub fn timer_init(tick_hz: u32) {
+    timer::host_timer_init(tick_hz);
+}
+
+pub fn console_write_str(s: &str) {
+    let _ = std::io::stdout().write_all(s.as_bytes());
+}
+
+/// Exit the process, with status 0 if the run succeeded
+pub fn arch_exit(success: bool) -> ! {
+    let _ = std::io::stdout().flush();
+    std::process::exit(if success { 0 } else { 1 })
+}
@@ -0,0 +1,346 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Deterministic simulated time
+//!
+//! While a simulation runs, the host port ignores the host clock. Virtual
+//! time only moves when the test calls `sim_advance()` or when the kernel
+//! goes idle, which jumps straight to the next event. Events are the tick
+//! timer and interrupts injected with `sim_inject()`. Each is delivered as
+//! its signal at exactly its virtual time, so it behaves like a real
+//! interrupt: it runs right away or stays pending while interrupts are
+//! disabled.
+//!
+//! With a jitter, every event is delivered late by a pseudo-random amount
+//! drawn from the seed, so a run replays identically from its seed.
+//!
+//! Only test builds can start a simulation.
+
+#![deny(warnings)]
+
+use super::irq::{irq_restore, irq_save, SIGNAL_SIM, SIGNAL_TIMER};
+use crate::kernel::irq::IrqLock;
+
+/// Handler of an injected interrupt, called with the virtual time in
+/// nanoseconds and the argument given to `sim_inject()`
+pub type SimHandler = fn(now: u64, arg: usize);
+
+struct Event {
+    /// Virtual delivery time, jitter included
+    at: u64,
+    handler: SimHandler,
+    arg: usize,
+}
+
+struct Sim {
+    active: bool,
+    /// Virtual time in nanoseconds
+    now: u64,
+    /// xorshift64* state
+    rng: u64,
+    /// Upper bound of the delivery latency added to every event
+    jitter: u64,
+    /// When the tick timer fires next
+    timer: Option<u64>,
+    /// Injected interrupts, in the order they were injected
+    events: Vec<Event>,
+    /// Injected interrupts that were delivered and wait for their handler
+    due: Vec<(SimHandler, usize)>,
+}
+
+enum Next {
+    Timer,
+    Injected(usize),
+}
+
+impl Sim {
+    fn random(&mut self) -> u64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn latency(&mut self) -> u64 {
+        if self.jitter == 0 {
+            0
+        } else {
+            self.random() % (self.jitter + 1)
+        }
+    }
+
+    /// The earliest event, the timer wins ties, then the earlier injection
+    fn next(&self) -> Option<(u64, Next)> {
+        let mut next = self.timer.map(|at| (at, Next::Timer));
+        for (i, event) in self.events.iter().enumerate() {
+            match next {
+                Some((at, _)) if at <= event.at => {}
+                _ => next = Some((event.at, Next::Injected(i))),
+            }
+        }
+        next
+    }
+}
+
+static SIM: IrqLock<Sim> = IrqLock::new(Sim {
+    active: false,
+    now: 0,
+    rng: 0,
+    jitter: 0,
+    timer: None,
+    events: Vec::new(),
+    due: Vec::new(),
+});
+
+/// Ends the simulation when dropped
+#[cfg(test)]
+pub struct SimGuard {
+    _private: (),
+}
+
+#[cfg(test)]
+impl Drop for SimGuard {
+    fn drop(&mut self) {
+        let mut sim = SIM.lock();
+        sim.active = false;
+        sim.timer = None;
+        sim.events.clear();
+        sim.due.clear();
+    }
+}
+
+/// Switch the host port to virtual time, starting at 0
+///
+/// Every event is delivered up to `jitter` nanoseconds late, the latencies
+/// are drawn from `seed`. Call this before `timer_init()`, which then runs
+/// the tick on virtual time. Once the guard is dropped, `timer_init()` has
+/// to be called again to get back to the host clock.
+#[cfg(test)]
+pub fn sim_start(seed: u64, jitter: u64) -> SimGuard {
+    let mut sim = SIM.lock();
+    sim.active = true;
+    sim.now = 0;
+    // xorshift gets stuck at 0
+    sim.rng = seed ^ 0x9e37_79b9_7f4a_7c15;
+    sim.jitter = jitter;
+    sim.timer = None;
+    sim.events.clear();
+    sim.due.clear();
+
+    SimGuard { _private: () }
+}
+
+/// Virtual time in nanoseconds
+#[cfg(test)]
+pub fn sim_now() -> u64 {
+    SIM.lock().now
+}
+
+/// Raise a simulated interrupt at virtual time `at` (plus jitter), which
+/// runs `handler(now, arg)`
+#[cfg(test)]
+pub fn sim_inject(at: u64, handler: SimHandler, arg: usize) {
+    let mut sim = SIM.lock();
+    assert!(sim.active, "no simulation running");
+
+    let at = at.max(sim.now) + sim.latency();
+    sim.events.push(Event { at, handler, arg });
+}
+
+/// Move virtual time forward by `ns`, delivering every event on the way
+#[cfg(test)]
+pub fn sim_advance(ns: u64) {
+    let target = SIM.lock().now + ns;
+    advance_to(target);
+}
+
+fn advance_to(target: u64) {
+    loop {
+        let signal = {
+            let mut sim = SIM.lock();
+            match sim.next() {
+                Some((at, next)) if at <= target => {
+                    sim.now = sim.now.max(at);
+                    match next {
+                        Next::Timer => {
+                            sim.timer = None;
+                            SIGNAL_TIMER
+                        }
+                        Next::Injected(i) => {
+                            let event = sim.events.remove(i);
+                            sim.due.push((event.handler, event.arg));
+                            SIGNAL_SIM
+                        }
+                    }
+                }
+                _ => {
+                    sim.now = sim.now.max(target);
+                    return;
+                }
+            }
+        };
+
+        // Runs the handler right here unless interrupts are disabled
+        unsafe {
+            libc::pthread_kill(libc::pthread_self(), signal);
+        }
+    }
+}
+
+/// Virtual time, if a simulation runs
+pub(super) fn sim_clock() -> Option<u64> {
+    let sim = SIM.lock();
+    if sim.active {
+        Some(sim.now)
+    } else {
+        None
+    }
+}
+
+/// Fire the tick timer at `deadline` if a simulation runs, returns whether
+/// it did
+pub(super) fn sim_arm_timer(deadline: u64) -> bool {
+    let mut sim = SIM.lock();
+    if sim.active {
+        let latency = sim.latency();
+        sim.timer = Some(deadline.max(sim.now) + latency);
+    }
+    sim.active
+}
+
+/// All threads are idle: skip to the next event and leave it pending
+///
+/// Returns false if no simulation runs.
+pub(super) fn sim_idle() -> bool {
+    let state = irq_save();
+
+    let next = {
+        let sim = SIM.lock();
+        if sim.active {
+            Some(sim.next().map(|(at, _)| at))
+        } else {
+            None
+        }
+    };
+
+    let active = match next {
+        Some(Some(at)) => {
+            advance_to(at);
+            true
+        }
+        Some(None) => panic!("simulation idle with nothing scheduled"),
+        None => false,
+    };
+
+    irq_restore(state);
+    active
+}
+
+/// Run the handlers of the injected interrupts that were delivered
+pub(super) fn sim_irq() {
+    loop {
+        let (now, due) = {
+            let mut sim = SIM.lock();
+            if sim.due.is_empty() {
+                return;
+            }
+            let due = sim.due.remove(0);
+            (sim.now, due)
+        };
+
+        let (handler, arg) = due;
+        handler(now, arg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::mem;
+
+    use super::*;
+    use crate::arch;
+    use crate::kernel::idle;
+    use crate::kernel::timer::{self, Timer};
+
+    const MS: u64 = 1_000_000;
+
+    static LOG: IrqLock<Vec<(u64, usize)>> = IrqLock::new(Vec::new());
+
+    fn record(now: u64, arg: usize) {
+        LOG.lock().push((now, arg));
+    }
+
+    fn take_log() -> Vec<(u64, usize)> {
+        mem::replace(&mut *LOG.lock(), Vec::new())
+    }
+
+    fn start(seed: u64, jitter: u64) -> SimGuard {
+        let guard = sim_start(seed, jitter);
+        arch::arch_early_init();
+        timer::timer_init();
+        take_log();
+        guard
+    }
+
+    fn fired(now: u64, _arg: usize) {
+        record(now, 0);
+    }
+
+    #[test]
+    fn timers_run_on_virtual_time() {
+        let _sim = start(1, 0);
+
+        let mut t = Timer::new();
+        let start = timer::current_ticks();
+        unsafe { t.set_oneshot(timer::ms_to_ticks(250), fired, 0) };
+
+        while LOG.lock().is_empty() {
+            idle::idle();
+        }
+
+        assert_eq!(sim_now(), 250 * MS);
+        assert_eq!(take_log(), vec![(start + 250, 0)]);
+    }
+
+    #[test]
+    fn injected_interrupts_are_delivered_in_order() {
+        let _sim = start(1, 0);
+
+        sim_inject(30 * MS, record, 3);
+        sim_inject(10 * MS, record, 1);
+        sim_inject(20 * MS, record, 2);
+
+        sim_advance(25 * MS);
+        assert_eq!(take_log(), vec![(10 * MS, 1), (20 * MS, 2)]);
+
+        // Disabled interrupts hold the last one back
+        let state = arch::irq_save();
+        sim_advance(10 * MS);
+        assert!(LOG.lock().is_empty());
+        arch::irq_restore(state);
+
+        assert_eq!(take_log(), vec![(35 * MS, 3)]);
+        assert_eq!(sim_now(), 35 * MS);
+    }
+
+    fn jittered_run(seed: u64) -> Vec<(u64, usize)> {
+        let _sim = start(seed, MS / 2);
+
+        for i in 0..20 {
+            sim_inject(i as u64 * MS, record, i);
+        }
+        sim_advance(25 * MS);
+        take_log()
+    }
+
+    #[test]
+    fn runs_replay_from_their_seed() {
+        let first = jittered_run(42);
+        assert_eq!(first.len(), 20);
+        assert_eq!(first, jittered_run(42));
+        assert_ne!(first, jittered_run(43));
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Context switch on ucontext
+//!
+//! The "stack pointer" of a suspended context is the address of its
+//! `ucontext_t`, which lives on its own stack: in the frame of
+//! `arch_context_switch()` once it ran, at the top of the stack before.
+
+#![deny(warnings)]
+
+use core::mem::{self, size_of};
+use core::ptr;
+
+use libc::ucontext_t;
+
+use super::irq;
+
+/// The context a new stack starts with
+#[repr(C)]
+struct StartFrame {
+    context: ucontext_t,
+    entry: extern "C" fn(usize) -> !,
+    arg: usize,
+}
+
+/// makecontext() only passes ints, the frame address comes in two halves
+extern "C" fn arch_context_start(hi: u32, lo: u32) {
+    let frame = ((hi as usize) << 32 | lo as usize) as *const StartFrame;
+    unsafe { ((*frame).entry)((*frame).arg) }
+}
+
+/// Save the current context, store its stack pointer to `old_sp` and
+/// resume the context saved at `new_sp`
+pub unsafe fn arch_context_switch(old_sp: *mut usize, new_sp: usize) {
+    let mut context: ucontext_t = mem::zeroed();
+    *old_sp = &mut context as *mut ucontext_t as usize;
+    libc::swapcontext(&mut context, new_sp as *const ucontext_t);
+}
+
+/// Build the initial frame of a context that calls `entry(arg)` the first
+/// time it is switched to. Returns the stack pointer to hand to
+/// `arch_context_switch()`.
+///
+/// # Unsafety
+///
+/// `[stack_base, stack_top)` must be a stack large enough for `entry`,
+/// which stays valid for as long as the context exists.
+pub unsafe fn arch_init_context(
+    stack_base: usize,
+    stack_top: usize,
+    entry: extern "C" fn(usize) -> !,
+    arg: usize,
+) -> usize {
+    let sp = (stack_top - size_of::<StartFrame>()) & !15;
+    let frame = sp as *mut StartFrame;
+    debug_assert!(sp > stack_base);
+
+    ptr::write_bytes(frame, 0, 1);
+    (*frame).entry = entry;
+    (*frame).arg = arg;
+
+    let context = &mut (*frame).context;
+    libc::getcontext(context);
+    // It may be first switched to from a signal handler, start with
+    // interrupts enabled anyway
+    for signal in &[irq::SIGNAL_TIMER, irq::SIGNAL_SIM] {
+        libc::sigdelset(&mut context.uc_sigmask, *signal);
+    }
+    context.uc_stack.ss_sp = stack_base as *mut libc::c_void;
+    context.uc_stack.ss_size = sp - stack_base;
+    context.uc_link = ptr::null_mut();
+
+    let start: extern "C" fn(u32, u32) = arch_context_start;
+    libc::makecontext(
+        context,
+        mem::transmute::<extern "C" fn(u32, u32), extern "C" fn()>(start),
+        2,
+        (sp >> 32) as u32,
+        sp as u32,
+    );
+
+    sp
+}
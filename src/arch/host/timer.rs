@@ -0,0 +1,164 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Tick timer on a POSIX timer
+//!
+//! A CLOCK_MONOTONIC timer signals the kernel thread at absolute deadlines.
+//! Tick `n` is due at `base + n * NSEC_PER_SEC / tick_hz`, a late signal
+//! (the host may not schedule the process for a while) catches up on all
+//! the ticks that passed.
+
+#![deny(warnings)]
+
+use core::mem;
+use core::ptr;
+
+use super::irq::SIGNAL_TIMER;
+use super::sim;
+use crate::kernel::irq::IrqLock;
+use crate::kernel::timer;
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+struct TickState {
+    timer: Option<libc::timer_t>,
+    /// CLOCK_MONOTONIC at tick 0, in nanoseconds
+    base: u64,
+    tick_hz: u64,
+    /// The last tick accounted for
+    tick: u64,
+}
+
+// The timer handle is only used with the lock held
+unsafe impl Send for TickState {}
+
+impl TickState {
+    /// Nanoseconds at which tick `n` is due
+    fn deadline(&self, n: u64) -> u64 {
+        self.base + n * NSEC_PER_SEC / self.tick_hz
+    }
+
+    /// The last tick due at or before `now`
+    fn tick_at(&self, now: u64) -> u64 {
+        ((now - self.base + 1) * self.tick_hz - 1) / NSEC_PER_SEC
+    }
+
+    /// Signal the kernel thread at `deadline`, right away if it passed
+    fn arm(&self, deadline: u64) {
+        if sim::sim_arm_timer(deadline) {
+            return;
+        }
+
+        let spec = libc::itimerspec {
+            it_interval: libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+            it_value: libc::timespec {
+                tv_sec: (deadline / NSEC_PER_SEC) as libc::time_t,
+                tv_nsec: (deadline % NSEC_PER_SEC) as libc::c_long,
+            },
+        };
+        if let Some(timer) = self.timer {
+            unsafe { libc::timer_settime(timer, libc::TIMER_ABSTIME, &spec, ptr::null_mut()) };
+        }
+    }
+}
+
+static TICKS: IrqLock<TickState> = IrqLock::new(TickState {
+    timer: None,
+    base: 0,
+    tick_hz: 1,
+    tick: 0,
+});
+
+/// The host clock, or virtual time during a simulation
+fn monotonic_ns() -> u64 {
+    if let Some(now) = sim::sim_clock() {
+        return now;
+    }
+
+    unsafe {
+        let mut ts: libc::timespec = mem::zeroed();
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts);
+        ts.tv_sec as u64 * NSEC_PER_SEC + ts.tv_nsec as u64
+    }
+}
+
+/// Start the timer, firing `tick_hz` times per second at the calling thread
+pub fn host_timer_init(tick_hz: u32) {
+    assert!(tick_hz > 0);
+
+    let mut state = TICKS.lock();
+    // The kernel may have moved to another thread (every test runs on its
+    // own) or into a simulation
+    if let Some(timer) = state.timer.take() {
+        unsafe { libc::timer_delete(timer) };
+    }
+    if sim::sim_clock().is_none() {
+        unsafe {
+            let mut event: libc::sigevent = mem::zeroed();
+            event.sigev_notify = libc::SIGEV_THREAD_ID;
+            event.sigev_signo = SIGNAL_TIMER;
+            event.sigev_notify_thread_id = libc::syscall(libc::SYS_gettid) as libc::c_int;
+
+            let mut timer: libc::timer_t = mem::zeroed();
+            let ret = libc::timer_create(libc::CLOCK_MONOTONIC, &mut event, &mut timer);
+            assert_eq!(ret, 0, "timer_create failed");
+            state.timer = Some(timer);
+        }
+    }
+
+    state.base = monotonic_ns();
+    state.tick_hz = tick_hz as u64;
+    state.tick = 0;
+    state.arm(state.deadline(1));
+}
+
+pub fn host_timer_irq() {
+    let ticks = {
+        let mut state = TICKS.lock();
+        let now = state.tick_at(monotonic_ns());
+        let ticks = now - state.tick;
+        state.tick = now;
+        state.arm(state.deadline(now + 1));
+        ticks
+    };
+
+    if ticks > 0 {
+        timer::timer_advance(ticks);
+    }
+}
+
+/// Sleep for up to `ticks` kernel ticks or until an interrupt, whichever
+/// comes first. Called with interrupts disabled. Returns the ticks that
+/// passed, the regular tick deadline is back in place afterwards.
+///
+/// The timer signal stays pending once the deadline is hit, so it is
+/// handled when the caller re-enables interrupts and accounts for the last
+/// tick itself, which is why it isn't counted here.
+#[cfg(feature = "tickless")]
+pub fn host_timer_sleep(ticks: u64) -> u64 {
+    // Keep the deadline arithmetic far away from overflowing
+    const MAX_SLEEP: u64 = 1 << 32;
+
+    let mut state = TICKS.lock();
+    let target = state.tick + ticks.min(MAX_SLEEP);
+    state.arm(state.deadline(target));
+    super::arch_idle();
+
+    let now = state.tick_at(monotonic_ns());
+    if now == state.tick {
+        state.arm(state.deadline(now + 1));
+        return 0;
+    }
+
+    // Leave the tick at `now` to the pending signal
+    let elapsed = now - 1 - state.tick;
+    state.tick = now - 1;
+    state.arm(state.deadline(now));
+    elapsed
+}
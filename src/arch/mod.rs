@@ -9,3 +9,21 @@ pub mod arm;
 
 #[cfg(target_arch = "arm")]
 pub use self::arm::*;
+
+#[cfg(target_arch = "aarch64")]
+pub mod arm64;
+
+#[cfg(target_arch = "aarch64")]
+pub use self::arm64::*;
+
+#[cfg(target_arch = "riscv32")]
+pub mod riscv;
+
+#[cfg(target_arch = "riscv32")]
+pub use self::riscv::*;
+
+#[cfg(host)]
+pub mod host;
+
+#[cfg(host)]
+pub use self::host::*;
@@ -0,0 +1,138 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! CLINT timer driver
+//!
+//! mtime is a free running 64 bit counter and mtimecmp raises the machine
+//! timer interrupt once mtime reaches it. Tick `n` is due at
+//! `base + n * MTIME_HZ / tick_hz`, computed from the tick number rather than
+//! by adding a rounded period, so a timebase that isn't a multiple of the
+//! tick rate (the 32768 Hz clock of the sifive_e) doesn't drift.
+
+#![deny(warnings)]
+
+use core::ptr;
+
+use super::csr;
+use crate::kernel::irq::IrqLock;
+use crate::kernel::timer;
+
+/// Frequency of mtime
+#[cfg(not(feature = "sifive-e"))]
+pub const MTIME_HZ: u64 = 10_000_000;
+#[cfg(feature = "sifive-e")]
+pub const MTIME_HZ: u64 = 32_768;
+
+const CLINT_BASE: usize = 0x0200_0000;
+/// mtimecmp of hart 0
+const CLINT_MTIMECMP: *mut u32 = (CLINT_BASE + 0x4000) as *mut u32;
+const CLINT_MTIME: *mut u32 = (CLINT_BASE + 0xbff8) as *mut u32;
+
+struct TickState {
+    /// mtime at tick 0
+    base: u64,
+    tick_hz: u64,
+    /// The last tick accounted for
+    tick: u64,
+}
+
+impl TickState {
+    /// mtime at which tick `n` is due
+    fn deadline(&self, n: u64) -> u64 {
+        self.base + n * MTIME_HZ / self.tick_hz
+    }
+
+    /// The last tick due at or before `now`
+    fn tick_at(&self, now: u64) -> u64 {
+        ((now - self.base + 1) * self.tick_hz - 1) / MTIME_HZ
+    }
+}
+
+static TICKS: IrqLock<TickState> = IrqLock::new(TickState {
+    base: 0,
+    tick_hz: 1,
+    tick: 0,
+});
+
+fn mtime() -> u64 {
+    // The two halves can't be read at once, retry if the low word wrapped
+    unsafe {
+        loop {
+            let hi = ptr::read_volatile(CLINT_MTIME.offset(1));
+            let lo = ptr::read_volatile(CLINT_MTIME);
+            if hi == ptr::read_volatile(CLINT_MTIME.offset(1)) {
+                return ((hi as u64) << 32) | lo as u64;
+            }
+        }
+    }
+}
+
+fn set_mtimecmp(cmp: u64) {
+    // Raise the low word first, so no intermediate value fires early
+    unsafe {
+        ptr::write_volatile(CLINT_MTIMECMP, !0);
+        ptr::write_volatile(CLINT_MTIMECMP.offset(1), (cmp >> 32) as u32);
+        ptr::write_volatile(CLINT_MTIMECMP, cmp as u32);
+    }
+}
+
+/// Start the timer, firing `tick_hz` times per second
+pub fn clint_timer_init(tick_hz: u32) {
+    assert!(tick_hz > 0 && tick_hz as u64 <= MTIME_HZ);
+
+    let mut state = TICKS.lock();
+    state.base = mtime();
+    state.tick_hz = tick_hz as u64;
+    state.tick = 0;
+    set_mtimecmp(state.deadline(1));
+
+    unsafe { csr::riscv_set_mie(csr::MIE_MTIE) };
+}
+
+pub fn clint_timer_irq() {
+    let ticks = {
+        let mut state = TICKS.lock();
+        let now = state.tick_at(mtime());
+        let ticks = now - state.tick;
+        state.tick = now;
+        set_mtimecmp(state.deadline(now + 1));
+        ticks
+    };
+
+    if ticks > 0 {
+        timer::timer_advance(ticks);
+    }
+}
+
+/// Sleep for up to `ticks` kernel ticks or until an interrupt, whichever
+/// comes first. Called with interrupts disabled. Returns the ticks that
+/// passed, the regular tick deadline is back in place afterwards.
+///
+/// The timer interrupt stays pending once the deadline is hit, so it runs
+/// when the caller re-enables interrupts and accounts for the last tick
+/// itself, which is why it isn't counted here.
+#[cfg(feature = "tickless")]
+pub fn clint_timer_sleep(ticks: u64) -> u64 {
+    // Keep the deadline arithmetic far away from overflowing
+    const MAX_SLEEP: u64 = 1 << 32;
+
+    let mut state = TICKS.lock();
+    let target = state.tick + ticks.min(MAX_SLEEP);
+    set_mtimecmp(state.deadline(target));
+    unsafe { csr::riscv_wfi() };
+
+    let now = state.tick_at(mtime());
+    if now == state.tick {
+        set_mtimecmp(state.deadline(now + 1));
+        return 0;
+    }
+
+    // Leave the tick at `now` to the pending interrupt
+    let elapsed = now - 1 - state.tick;
+    state.tick = now - 1;
+    set_mtimecmp(state.deadline(now));
+    elapsed
+}
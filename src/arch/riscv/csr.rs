@@ -0,0 +1,69 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Machine mode CSR accessors
+//!
+//! One small assembly function per access, so the rest of the port can stay
+//! in plain Rust.
+
+#![deny(warnings)]
+
+/// mstatus.MIE, the global machine interrupt enable
+pub const MSTATUS_MIE: usize = 1 << 3;
+
+/// mie.MTIE, machine timer interrupts
+pub const MIE_MTIE: usize = 1 << 7;
+/// mie.MEIE, machine external interrupts (the PLIC)
+pub const MIE_MEIE: usize = 1 << 11;
+
+global_asm!(
+    r#"
+    .section .text.riscv_csr, "ax", %progbits
+
+    .global riscv_irq_save
+riscv_irq_save:
+    csrrci a0, mstatus, 8
+    ret
+
+    .global riscv_irq_restore
+riscv_irq_restore:
+    andi a0, a0, 8
+    csrs mstatus, a0
+    ret
+
+    .global riscv_irq_enable
+riscv_irq_enable:
+    csrsi mstatus, 8
+    ret
+
+    .global riscv_wfi
+riscv_wfi:
+    wfi
+    ret
+
+    .global riscv_set_mie
+riscv_set_mie:
+    csrs mie, a0
+    ret
+
+    .global riscv_read_mtval
+riscv_read_mtval:
+    csrr a0, mtval
+    ret
+"#
+);
+
+extern "C" {
+    /// Clear mstatus.MIE, returns the previous mstatus
+    pub fn riscv_irq_save() -> usize;
+    /// Set mstatus.MIE again if it was set in `mstatus`
+    pub fn riscv_irq_restore(mstatus: usize);
+    pub fn riscv_irq_enable();
+    pub fn riscv_wfi();
+    /// Enable the interrupt sources in `bits`
+    pub fn riscv_set_mie(bits: usize);
+    pub fn riscv_read_mtval() -> usize;
+}
@@ -0,0 +1,73 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! RV32IMAC in machine mode, on QEMU virt or sifive_e
+//!
+//! Both boards share the CLINT and PLIC addresses. The console is
+//! semihosting, so they only differ in their memory map (see build.rs) and
+//! the mtime frequency.
+
+pub mod start;
+
+pub mod plic;
+pub mod semihosting;
+
+mod clint;
+mod csr;
+mod switch;
+mod trap;
+
+pub use self::switch::{arch_context_switch, arch_init_context};
+
+#[cfg(feature = "tickless")]
+pub use self::clint::clint_timer_sleep as timer_sleep;
+
+pub const PAGE_SIZE: u32 = 4096;
+pub const PAGE_SIZE_SHIFT: u32 = 12;
+
+pub fn arch_early_init() {
+    plic::plic_init();
+    unsafe { csr::riscv_irq_enable() };
+}
+
+/// Start the periodic kernel tick
+pub fn timer_init(tick_hz: u32) {
+    clint::clint_timer_init(tick_hz);
+}
+
+/// Disable interrupts, returns the previous state for `irq_restore()`
+pub fn irq_save() -> usize {
+    unsafe { csr::riscv_irq_save() }
+}
+
+pub fn irq_restore(state: usize) {
+    unsafe { csr::riscv_irq_restore(state) }
+}
+
+/// Nothing to do, the trap handler calls `thread::thread_preempt()` after
+/// every interrupt
+pub fn arch_request_preempt() {}
+
+/// No stack guard, overflows are caught by the canary check when a thread
+/// is switched out
+pub const STACK_GUARD_SIZE: usize = 0;
+
+pub fn arch_stack_guard(_base: usize) {}
+
+/// Wait for the next interrupt
+pub fn arch_idle() {
+    unsafe { csr::riscv_wfi() }
+}
+
+/// Write to the semihosting console of QEMU
+pub fn console_write_str(s: &str) {
+    semihosting::sys_write_str(s);
+}
+
+/// Leave QEMU with an exit status telling whether the run succeeded
+pub fn arch_exit(success: bool) -> ! {
+    semihosting::sys_exit(success)
+}
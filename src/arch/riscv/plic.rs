@@ -0,0 +1,76 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Platform-Level Interrupt Controller
+//!
+//! QEMU virt and sifive_e have the PLIC at the same address, with machine
+//! mode of hart 0 as context 0. Every source gets the same priority and the
+//! threshold stays at 0, so any enabled source interrupts.
+
+#![deny(warnings)]
+
+use core::ptr;
+
+use super::csr;
+use crate::kernel::irq::IrqLock;
+
+const PLIC_BASE: usize = 0x0c00_0000;
+
+const PLIC_PRIORITY: usize = PLIC_BASE;
+/// Enable bits of context 0
+const PLIC_ENABLE: usize = PLIC_BASE + 0x2000;
+const PLIC_THRESHOLD: *mut u32 = (PLIC_BASE + 0x20_0000) as *mut u32;
+const PLIC_CLAIM: *mut u32 = (PLIC_BASE + 0x20_0004) as *mut u32;
+
+/// The interrupt sources particle can route to a handler, source 0 means
+/// "no interrupt"
+pub const MAX_IRQS: usize = 128;
+
+static HANDLERS: IrqLock<[Option<fn()>; MAX_IRQS]> = IrqLock::new([None; MAX_IRQS]);
+
+/// Mask every source and enable external interrupts on the hart
+pub fn plic_init() {
+    unsafe {
+        for word in 0..MAX_IRQS / 32 {
+            ptr::write_volatile((PLIC_ENABLE as *mut u32).add(word), 0);
+        }
+        ptr::write_volatile(PLIC_THRESHOLD, 0);
+
+        csr::riscv_set_mie(csr::MIE_MEIE);
+    }
+}
+
+/// Route source `irq` to `handler` and unmask it
+pub fn plic_register(irq: u32, handler: fn()) {
+    assert!(irq > 0 && (irq as usize) < MAX_IRQS);
+
+    HANDLERS.lock()[irq as usize] = Some(handler);
+
+    unsafe {
+        ptr::write_volatile((PLIC_PRIORITY as *mut u32).add(irq as usize), 1);
+        let enable = (PLIC_ENABLE as *mut u32).add(irq as usize / 32);
+        ptr::write_volatile(enable, ptr::read_volatile(enable) | 1 << (irq % 32));
+    }
+}
+
+/// Claim the pending source, run its handler and complete it
+pub fn plic_handle_irq() {
+    let irq = unsafe { ptr::read_volatile(PLIC_CLAIM) };
+    if irq == 0 {
+        return;
+    }
+
+    let handler = if (irq as usize) < MAX_IRQS {
+        HANDLERS.lock()[irq as usize]
+    } else {
+        None
+    };
+    if let Some(handler) = handler {
+        handler();
+    }
+
+    unsafe { ptr::write_volatile(PLIC_CLAIM, irq) };
+}
@@ -0,0 +1,76 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! The few semihosting calls particle needs on RISC-V
+//!
+//! A call is the `slli; ebreak; srai` sequence with the operation in a0 and
+//! its argument in a1. The three instructions must be uncompressed and on
+//! the same page. QEMU needs `-semihosting-config enable=on`.
+
+#![deny(warnings)]
+
+#[cfg(feature = "ktest")]
+const SYS_CLOCK: usize = 0x10;
+const SYS_WRITE0: usize = 0x04;
+const SYS_EXIT: usize = 0x18;
+
+const ADP_STOPPED_RUN_TIME_ERROR_UNKNOWN: usize = 0x20023;
+const ADP_STOPPED_APPLICATION_EXIT: usize = 0x20026;
+
+global_asm!(
+    r#"
+    .section .text.riscv_semihosting, "ax", %progbits
+    .option push
+    .option norvc
+    .balign 16
+    .global riscv_semihosting
+riscv_semihosting:
+    slli zero, zero, 0x1f
+    ebreak
+    srai zero, zero, 7
+    ret
+    .option pop
+"#
+);
+
+extern "C" {
+    fn riscv_semihosting(op: usize, arg: usize) -> usize;
+}
+
+/// Write `s` to the host console
+pub fn sys_write_str(s: &str) {
+    // SYS_WRITE0 takes a NUL terminated string, go through a buffer
+    let mut buf = [0u8; 64];
+    for chunk in s.as_bytes().chunks(buf.len() - 1) {
+        buf[..chunk.len()].copy_from_slice(chunk);
+        buf[chunk.len()] = 0;
+        unsafe {
+            riscv_semihosting(SYS_WRITE0, buf.as_ptr() as usize);
+        }
+    }
+}
+
+/// Centiseconds since the program started, as the host sees it
+#[cfg(feature = "ktest")]
+pub fn sys_clock() -> u64 {
+    unsafe { riscv_semihosting(SYS_CLOCK, 0) as u64 }
+}
+
+/// Stop QEMU, exit status 0 on `success` and 1 otherwise
+///
+/// On 32 bit targets the argument is the stop reason itself.
+pub fn sys_exit(success: bool) -> ! {
+    let reason = if success {
+        ADP_STOPPED_APPLICATION_EXIT
+    } else {
+        ADP_STOPPED_RUN_TIME_ERROR_UNKNOWN
+    };
+    unsafe {
+        riscv_semihosting(SYS_EXIT, reason);
+    }
+
+    loop {}
+}
@@ -0,0 +1,59 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Boot code for RV32
+//!
+//! - `_start`. The first instruction of the image. Harts other than 0 are
+//! parked, hart 0 sets up gp, its stack and the trap vector and continues in
+//! `riscv_reset`, which initializes RAM and calls kmain().
+
+#![deny(warnings)]
+
+global_asm!(
+    r#"
+    .section .text.init, "ax", %progbits
+    .global _start
+_start:
+    .option push
+    .option norelax
+    la gp, __global_pointer$
+    .option pop
+
+    csrr t0, mhartid
+    bnez t0, 2f
+
+    la sp, _stack_start
+
+    la t0, riscv_trap_entry
+    csrw mtvec, t0
+
+    call riscv_reset
+2:
+    wfi
+    j 2b
+"#
+);
+
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn riscv_reset() -> ! {
+    extern "C" {
+        // These symbols come from `link-riscv.x`
+        static mut __sbss: u32;
+        static mut __ebss: u32;
+
+        static mut __sdata: u32;
+        static mut __edata: u32;
+
+        static __sidata: u32;
+    }
+
+    // Initialize RAM
+    rrt0::zero_bss(&mut __sbss, &mut __ebss);
+    rrt0::init_data(&mut __sdata, &mut __edata, &__sidata);
+
+    crate::kmain();
+}
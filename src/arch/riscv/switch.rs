@@ -0,0 +1,117 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Cooperative context switch
+//!
+//! Same contract as on the ARM ports: `arch_context_switch()` saves the
+//! callee saved registers (ra and s0-s11) on the current stack, stores the
+//! stack pointer and resumes the context saved on the other stack.
+
+#![deny(warnings)]
+
+use core::mem::size_of;
+
+/// The registers saved by `arch_context_switch()`, lowest address first
+#[repr(C)]
+struct SwitchFrame {
+    ra: usize,
+    s0_s11: [usize; 12],
+    _pad: [usize; 3],
+}
+
+global_asm!(
+    r#"
+    .section .text.arch_context_switch, "ax", %progbits
+    .global arch_context_switch
+    .type arch_context_switch,%function
+arch_context_switch:
+    addi sp, sp, -64
+    sw ra, 0(sp)
+    sw s0, 4(sp)
+    sw s1, 8(sp)
+    sw s2, 12(sp)
+    sw s3, 16(sp)
+    sw s4, 20(sp)
+    sw s5, 24(sp)
+    sw s6, 28(sp)
+    sw s7, 32(sp)
+    sw s8, 36(sp)
+    sw s9, 40(sp)
+    sw s10, 44(sp)
+    sw s11, 48(sp)
+    sw sp, 0(a0)
+    mv sp, a1
+    lw ra, 0(sp)
+    lw s0, 4(sp)
+    lw s1, 8(sp)
+    lw s2, 12(sp)
+    lw s3, 16(sp)
+    lw s4, 20(sp)
+    lw s5, 24(sp)
+    lw s6, 28(sp)
+    lw s7, 32(sp)
+    lw s8, 36(sp)
+    lw s9, 40(sp)
+    lw s10, 44(sp)
+    lw s11, 48(sp)
+    addi sp, sp, 64
+    ret
+"#
+);
+
+// A new context starts here with the argument in s0 and the entry point in
+// s1. It may be first switched to from the trap handler, so it enables
+// interrupts.
+global_asm!(
+    r#"
+    .section .text.arch_context_start, "ax", %progbits
+    .global arch_context_start
+    .type arch_context_start,%function
+arch_context_start:
+    csrsi mstatus, 8
+    mv a0, s0
+    jr s1
+"#
+);
+
+extern "C" {
+    /// Save the current context, store its stack pointer to `old_sp` and
+    /// resume the context saved at `new_sp`
+    pub fn arch_context_switch(old_sp: *mut usize, new_sp: usize);
+
+    fn arch_context_start();
+}
+
+/// Build the initial frame of a context that calls `entry(arg)` the first
+/// time it is switched to. Returns the stack pointer to hand to
+/// `arch_context_switch()`.
+///
+/// # Unsafety
+///
+/// `[stack_base, stack_top)` must be a stack large enough for `entry`,
+/// which stays valid for as long as the context exists.
+pub unsafe fn arch_init_context(
+    stack_base: usize,
+    stack_top: usize,
+    entry: extern "C" fn(usize) -> !,
+    arg: usize,
+) -> usize {
+    // The psABI wants sp 16 byte aligned
+    let sp = (stack_top & !15) - size_of::<SwitchFrame>();
+    let frame = sp as *mut SwitchFrame;
+    debug_assert!(sp > stack_base);
+
+    let mut s0_s11 = [0; 12];
+    s0_s11[0] = arg;
+    s0_s11[1] = entry as usize;
+    frame.write(SwitchFrame {
+        ra: arch_context_start as usize,
+        s0_s11,
+        _pad: [0; 3],
+    });
+
+    sp
+}
@@ -0,0 +1,115 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Machine mode trap vector
+//!
+//! mtvec points at `riscv_trap_entry` in direct mode. It saves the caller
+//! saved registers, the handler is a regular function that preserves the
+//! rest. Timer and external interrupts are dispatched to the CLINT and the
+//! PLIC, any exception is fatal for now. The frame stays on the stack of the
+//! interrupted thread, so the handler may switch to another thread when an
+//! interrupt made one ready.
+
+#![deny(warnings)]
+
+use super::{clint, csr, plic};
+use crate::thread;
+
+/// The register state saved on trap entry
+#[allow(dead_code)]
+#[repr(C)]
+pub struct TrapFrame {
+    pub ra: usize,
+    pub t0_t2: [usize; 3],
+    pub a0_a7: [usize; 8],
+    pub t3_t6: [usize; 4],
+    pub mepc: usize,
+    pub mstatus: usize,
+    _pad: [usize; 2],
+}
+
+/// mcause: the trap is an interrupt
+const MCAUSE_INTERRUPT: usize = 1 << 31;
+const IRQ_M_TIMER: usize = 7;
+const IRQ_M_EXT: usize = 11;
+
+global_asm!(
+    r#"
+    .section .text.riscv_trap_entry, "ax", %progbits
+    .balign 4
+    .global riscv_trap_entry
+riscv_trap_entry:
+    addi sp, sp, -80
+    sw ra, 0(sp)
+    sw t0, 4(sp)
+    sw t1, 8(sp)
+    sw t2, 12(sp)
+    sw a0, 16(sp)
+    sw a1, 20(sp)
+    sw a2, 24(sp)
+    sw a3, 28(sp)
+    sw a4, 32(sp)
+    sw a5, 36(sp)
+    sw a6, 40(sp)
+    sw a7, 44(sp)
+    sw t3, 48(sp)
+    sw t4, 52(sp)
+    sw t5, 56(sp)
+    sw t6, 60(sp)
+    csrr t0, mepc
+    sw t0, 64(sp)
+    csrr t0, mstatus
+    sw t0, 68(sp)
+
+    mv a0, sp
+    csrr a1, mcause
+    call riscv_trap_handler
+
+    lw t0, 64(sp)
+    csrw mepc, t0
+    lw t0, 68(sp)
+    csrw mstatus, t0
+    lw ra, 0(sp)
+    lw t0, 4(sp)
+    lw t1, 8(sp)
+    lw t2, 12(sp)
+    lw a0, 16(sp)
+    lw a1, 20(sp)
+    lw a2, 24(sp)
+    lw a3, 28(sp)
+    lw a4, 32(sp)
+    lw a5, 36(sp)
+    lw a6, 40(sp)
+    lw a7, 44(sp)
+    lw t3, 48(sp)
+    lw t4, 52(sp)
+    lw t5, 56(sp)
+    lw t6, 60(sp)
+    addi sp, sp, 80
+    mret
+"#
+);
+
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn riscv_trap_handler(frame: &mut TrapFrame, mcause: usize) {
+    if mcause & MCAUSE_INTERRUPT != 0 {
+        match mcause & !MCAUSE_INTERRUPT {
+            IRQ_M_TIMER => clint::clint_timer_irq(),
+            IRQ_M_EXT => plic::plic_handle_irq(),
+            _ => {}
+        }
+        thread::thread_preempt();
+        return;
+    }
+
+    panic!(
+        "unhandled exception: mcause {:#x} mtval {:#x} mepc {:#x}",
+        mcause,
+        csr::riscv_read_mtval(),
+        frame.mepc
+    );
+}
@@ -0,0 +1,56 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Kernel console
+//!
+//! `print!` and `println!` format into the console of the architecture,
+//! semihosting on Cortex-M and RISC-V and the PL011 UART on AArch64.
+
+#![deny(warnings)]
+
+use core::fmt::{self, Write};
+
+use crate::arch;
+use crate::kernel::irq::IrqLock;
+
+struct Console;
+
+impl Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        arch::console_write_str(s);
+        Ok(())
+    }
+}
+
+/// Serializes output, so lines from interrupt handlers don't get mixed in
+static CONSOLE: IrqLock<Console> = IrqLock::new(Console);
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    let _ = CONSOLE.lock().write_fmt(args);
+}
+
+/// Print to the kernel console
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::console::_print(format_args!($($arg)*))
+    };
+}
+
+/// Print to the kernel console, with a newline
+#[macro_export]
+macro_rules! println {
+    () => {
+        $crate::print!("\n")
+    };
+    ($fmt:expr) => {
+        $crate::print!(concat!($fmt, "\n"))
+    };
+    ($fmt:expr, $($arg:tt)*) => {
+        $crate::print!(concat!($fmt, "\n"), $($arg)*)
+    };
+}
@@ -0,0 +1,46 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+#![deny(warnings)]
+
+use crate::arch;
+
+/// Wait for something to happen
+///
+/// Without the `tickless` feature this waits for the next interrupt, which
+/// is at most one tick away. With it, the tick source is reprogrammed to
+/// fire at the next timer deadline and the clock is corrected by the time
+/// that actually passed once the core wakes up.
+pub fn idle() {
+    #[cfg(feature = "tickless")]
+    tickless_idle();
+
+    #[cfg(not(feature = "tickless"))]
+    arch::arch_idle();
+}
+
+#[cfg(feature = "tickless")]
+fn tickless_idle() {
+    use super::timer;
+
+    let state = arch::irq_save();
+
+    let now = timer::current_ticks();
+    let ticks = match timer::next_deadline() {
+        Some(deadline) if deadline <= now => 0,
+        Some(deadline) => deadline - now,
+        None => core::u64::MAX,
+    };
+
+    if ticks > 0 {
+        let elapsed = arch::timer_sleep(ticks);
+        if elapsed > 0 {
+            timer::timer_advance(elapsed);
+        }
+    }
+
+    arch::irq_restore(state);
+}
@@ -0,0 +1,30 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Passing messages between threads
+//!
+//! Threads block on the wait queues of the objects (see `kernel::wait`)
+//! while they can't send or receive, interrupt handlers only try. Timeouts
+//! are in ticks.
+
+#![deny(warnings)]
+
+/// Bounded message queues
+mod queue;
+
+pub use self::queue::{Queue, SendError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// A try or a timeout of 0 found no room for the message
+    Full,
+    /// A try or a timeout of 0 found no message
+    Empty,
+    /// The timeout passed first
+    TimedOut,
+    /// The queue was dropped while the thread waited
+    Deleted,
+}
@@ -0,0 +1,378 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Bounded message queues
+//!
+//! A `Queue<T, N>` holds up to `N` messages in a ring. Senders block on one
+//! wait queue while it is full, receivers and peekers on another while it
+//! is empty, both most urgent first. Every message sent wakes a receiver and
+//! every message received a sender. A woken thread tries again and passes
+//! the wakeup on when there is more left for the next waiter, so a peeker
+//! or a thread that got there first doesn't swallow it.
+
+#![deny(warnings)]
+
+use alloc::alloc::{alloc, handle_alloc_error, Layout};
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ptr;
+
+use super::Error;
+use crate::kernel::irq::IrqLock;
+use crate::kernel::timer;
+use crate::kernel::wait::{self, BlockedOn, WaitQueue, WakeReason};
+
+/// A message that could not be sent, and why
+#[derive(Debug, PartialEq, Eq)]
+pub struct SendError<T> {
+    pub msg: T,
+    pub error: Error,
+}
+
+struct State {
+    /// Index of the oldest message
+    head: usize,
+    len: usize,
+    /// Threads waiting for room
+    senders: WaitQueue,
+    /// Threads waiting for a message
+    receivers: WaitQueue,
+}
+
+/// A queue of up to `N` messages of type `T`
+///
+/// `new()` builds one in a static, `boxed()` one on the heap.
+pub struct Queue<T, const N: usize> {
+    state: IrqLock<State>,
+    /// The slots `state` says are in use hold messages, written and read
+    /// with it locked
+    buf: UnsafeCell<MaybeUninit<[T; N]>>,
+}
+
+unsafe impl<T: Send, const N: usize> Sync for Queue<T, { N }> {}
+unsafe impl<T: Send, const N: usize> Send for Queue<T, { N }> {}
+
+impl<T, const N: usize> Default for Queue<T, { N }> {
+    fn default() -> Queue<T, { N }> {
+        Queue::new()
+    }
+}
+
+/// Where a message goes in the queue
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum End {
+    Back,
+    Front,
+}
+
+impl<T, const N: usize> Queue<T, { N }> {
+    /// Indexing out of bounds stops the build of a queue without room
+    const HAS_ROOM: () = [()][(N == 0) as usize];
+
+    pub const fn new() -> Queue<T, { N }> {
+        let () = Self::HAS_ROOM;
+        Queue {
+            state: IrqLock::new(State::new()),
+            buf: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// A queue on the heap, built there so a large one doesn't go through
+    /// the stack
+    pub fn boxed() -> Box<Queue<T, { N }>> {
+        let () = Self::HAS_ROOM;
+        let layout = Layout::new::<Queue<T, { N }>>();
+        unsafe {
+            let queue = alloc(layout) as *mut Queue<T, { N }>;
+            if queue.is_null() {
+                handle_alloc_error(layout);
+            }
+            // The messages need no initialization
+            ptr::write(&mut (*queue).state, IrqLock::new(State::new()));
+            Box::from_raw(queue)
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    /// How many messages the queue holds
+    pub fn len(&self) -> usize {
+        self.state.lock().len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Block until there is room, and append `msg`
+    pub fn send(&self, msg: T) {
+        let sent = self.put(msg, End::Back, None);
+        debug_assert!(sent.is_ok());
+    }
+
+    /// Block for at most `ticks` ticks until there is room, and append `msg`
+    pub fn send_timeout(&self, msg: T, ticks: u64) -> Result<(), SendError<T>> {
+        self.put(msg, End::Back, Some(ticks))
+    }
+
+    /// Append `msg` if there is room
+    pub fn try_send(&self, msg: T) -> Result<(), SendError<T>> {
+        self.put(msg, End::Back, Some(0))
+    }
+
+    /// Block until there is room, and put `msg` before the other messages
+    pub fn send_front(&self, msg: T) {
+        let sent = self.put(msg, End::Front, None);
+        debug_assert!(sent.is_ok());
+    }
+
+    /// Block for at most `ticks` ticks until there is room, and put `msg`
+    /// before the other messages
+    pub fn send_front_timeout(&self, msg: T, ticks: u64) -> Result<(), SendError<T>> {
+        self.put(msg, End::Front, Some(ticks))
+    }
+
+    /// Put `msg` before the other messages if there is room
+    pub fn try_send_front(&self, msg: T) -> Result<(), SendError<T>> {
+        self.put(msg, End::Front, Some(0))
+    }
+
+    /// `try_send()` for interrupt handlers
+    pub fn send_from_isr(&self, msg: T) -> Result<(), SendError<T>> {
+        let mut state = self.state.lock();
+        if state.len == N {
+            return Err(SendError {
+                msg,
+                error: Error::Full,
+            });
+        }
+        let preempt = unsafe { self.push(&mut state, msg, End::Back) };
+        drop(state);
+        wait::preempt_from_isr(preempt);
+        Ok(())
+    }
+
+    /// Block until there is a message, and take the oldest
+    pub fn recv(&self) -> T {
+        match self.get(None) {
+            Ok(msg) => msg,
+            Err(_) => unreachable!(),
+        }
+    }
+
+    /// Block for at most `ticks` ticks until there is a message, and take
+    /// the oldest
+    pub fn recv_timeout(&self, ticks: u64) -> Result<T, Error> {
+        self.get(Some(ticks))
+    }
+
+    /// Take the oldest message if there is one
+    pub fn try_recv(&self) -> Result<T, Error> {
+        self.get(Some(0))
+    }
+
+    /// `try_recv()` for interrupt handlers
+    pub fn recv_from_isr(&self) -> Result<T, Error> {
+        let mut state = self.state.lock();
+        if state.len == 0 {
+            return Err(Error::Empty);
+        }
+        let (msg, preempt) = unsafe { self.pop(&mut state) };
+        drop(state);
+        wait::preempt_from_isr(preempt);
+        Ok(msg)
+    }
+
+    /// Take the oldest message, blocking for at most `timeout` ticks
+    fn get(&self, timeout: Option<u64>) -> Result<T, Error> {
+        let (msg, preempt) =
+            self.wait_for_message(timeout, |queue, state| unsafe { queue.pop(state) })?;
+        wait::preempt(preempt);
+        Ok(msg)
+    }
+
+    /// Append or prepend `msg`, blocking for at most `timeout` ticks
+    fn put(&self, msg: T, end: End, timeout: Option<u64>) -> Result<(), SendError<T>> {
+        let deadline = timeout.map(|ticks| timer::current_ticks() + ticks);
+        loop {
+            let mut state = self.state.lock();
+            if state.len < N {
+                let preempt = unsafe { self.push(&mut state, msg, end) };
+                drop(state);
+                wait::preempt(preempt);
+                return Ok(());
+            }
+
+            let left = remaining(deadline);
+            if left == Some(0) {
+                let error = if timeout == Some(0) {
+                    Error::Full
+                } else {
+                    Error::TimedOut
+                };
+                return Err(SendError { msg, error });
+            }
+            let addr = self as *const Self as usize;
+            let reason = wait::wait(
+                state,
+                |state| &mut state.senders,
+                left,
+                BlockedOn::Queue(addr),
+            );
+            if let Some(error) = wake_error(reason) {
+                return Err(SendError { msg, error });
+            }
+        }
+    }
+
+    /// Block for at most `timeout` ticks until there is a message, then
+    /// call `take` with the state locked
+    fn wait_for_message<R, F>(&self, timeout: Option<u64>, take: F) -> Result<R, Error>
+    where
+        F: FnOnce(&Self, &mut State) -> R,
+    {
+        let deadline = timeout.map(|ticks| timer::current_ticks() + ticks);
+        loop {
+            let mut state = self.state.lock();
+            if state.len > 0 {
+                return Ok(take(self, &mut *state));
+            }
+
+            let left = remaining(deadline);
+            if left == Some(0) {
+                return Err(if timeout == Some(0) {
+                    Error::Empty
+                } else {
+                    Error::TimedOut
+                });
+            }
+            let addr = self as *const Self as usize;
+            let reason = wait::wait(
+                state,
+                |state| &mut state.receivers,
+                left,
+                BlockedOn::Queue(addr),
+            );
+            if let Some(error) = wake_error(reason) {
+                return Err(error);
+            }
+        }
+    }
+
+    /// Store `msg` and wake a receiver, returns whether it should preempt
+    /// the current thread
+    unsafe fn push(&self, state: &mut State, msg: T, end: End) -> bool {
+        let slot = match end {
+            End::Back => (state.head + state.len) % N,
+            End::Front => {
+                state.head = (state.head + N - 1) % N;
+                state.head
+            }
+        };
+        ptr::write(self.slot(slot), msg);
+        state.len += 1;
+
+        let mut preempt = wait::wake_one(&mut state.receivers, WakeReason::Woken);
+        if state.len < N {
+            preempt |= wait::wake_one(&mut state.senders, WakeReason::Woken);
+        }
+        preempt
+    }
+
+    /// Take the oldest message and wake a sender, also returns whether it
+    /// should preempt the current thread
+    unsafe fn pop(&self, state: &mut State) -> (T, bool) {
+        let msg = ptr::read(self.slot(state.head));
+        state.head = (state.head + 1) % N;
+        state.len -= 1;
+
+        let mut preempt = wait::wake_one(&mut state.senders, WakeReason::Woken);
+        if state.len > 0 {
+            preempt |= wait::wake_one(&mut state.receivers, WakeReason::Woken);
+        }
+        (msg, preempt)
+    }
+
+    unsafe fn slot(&self, index: usize) -> *mut T {
+        ((*self.buf.get()).as_mut_ptr() as *mut T).add(index)
+    }
+}
+
+/// Peeking copies the message with interrupts disabled, so only for small
+/// `Copy` messages
+impl<T: Copy, const N: usize> Queue<T, { N }> {
+    /// Block until there is a message, and copy the oldest
+    pub fn peek(&self) -> T {
+        match self.peek_for(None) {
+            Ok(msg) => msg,
+            Err(_) => unreachable!(),
+        }
+    }
+
+    /// Block for at most `ticks` ticks until there is a message, and copy
+    /// the oldest
+    pub fn peek_timeout(&self, ticks: u64) -> Result<T, Error> {
+        self.peek_for(Some(ticks))
+    }
+
+    /// Copy the oldest message if there is one
+    pub fn try_peek(&self) -> Result<T, Error> {
+        self.peek_for(Some(0))
+    }
+
+    fn peek_for(&self, timeout: Option<u64>) -> Result<T, Error> {
+        let (msg, preempt) = self.wait_for_message(timeout, |queue, state| unsafe {
+            let msg = *queue.slot(state.head);
+            // The message is still there for the next receiver
+            let preempt = wait::wake_one(&mut state.receivers, WakeReason::Woken);
+            (msg, preempt)
+        })?;
+        wait::preempt(preempt);
+        Ok(msg)
+    }
+}
+
+impl<T, const N: usize> Drop for Queue<T, { N }> {
+    fn drop(&mut self) {
+        let mut state = self.state.lock();
+        let preempt = state.senders.delete() | state.receivers.delete();
+        while state.len > 0 {
+            unsafe { ptr::drop_in_place(self.slot(state.head)) };
+            state.head = (state.head + 1) % N;
+            state.len -= 1;
+        }
+        drop(state);
+        wait::preempt(preempt);
+    }
+}
+
+impl State {
+    const fn new() -> State {
+        State {
+            head: 0,
+            len: 0,
+            senders: WaitQueue::new(),
+            receivers: WaitQueue::new(),
+        }
+    }
+}
+
+/// Ticks left until `deadline`, None for no deadline
+fn remaining(deadline: Option<u64>) -> Option<u64> {
+    deadline.map(|deadline| deadline.saturating_sub(timer::current_ticks()))
+}
+
+/// Why a woken thread gives up, None when it should try again
+fn wake_error(reason: WakeReason) -> Option<Error> {
+    match reason {
+        WakeReason::Woken => None,
+        WakeReason::TimedOut => Some(Error::TimedOut),
+        WakeReason::Deleted => Some(Error::Deleted),
+    }
+}
@@ -0,0 +1,69 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+#![deny(warnings)]
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+
+use crate::arch;
+
+/// A lock for data shared between threads and interrupt handlers
+///
+/// Locking disables interrupts until the guard is dropped. Particle runs
+/// on a single core, so this is enough to get exclusive access. The lock
+/// must not be taken again while it is held.
+pub struct IrqLock<T> {
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for IrqLock<T> {}
+unsafe impl<T: Send> Send for IrqLock<T> {}
+
+impl<T> IrqLock<T> {
+    pub const fn new(data: T) -> IrqLock<T> {
+        IrqLock {
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> IrqLockGuard<T> {
+        let state = arch::irq_save();
+        IrqLockGuard {
+            data: unsafe { &mut *self.data.get() },
+            state,
+        }
+    }
+}
+
+/// A guard to which the protected data can be accessed
+///
+/// When the guard falls out of scope the previous interrupt state is
+/// restored.
+pub struct IrqLockGuard<'a, T> {
+    data: &'a mut T,
+    state: usize,
+}
+
+impl<'a, T> Deref for IrqLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'a, T> DerefMut for IrqLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.data
+    }
+}
+
+impl<'a, T> Drop for IrqLockGuard<'a, T> {
+    fn drop(&mut self) {
+        arch::irq_restore(self.state);
+    }
+}
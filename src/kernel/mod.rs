@@ -0,0 +1,23 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+/// Interrupt safe locking
+pub mod irq;
+
+/// Monotonic clock and software timers
+pub mod timer;
+
+/// The idle path
+pub mod idle;
+
+/// Wait queues for blocking kernel objects
+pub mod wait;
+
+/// Blocking locks and signals
+pub mod sync;
+
+/// Message passing between threads
+pub mod ipc;
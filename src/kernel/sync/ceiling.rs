@@ -0,0 +1,113 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Mutexes with a priority ceiling
+//!
+//! The ceiling is the priority of the most urgent thread that ever takes
+//! the mutex, fixed when it is created. Taking the mutex raises the holder
+//! to the ceiling right away, so no other user of the mutex preempts it
+//! until it unlocks: a thread waits for the mutex at most once, for at most
+//! the longest critical section, which is what schedulability analysis
+//! needs. A thread above the ceiling taking the mutex would break that, the
+//! kernel refuses it, and a ceiling that is no priority.
+//!
+//! A holder that blocks or sleeps lets the other users run, those then wait
+//! for the mutex like for a `Mutex`.
+
+#![deny(warnings)]
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+
+use super::mutex::Lock;
+use super::Error;
+
+/// Mutual exclusion for `T` between threads no more urgent than the
+/// ceiling
+pub struct CeilingMutex<T> {
+    lock: Lock,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for CeilingMutex<T> {}
+unsafe impl<T: Send> Send for CeilingMutex<T> {}
+
+impl<T> CeilingMutex<T> {
+    /// A mutex raising its holder to `ceiling`, which must be below
+    /// `NUM_PRIORITIES` or locking it fails
+    pub const fn new(ceiling: u8, data: T) -> CeilingMutex<T> {
+        CeilingMutex {
+            lock: Lock::with_ceiling(ceiling),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn ceiling(&self) -> u8 {
+        self.lock.ceiling().unwrap()
+    }
+
+    /// Block until the mutex is free
+    ///
+    /// Panics if the thread already holds it, its priority is above the
+    /// ceiling or the ceiling is invalid.
+    pub fn lock(&self) -> CeilingMutexGuard<T> {
+        if let Err(err) = self.lock.acquire(None) {
+            panic!("ceiling mutex lock failed: {:?}", err);
+        }
+        CeilingMutexGuard {
+            mutex: self,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Block for at most `ticks` ticks until the mutex is free
+    pub fn lock_timeout(&self, ticks: u64) -> Result<CeilingMutexGuard<T>, Error> {
+        self.lock.acquire(Some(ticks))?;
+        Ok(CeilingMutexGuard {
+            mutex: self,
+            _not_send: PhantomData,
+        })
+    }
+
+    /// Take the mutex if it is free
+    pub fn try_lock(&self) -> Result<CeilingMutexGuard<T>, Error> {
+        self.lock_timeout(0)
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+}
+
+/// Unlocks the mutex and drops the holder back from the ceiling when
+/// dropped, in the thread that locked it
+pub struct CeilingMutexGuard<'a, T> {
+    mutex: &'a CeilingMutex<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+unsafe impl<'a, T: Sync> Sync for CeilingMutexGuard<'a, T> {}
+
+impl<'a, T> Deref for CeilingMutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for CeilingMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> Drop for CeilingMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.lock.release();
+    }
+}
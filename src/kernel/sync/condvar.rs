@@ -0,0 +1,173 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Condition variables
+//!
+//! A waiting thread blocks on the wait queue of the `Condvar` and releases
+//! the mutex with that queue locked, so a notification sent once the mutex
+//! is free always finds it there. Once woken up it takes the mutex again
+//! before returning. Like with `std`, a thread may wake up without the
+//! condition holding and has to check it in a loop.
+//!
+//! The `spin` variants do the same with a `spin::Mutex`, only getting it
+//! back spins. They lock the mutex themselves and wait while a condition on
+//! its data holds, so the lock they release is always the one they take
+//! back.
+
+#![deny(warnings)]
+
+use core::mem;
+
+use super::mutex::MutexGuard;
+use crate::kernel::irq::{IrqLock, IrqLockGuard};
+use crate::kernel::timer;
+use crate::kernel::wait::{self, BlockedOn, WaitQueue, WakeReason};
+
+/// Whether `Condvar::wait_timeout()` returned because the timeout passed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+pub struct Condvar {
+    waiters: IrqLock<WaitQueue>,
+}
+
+impl Default for Condvar {
+    fn default() -> Condvar {
+        Condvar::new()
+    }
+}
+
+impl Condvar {
+    pub const fn new() -> Condvar {
+        Condvar {
+            waiters: IrqLock::new(WaitQueue::new()),
+        }
+    }
+
+    /// Release the mutex `guard` holds and block until notified, then take
+    /// the mutex again
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        self.wait_for(guard, None).0
+    }
+
+    /// `wait()` for at most `ticks` ticks, the mutex is held again either
+    /// way
+    pub fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        ticks: u64,
+    ) -> (MutexGuard<'a, T>, WaitTimeoutResult) {
+        self.wait_for(guard, Some(ticks))
+    }
+
+    /// Lock `mutex`, then block until notified for as long as `condition`
+    /// holds for its data, returns with the mutex held
+    pub fn wait_spin_while<'a, T, F>(
+        &self,
+        mutex: &'a spin::Mutex<T>,
+        condition: F,
+    ) -> spin::MutexGuard<'a, T>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        self.wait_spin_for(mutex, None, condition).0
+    }
+
+    /// `wait_spin_while()` for at most `ticks` ticks, the mutex is held
+    /// again either way, and the result only timed out if `condition` still
+    /// holds
+    pub fn wait_spin_timeout_while<'a, T, F>(
+        &self,
+        mutex: &'a spin::Mutex<T>,
+        ticks: u64,
+        condition: F,
+    ) -> (spin::MutexGuard<'a, T>, WaitTimeoutResult)
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        self.wait_spin_for(mutex, Some(ticks), condition)
+    }
+
+    /// Wake the most urgent waiter up
+    pub fn notify_one(&self) {
+        let mut waiters = self.waiters.lock();
+        let preempt = unsafe { wait::wake_one(&mut *waiters, WakeReason::Woken) };
+        drop(waiters);
+        wait::preempt(preempt);
+    }
+
+    /// Wake every waiter up, they take the mutex most urgent first
+    pub fn notify_all(&self) {
+        let mut waiters = self.waiters.lock();
+        let preempt = unsafe { wait::wake_all(&mut *waiters, WakeReason::Woken) };
+        drop(waiters);
+        wait::preempt(preempt);
+    }
+
+    fn wait_for<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Option<u64>,
+    ) -> (MutexGuard<'a, T>, WaitTimeoutResult) {
+        let mutex = guard.mutex;
+        let waiters = self.waiters.lock();
+        mem::forget(guard);
+        // The thread blocks right after, which lets a woken waiter of the
+        // mutex run anyway
+        mutex.lock.release_deferred();
+        let reason = self.park(waiters, timeout);
+        (mutex.lock(), reason)
+    }
+
+    fn wait_spin_for<'a, T, F>(
+        &self,
+        mutex: &'a spin::Mutex<T>,
+        timeout: Option<u64>,
+        mut condition: F,
+    ) -> (spin::MutexGuard<'a, T>, WaitTimeoutResult)
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        let deadline = timeout.map(|ticks| timer::current_ticks() + ticks);
+        let mut guard = mutex.lock();
+        while condition(&mut *guard) {
+            let left = deadline.map(|deadline| deadline.saturating_sub(timer::current_ticks()));
+            if left == Some(0) {
+                return (guard, WaitTimeoutResult(true));
+            }
+            let waiters = self.waiters.lock();
+            drop(guard);
+            self.park(waiters, left);
+            guard = mutex.lock();
+        }
+        (guard, WaitTimeoutResult(false))
+    }
+
+    /// Block on the queue `waiters` locks until notified
+    fn park(&self, waiters: IrqLockGuard<WaitQueue>, timeout: Option<u64>) -> WaitTimeoutResult {
+        let addr = self as *const Condvar as usize;
+        let reason = wait::wait(
+            waiters,
+            |waiters| waiters,
+            timeout,
+            BlockedOn::Condvar(addr),
+        );
+        WaitTimeoutResult(reason != WakeReason::Woken)
+    }
+}
+
+impl Drop for Condvar {
+    fn drop(&mut self) {
+        let preempt = self.waiters.lock().delete();
+        wait::preempt(preempt);
+    }
+}
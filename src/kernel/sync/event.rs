@@ -0,0 +1,211 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Event groups
+//!
+//! An `EventGroup` is a word of 32 flags, which threads and interrupt
+//! handlers set and clear. A thread waits for any or all flags of a mask.
+//! Setting flags checks every waiter against the same flags, wakes the ones
+//! they satisfy together, most urgent first, and only then clears the flags
+//! those asked to clear.
+
+#![deny(warnings)]
+
+use super::{woken, Error};
+use crate::kernel::irq::IrqLock;
+use crate::kernel::wait::{self, BlockedOn, WaitQueue, WakeReason};
+
+/// Which flags of the mask a waiter needs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaitFor {
+    /// At least one
+    Any,
+    /// Every one
+    All,
+}
+
+/// What a blocked thread waits for, on its stack
+struct Waiter {
+    mask: u32,
+    wait_for: WaitFor,
+    clear_on_exit: bool,
+    /// The flags of the mask that woke it up
+    matched: u32,
+}
+
+impl Waiter {
+    /// The flags of the mask set in `flags`, if they are enough
+    fn matches(&self, flags: u32) -> Option<u32> {
+        let matched = flags & self.mask;
+        let enough = match self.wait_for {
+            WaitFor::Any => matched != 0,
+            WaitFor::All => matched == self.mask,
+        };
+        if enough {
+            Some(matched)
+        } else {
+            None
+        }
+    }
+}
+
+struct RawEventGroup {
+    flags: u32,
+    waiters: WaitQueue,
+}
+
+pub struct EventGroup {
+    raw: IrqLock<RawEventGroup>,
+}
+
+impl Default for EventGroup {
+    fn default() -> EventGroup {
+        EventGroup::new()
+    }
+}
+
+impl EventGroup {
+    pub const fn new() -> EventGroup {
+        EventGroup {
+            raw: IrqLock::new(RawEventGroup {
+                flags: 0,
+                waiters: WaitQueue::new(),
+            }),
+        }
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.raw.lock().flags
+    }
+
+    /// Set `flags` and wake the threads waiting for them, returns the flags
+    /// left once the woken threads cleared theirs
+    pub fn set(&self, flags: u32) -> u32 {
+        let (flags, preempt) = self.raise(flags);
+        wait::preempt(preempt);
+        flags
+    }
+
+    /// `set()` for interrupt handlers
+    pub fn set_from_isr(&self, flags: u32) -> u32 {
+        let (flags, preempt) = self.raise(flags);
+        wait::preempt_from_isr(preempt);
+        flags
+    }
+
+    /// Clear `flags`, returns the flags before, also from interrupt
+    /// handlers
+    pub fn clear(&self, flags: u32) -> u32 {
+        let mut raw = self.raw.lock();
+        let previous = raw.flags;
+        raw.flags &= !flags;
+        previous
+    }
+
+    /// Block until any or all flags of `mask` are set, returns those set
+    ///
+    /// With `clear_on_exit` they are cleared again before the call returns.
+    pub fn wait(&self, mask: u32, wait_for: WaitFor, clear_on_exit: bool) -> u32 {
+        match self.wait_flags(mask, wait_for, clear_on_exit, None) {
+            Ok(matched) => matched,
+            Err(_) => unreachable!(),
+        }
+    }
+
+    /// Block for at most `ticks` ticks until any or all flags of `mask` are
+    /// set
+    pub fn wait_timeout(
+        &self,
+        mask: u32,
+        wait_for: WaitFor,
+        clear_on_exit: bool,
+        ticks: u64,
+    ) -> Result<u32, Error> {
+        self.wait_flags(mask, wait_for, clear_on_exit, Some(ticks))
+    }
+
+    /// Returns the flags of `mask` set, if they are enough
+    pub fn try_wait(
+        &self,
+        mask: u32,
+        wait_for: WaitFor,
+        clear_on_exit: bool,
+    ) -> Result<u32, Error> {
+        self.wait_flags(mask, wait_for, clear_on_exit, Some(0))
+    }
+
+    fn wait_flags(
+        &self,
+        mask: u32,
+        wait_for: WaitFor,
+        clear_on_exit: bool,
+        timeout: Option<u64>,
+    ) -> Result<u32, Error> {
+        debug_assert!(mask != 0, "waiting for no flags");
+        let mut waiter = Waiter {
+            mask,
+            wait_for,
+            clear_on_exit,
+            matched: 0,
+        };
+
+        let mut raw = self.raw.lock();
+        if let Some(matched) = waiter.matches(raw.flags) {
+            if clear_on_exit {
+                raw.flags &= !mask;
+            }
+            return Ok(matched);
+        }
+        if timeout == Some(0) {
+            return Err(Error::WouldBlock);
+        }
+
+        // Blocked until `raise()` wrote the flags that matched
+        let addr = self as *const EventGroup as usize;
+        woken(wait::wait_with(
+            raw,
+            |raw| &mut raw.waiters,
+            &mut waiter as *mut Waiter as usize,
+            timeout,
+            BlockedOn::Events(addr),
+        ))?;
+        Ok(waiter.matched)
+    }
+
+    /// Set `flags` and wake the waiters they satisfy, returns the flags left
+    /// and whether a woken thread should preempt the current one
+    fn raise(&self, flags: u32) -> (u32, bool) {
+        let mut raw = self.raw.lock();
+        raw.flags |= flags;
+        let flags = raw.flags;
+
+        let mut clear = 0;
+        let preempt = unsafe {
+            wait::wake_matching(&mut raw.waiters, WakeReason::Woken, |data| {
+                let waiter = &mut *(data as *mut Waiter);
+                match waiter.matches(flags) {
+                    Some(matched) => {
+                        waiter.matched = matched;
+                        if waiter.clear_on_exit {
+                            clear |= waiter.mask;
+                        }
+                        true
+                    }
+                    None => false,
+                }
+            })
+        };
+        raw.flags &= !clear;
+        (raw.flags, preempt)
+    }
+}
+
+impl Drop for EventGroup {
+    fn drop(&mut self) {
+        let preempt = self.raw.lock().waiters.delete();
+        wait::preempt(preempt);
+    }
+}
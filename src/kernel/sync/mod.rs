@@ -0,0 +1,57 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Blocking synchronization between threads
+//!
+//! Unlike `spin::Mutex`, waiting threads block on a wait queue (see
+//! `kernel::wait`) and the scheduler runs the other ones meanwhile.
+//! Timeouts are in ticks.
+
+#![deny(warnings)]
+
+use crate::kernel::wait::WakeReason;
+
+/// Mutexes with a priority ceiling
+mod ceiling;
+/// Condition variables
+mod condvar;
+/// Flags to wait for
+mod event;
+/// Mutexes with priority inheritance
+mod mutex;
+/// Counting and binary semaphores
+mod semaphore;
+
+pub use self::ceiling::{CeilingMutex, CeilingMutexGuard};
+pub use self::condvar::{Condvar, WaitTimeoutResult};
+pub use self::event::{EventGroup, WaitFor};
+pub use self::mutex::{Mutex, MutexGuard, RecursiveMutex, RecursiveMutexGuard};
+pub use self::semaphore::Semaphore;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The timeout passed first
+    TimedOut,
+    /// A try or a timeout of 0 found the object taken
+    WouldBlock,
+    /// The thread already holds the lock
+    Deadlock,
+    /// The thread is more urgent than the ceiling of the lock
+    AboveCeiling,
+    /// The ceiling of the lock is not below `NUM_PRIORITIES`
+    InvalidCeiling,
+    /// The object was deleted while the thread waited
+    Deleted,
+}
+
+/// What waiting on an object that woke the thread up means
+fn woken(reason: WakeReason) -> Result<(), Error> {
+    match reason {
+        WakeReason::Woken => Ok(()),
+        WakeReason::TimedOut => Err(Error::TimedOut),
+        WakeReason::Deleted => Err(Error::Deleted),
+    }
+}
@@ -0,0 +1,301 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Mutexes with priority inheritance
+//!
+//! A thread that finds the mutex taken blocks on its wait queue, which the
+//! holder owns: the holder runs at the priority of the most urgent waiter
+//! until it unlocks, so a less urgent thread can't keep it off the core
+//! meanwhile. Unlocking hands the mutex straight to that waiter.
+//!
+//! Mutexes are for threads, interrupt handlers can't block.
+
+#![deny(warnings)]
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+
+use super::{woken, Error};
+use crate::kernel::irq::IrqLock;
+use crate::kernel::wait::{self, BlockedOn, WaitQueue, WakeReason};
+use crate::thread::NUM_PRIORITIES;
+
+struct RawMutex {
+    /// Owned by the holder of the mutex
+    waiters: WaitQueue,
+    /// How many times the holder locked it
+    count: u32,
+}
+
+/// The lock shared by `Mutex`, `RecursiveMutex` and `CeilingMutex`
+pub(super) struct Lock {
+    raw: IrqLock<RawMutex>,
+    recursive: bool,
+}
+
+impl Lock {
+    const fn new(recursive: bool) -> Lock {
+        Lock {
+            raw: IrqLock::new(RawMutex {
+                waiters: WaitQueue::new(),
+                count: 0,
+            }),
+            recursive,
+        }
+    }
+
+    /// A lock that raises its holder to `ceiling`
+    pub(super) const fn with_ceiling(ceiling: u8) -> Lock {
+        Lock {
+            raw: IrqLock::new(RawMutex {
+                waiters: WaitQueue::with_ceiling(ceiling),
+                count: 0,
+            }),
+            recursive: false,
+        }
+    }
+
+    /// The priority the holder is raised to, if any
+    pub(super) fn ceiling(&self) -> Option<u8> {
+        self.raw.lock().waiters.ceiling()
+    }
+
+    /// Take the lock, blocking for at most `timeout` ticks
+    pub(super) fn acquire(&self, timeout: Option<u64>) -> Result<(), Error> {
+        let current = wait::current_thread();
+        let mut raw = self.raw.lock();
+
+        if let Some(ceiling) = raw.waiters.ceiling() {
+            if ceiling as usize >= NUM_PRIORITIES {
+                return Err(Error::InvalidCeiling);
+            }
+        }
+        if !unsafe { raw.waiters.may_own(current) } {
+            return Err(Error::AboveCeiling);
+        }
+        let owner = raw.waiters.owner();
+        if owner.is_null() {
+            unsafe { raw.waiters.set_owner(current) };
+            raw.count = 1;
+            return Ok(());
+        }
+        if owner == current {
+            if !self.recursive {
+                return Err(Error::Deadlock);
+            }
+            raw.count += 1;
+            return Ok(());
+        }
+        if timeout == Some(0) {
+            return Err(Error::WouldBlock);
+        }
+
+        // Blocked until `release()` hands the lock over, or the timeout
+        let addr = self as *const Lock as usize;
+        woken(wait::wait(
+            raw,
+            |raw| &mut raw.waiters,
+            timeout,
+            BlockedOn::Mutex(addr),
+        ))
+    }
+
+    pub(super) fn release(&self) {
+        let preempt = self.release_deferred();
+        wait::preempt(preempt);
+    }
+
+    /// Give up the lock without letting the woken waiter run, returns
+    /// whether it should preempt the current thread
+    pub(super) fn release_deferred(&self) -> bool {
+        let mut raw = self.raw.lock();
+        assert!(
+            raw.waiters.owner() == wait::current_thread(),
+            "mutex released by a thread that does not hold it"
+        );
+        raw.count -= 1;
+        if raw.count > 0 {
+            return false;
+        }
+
+        unsafe {
+            match wait::dequeue(&mut raw.waiters) {
+                Some(next) => {
+                    raw.waiters.set_owner(next);
+                    raw.count = 1;
+                    wait::ready(next, WakeReason::Woken)
+                }
+                None => {
+                    raw.waiters.set_owner(ptr::null_mut());
+                    false
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let mut raw = self.raw.lock();
+        let preempt = raw.waiters.delete();
+        // The holder forgot its guard, don't leave it owning the queue
+        unsafe { raw.waiters.set_owner(ptr::null_mut()) };
+        drop(raw);
+        wait::preempt(preempt);
+    }
+}
+
+/// Mutual exclusion for `T` between threads
+pub struct Mutex<T> {
+    pub(super) lock: Lock,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+unsafe impl<T: Send> Send for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Mutex<T> {
+        Mutex {
+            lock: Lock::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Block until the mutex is free
+    ///
+    /// Panics if the thread already holds it.
+    pub fn lock(&self) -> MutexGuard<T> {
+        if let Err(err) = self.lock.acquire(None) {
+            panic!("mutex lock failed: {:?}", err);
+        }
+        MutexGuard {
+            mutex: self,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Block for at most `ticks` ticks until the mutex is free
+    pub fn lock_timeout(&self, ticks: u64) -> Result<MutexGuard<T>, Error> {
+        self.lock.acquire(Some(ticks))?;
+        Ok(MutexGuard {
+            mutex: self,
+            _not_send: PhantomData,
+        })
+    }
+
+    /// Take the mutex if it is free
+    pub fn try_lock(&self) -> Result<MutexGuard<T>, Error> {
+        self.lock_timeout(0)
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+}
+
+/// Unlocks the mutex when dropped
+///
+/// Only the thread that locked the mutex may unlock it, so the guard can't
+/// be sent to another one.
+pub struct MutexGuard<'a, T> {
+    pub(super) mutex: &'a Mutex<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+unsafe impl<'a, T: Sync> Sync for MutexGuard<'a, T> {}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.lock.release();
+    }
+}
+
+/// A mutex its holder may lock again, it is free once every guard is gone
+///
+/// The guards only give shared access to `T`, use a `Cell` or `RefCell`
+/// inside to change it.
+pub struct RecursiveMutex<T> {
+    lock: Lock,
+    data: T,
+}
+
+unsafe impl<T: Send> Sync for RecursiveMutex<T> {}
+unsafe impl<T: Send> Send for RecursiveMutex<T> {}
+
+impl<T> RecursiveMutex<T> {
+    pub const fn new(data: T) -> RecursiveMutex<T> {
+        RecursiveMutex {
+            lock: Lock::new(true),
+            data,
+        }
+    }
+
+    /// Block until the mutex is free or held by this thread
+    pub fn lock(&self) -> RecursiveMutexGuard<T> {
+        if let Err(err) = self.lock.acquire(None) {
+            panic!("mutex lock failed: {:?}", err);
+        }
+        RecursiveMutexGuard {
+            mutex: self,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Block for at most `ticks` ticks until the mutex is free or held by
+    /// this thread
+    pub fn lock_timeout(&self, ticks: u64) -> Result<RecursiveMutexGuard<T>, Error> {
+        self.lock.acquire(Some(ticks))?;
+        Ok(RecursiveMutexGuard {
+            mutex: self,
+            _not_send: PhantomData,
+        })
+    }
+
+    /// Take the mutex if it is free or held by this thread
+    pub fn try_lock(&self) -> Result<RecursiveMutexGuard<T>, Error> {
+        self.lock_timeout(0)
+    }
+}
+
+/// Unlocks the recursive mutex once when dropped, in the thread that locked
+/// it
+pub struct RecursiveMutexGuard<'a, T> {
+    mutex: &'a RecursiveMutex<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+unsafe impl<'a, T: Sync> Sync for RecursiveMutexGuard<'a, T> {}
+
+impl<'a, T> Deref for RecursiveMutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.mutex.data
+    }
+}
+
+impl<'a, T> Drop for RecursiveMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.lock.release();
+    }
+}
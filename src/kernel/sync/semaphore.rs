@@ -0,0 +1,125 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Counting and binary semaphores
+//!
+//! A thread taking an empty semaphore blocks on its wait queue. Giving it
+//! hands the unit straight to the most urgent waiter, or counts it when
+//! nobody waits. Interrupt handlers give with `give_from_isr()`: a woken
+//! thread more urgent than the interrupted one runs once they returned.
+//!
+//! A binary semaphore, with a count of at most 1, is how a driver tells a
+//! thread that an interrupt happened.
+
+#![deny(warnings)]
+
+use super::{woken, Error};
+use crate::kernel::irq::IrqLock;
+use crate::kernel::wait::{self, BlockedOn, WaitQueue, WakeReason};
+
+struct RawSemaphore {
+    waiters: WaitQueue,
+    count: u32,
+}
+
+pub struct Semaphore {
+    raw: IrqLock<RawSemaphore>,
+    max: u32,
+}
+
+impl Semaphore {
+    /// A semaphore holding `count` units, at most `max`
+    pub const fn new(count: u32, max: u32) -> Semaphore {
+        Semaphore {
+            raw: IrqLock::new(RawSemaphore {
+                waiters: WaitQueue::new(),
+                count,
+            }),
+            max,
+        }
+    }
+
+    /// A semaphore holding at most one unit, the first `take()` blocks
+    pub const fn binary() -> Semaphore {
+        Semaphore::new(0, 1)
+    }
+
+    /// How many units the semaphore holds
+    pub fn count(&self) -> u32 {
+        self.raw.lock().count
+    }
+
+    /// Block until the semaphore holds a unit, and take it
+    pub fn take(&self) {
+        let taken = self.acquire(None);
+        debug_assert!(taken.is_ok());
+    }
+
+    /// Block for at most `ticks` ticks until the semaphore holds a unit
+    pub fn take_timeout(&self, ticks: u64) -> Result<(), Error> {
+        self.acquire(Some(ticks))
+    }
+
+    /// Take a unit if the semaphore holds one
+    pub fn try_take(&self) -> Result<(), Error> {
+        self.acquire(Some(0))
+    }
+
+    /// Give a unit back, to the most urgent waiter if there is one
+    ///
+    /// A unit given to a full semaphore is lost, giving a binary semaphore
+    /// twice wakes one `take()`.
+    pub fn give(&self) {
+        let preempt = self.release();
+        wait::preempt(preempt);
+    }
+
+    /// `give()` for interrupt handlers
+    pub fn give_from_isr(&self) {
+        let preempt = self.release();
+        wait::preempt_from_isr(preempt);
+    }
+
+    fn acquire(&self, timeout: Option<u64>) -> Result<(), Error> {
+        let mut raw = self.raw.lock();
+        if raw.count > 0 {
+            raw.count -= 1;
+            return Ok(());
+        }
+        if timeout == Some(0) {
+            return Err(Error::WouldBlock);
+        }
+
+        // Blocked until `release()` hands a unit over, or the timeout
+        let addr = self as *const Semaphore as usize;
+        woken(wait::wait(
+            raw,
+            |raw| &mut raw.waiters,
+            timeout,
+            BlockedOn::Semaphore(addr),
+        ))
+    }
+
+    /// Returns whether the woken waiter should preempt the current thread
+    fn release(&self) -> bool {
+        let mut raw = self.raw.lock();
+        if raw.waiters.is_empty() {
+            if raw.count < self.max {
+                raw.count += 1;
+            }
+            false
+        } else {
+            unsafe { wait::wake_one(&mut raw.waiters, WakeReason::Woken) }
+        }
+    }
+}
+
+impl Drop for Semaphore {
+    fn drop(&mut self) {
+        let preempt = self.raw.lock().waiters.delete();
+        wait::preempt(preempt);
+    }
+}
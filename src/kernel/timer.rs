@@ -0,0 +1,231 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Monotonic clock and software timers
+//!
+//! The kernel counts time in ticks of `TICK_RATE_HZ`. Armed timers are kept
+//! in a list sorted by deadline and fired from the tick interrupt. Timers
+//! are linked in place, so an armed timer must not move until it fired or
+//! was cancelled.
+
+#![deny(warnings)]
+
+use core::ptr;
+
+use super::irq::IrqLock;
+use crate::arch;
+use crate::thread;
+
+/// Frequency of the kernel tick
+pub const TICK_RATE_HZ: u32 = 1000;
+
+/// Called with the current time and the argument given when arming
+pub type TimerCallback = fn(now: u64, arg: usize);
+
+const TIMER_MAGIC: u32 = 0x74696d72; // 'timr'
+
+#[derive(Debug)]
+pub struct Timer {
+    /// The magic of this timer
+    magic: u32,
+    /// Absolute deadline in ticks
+    deadline: u64,
+    /// Reload interval in ticks, 0 for one shot timers
+    period: u64,
+    callback: Option<TimerCallback>,
+    arg: usize,
+    /// Next timer in the timer queue
+    next: *mut Timer,
+    /// Whether this timer is linked into the timer queue
+    armed: bool,
+}
+
+struct TimerQueue {
+    /// Ticks since `timer_init()`
+    now: u64,
+    /// Armed timers sorted by deadline
+    head: *mut Timer,
+}
+
+unsafe impl Send for TimerQueue {}
+
+static TIMER_QUEUE: IrqLock<TimerQueue> = IrqLock::new(TimerQueue {
+    now: 0,
+    head: ptr::null_mut(),
+});
+
+impl TimerQueue {
+    unsafe fn insert(&mut self, timer: *mut Timer) {
+        let mut link = &mut self.head as *mut *mut Timer;
+        while !(*link).is_null() && (**link).deadline <= (*timer).deadline {
+            link = &mut (**link).next;
+        }
+        (*timer).next = *link;
+        (*timer).armed = true;
+        *link = timer;
+    }
+
+    unsafe fn remove(&mut self, timer: *mut Timer) {
+        let mut link = &mut self.head as *mut *mut Timer;
+        while !(*link).is_null() {
+            if *link == timer {
+                *link = (*timer).next;
+                break;
+            }
+            link = &mut (**link).next;
+        }
+        (*timer).next = ptr::null_mut();
+        (*timer).armed = false;
+    }
+
+    /// Unlink the first timer if its deadline has passed
+    unsafe fn pop_expired(&mut self) -> Option<(TimerCallback, usize)> {
+        let timer = self.head;
+        if timer.is_null() || (*timer).deadline > self.now {
+            return None;
+        }
+
+        self.remove(timer);
+        if (*timer).period != 0 {
+            (*timer).deadline += (*timer).period;
+            self.insert(timer);
+        }
+
+        (*timer).callback.map(|callback| (callback, (*timer).arg))
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Timer {
+        Timer::new()
+    }
+}
+
+impl Timer {
+    pub const fn new() -> Timer {
+        Timer {
+            magic: TIMER_MAGIC,
+            deadline: 0,
+            period: 0,
+            callback: None,
+            arg: 0,
+            next: ptr::null_mut(),
+            armed: false,
+        }
+    }
+
+    /// Fire `callback` once, `delay` ticks from now
+    ///
+    /// # Safety
+    ///
+    /// The timer must not be moved until it fired or was cancelled.
+    pub unsafe fn set_oneshot(&mut self, delay: u64, callback: TimerCallback, arg: usize) {
+        self.set(delay, 0, callback, arg);
+    }
+
+    /// Fire `callback` every `period` ticks
+    ///
+    /// # Safety
+    ///
+    /// The timer must not be moved until it was cancelled.
+    pub unsafe fn set_periodic(&mut self, period: u64, callback: TimerCallback, arg: usize) {
+        assert!(period > 0);
+        self.set(period, period, callback, arg);
+    }
+
+    unsafe fn set(&mut self, delay: u64, period: u64, callback: TimerCallback, arg: usize) {
+        assert_eq!(self.magic, TIMER_MAGIC);
+
+        let mut queue = TIMER_QUEUE.lock();
+        if self.armed {
+            queue.remove(self);
+        }
+        self.deadline = queue.now + delay;
+        self.period = period;
+        self.callback = Some(callback);
+        self.arg = arg;
+        queue.insert(self);
+    }
+
+    /// Disarm the timer, it is fine to cancel a timer that isn't armed
+    pub fn cancel(&mut self) {
+        let mut queue = TIMER_QUEUE.lock();
+        if self.armed {
+            unsafe { queue.remove(self) };
+        }
+    }
+
+    pub fn is_armed(&self) -> bool {
+        let _queue = TIMER_QUEUE.lock();
+        self.armed
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Start the kernel tick
+///
+/// This function is called once, from kmain()
+pub fn timer_init() {
+    arch::timer_init(TICK_RATE_HZ);
+}
+
+/// Ticks since `timer_init()`
+pub fn current_ticks() -> u64 {
+    TIMER_QUEUE.lock().now
+}
+
+/// Convert milliseconds to ticks, rounding up
+pub fn ms_to_ticks(ms: u64) -> u64 {
+    (ms * TICK_RATE_HZ as u64 + 999) / 1000
+}
+
+/// Convert ticks to milliseconds
+pub fn ticks_to_ms(ticks: u64) -> u64 {
+    ticks * 1000 / TICK_RATE_HZ as u64
+}
+
+/// The earliest deadline of all armed timers
+pub fn next_deadline() -> Option<u64> {
+    let queue = TIMER_QUEUE.lock();
+    if queue.head.is_null() {
+        None
+    } else {
+        Some(unsafe { (*queue.head).deadline })
+    }
+}
+
+/// Account one tick, called from the tick interrupt
+pub fn timer_tick() {
+    timer_advance(1);
+}
+
+/// Move the clock forward by `ticks`, fire every timer that expired and
+/// charge the ticks to the timeslice of the running thread
+///
+/// Callbacks are called without the timer queue locked, so they may arm
+/// and cancel timers.
+pub fn timer_advance(ticks: u64) {
+    let now = {
+        let mut queue = TIMER_QUEUE.lock();
+        queue.now += ticks;
+        queue.now
+    };
+
+    loop {
+        let expired = unsafe { TIMER_QUEUE.lock().pop_expired() };
+        match expired {
+            Some((callback, arg)) => callback(now, arg),
+            None => break,
+        }
+    }
+
+    thread::thread_tick(ticks);
+}
@@ -0,0 +1,110 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Wait queues, what every blocking kernel object is built on
+//!
+//! An object keeps its state and a `WaitQueue` behind an `IrqLock`. A
+//! thread that has to wait hands the guard to `wait()`, which blocks it on
+//! the queue, moving it from Running to Blocked and arming its timer for
+//! the timeout, and only then releases the lock. Whoever changes the state
+//! wakes waiters with `WaitQueue::wake_one()` or `wake_all()` while holding
+//! the lock, releases it, then lets a more urgent woken thread run with
+//! `preempt()`, or `preempt_from_isr()` from an interrupt handler.
+//!
+//! The waiters get a `WakeReason`: woken up, timed out, or the object was
+//! deleted under them, which `WaitQueue::delete()` tells them when the
+//! object is dropped.
+
+#![deny(warnings)]
+
+use crate::arch;
+use crate::kernel::irq::IrqLockGuard;
+use crate::thread::wait as raw;
+
+pub(crate) use crate::thread::wait::{
+    current_thread, dequeue, ready, wake_all, wake_matching, wake_one,
+};
+pub use crate::thread::wait::{preempt, preempt_from_isr, Order, WaitQueue, WakeReason};
+pub use crate::thread::BlockedOn;
+
+impl WaitQueue {
+    /// Make the first waiter ready, returns whether it should preempt the
+    /// current thread
+    pub fn wake_one(&mut self, reason: WakeReason) -> bool {
+        let state = arch::irq_save();
+        let preempt = unsafe { raw::wake_one(self, reason) };
+        arch::irq_restore(state);
+        preempt
+    }
+
+    /// Make every waiter ready, returns whether one of them should preempt
+    /// the current thread
+    pub fn wake_all(&mut self, reason: WakeReason) -> bool {
+        let state = arch::irq_save();
+        let preempt = unsafe { raw::wake_all(self, reason) };
+        arch::irq_restore(state);
+        preempt
+    }
+
+    /// Make the waiters `wanted` picks by the data they waited with ready,
+    /// first to last, returns whether one of them should preempt the
+    /// current thread
+    pub fn wake_matching<F>(&mut self, reason: WakeReason, wanted: F) -> bool
+    where
+        F: FnMut(usize) -> bool,
+    {
+        let state = arch::irq_save();
+        let preempt = unsafe { raw::wake_matching(self, reason, wanted) };
+        arch::irq_restore(state);
+        preempt
+    }
+
+    /// Wake every waiter with `WakeReason::Deleted`, for an object going
+    /// away
+    pub fn delete(&mut self) -> bool {
+        self.wake_all(WakeReason::Deleted)
+    }
+}
+
+/// Block the current thread on the queue `queue` picks in the object
+/// `guard` locks, until it is woken up or `timeout` ticks passed
+///
+/// The lock is released once the thread is on the queue, so no wakeup gets
+/// lost. A timeout of 0 times out without blocking.
+pub fn wait<T, F>(
+    guard: IrqLockGuard<T>,
+    queue: F,
+    timeout: Option<u64>,
+    blocked_on: BlockedOn,
+) -> WakeReason
+where
+    F: FnOnce(&mut T) -> &mut WaitQueue,
+{
+    wait_with(guard, queue, 0, timeout, blocked_on)
+}
+
+/// `wait()` handing `data` to `wake_matching()`
+///
+/// `data` usually points to what the thread waits for, on its stack. The
+/// waker may write the outcome there before making it ready.
+pub fn wait_with<T, F>(
+    mut guard: IrqLockGuard<T>,
+    queue: F,
+    data: usize,
+    timeout: Option<u64>,
+    blocked_on: BlockedOn,
+) -> WakeReason
+where
+    F: FnOnce(&mut T) -> &mut WaitQueue,
+{
+    if timeout == Some(0) {
+        return WakeReason::TimedOut;
+    }
+    unsafe { raw::block(queue(&mut guard), timeout, blocked_on, data) };
+    // Cortex-M only switches away here
+    drop(guard);
+    raw::wake_reason()
+}
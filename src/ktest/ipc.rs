@@ -0,0 +1,158 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{increment, spawn, TestResult};
+use crate::kernel::ipc::{Error, Queue, SendError};
+use crate::kernel::timer::{self, Timer};
+use crate::thread::{self, DEFAULT_PRIORITY};
+
+/// Messages come out oldest first, after the ones sent to the front
+pub fn queue_order() -> TestResult {
+    let queue: Queue<u32, 4> = Queue::new();
+    for msg in 1..4 {
+        ktest_assert!(queue.try_send(msg).is_ok(), "send failed");
+    }
+    ktest_assert!(queue.try_send_front(0).is_ok(), "send to front failed");
+    ktest_assert!(
+        queue.try_send(4)
+            == Err(SendError {
+                msg: 4,
+                error: Error::Full,
+            }),
+        "sent to a full queue"
+    );
+    ktest_assert!(queue.try_peek() == Ok(0), "peek failed");
+    ktest_assert!(queue.len() == 4, "peek took the message");
+
+    for msg in 0..4 {
+        ktest_assert!(queue.try_recv() == Ok(msg), "received out of order");
+    }
+    ktest_assert!(
+        queue.try_recv() == Err(Error::Empty),
+        "received from nothing"
+    );
+    let start = timer::current_ticks();
+    ktest_assert!(
+        queue.recv_timeout(5) == Err(Error::TimedOut),
+        "receive did not time out"
+    );
+    ktest_assert!(timer::current_ticks() - start >= 5, "timed out early");
+    Ok(())
+}
+
+static PIPE: Queue<usize, 4> = Queue::new();
+
+fn produce(count: usize) -> i32 {
+    for msg in 0..count {
+        PIPE.send(msg);
+    }
+    0
+}
+
+/// A sender blocks while the queue is full, until the receiver makes room
+pub fn queue_blocking() -> TestResult {
+    // More urgent, it fills the queue and blocks before this thread runs
+    let producer = spawn("producer", DEFAULT_PRIORITY + 1, produce, 16);
+    ktest_assert!(PIPE.len() == 4, "producer did not fill the queue");
+    for msg in 0..16 {
+        ktest_assert!(PIPE.recv() == msg, "message lost");
+    }
+    ktest_assert!(thread::join(producer) == Ok(0), "join failed");
+    ktest_assert!(PIPE.is_empty(), "messages left over");
+    Ok(())
+}
+
+static MAILBOX: Queue<usize, 2> = Queue::new();
+static GOT: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+fn consume(who: usize) -> i32 {
+    let msg = MAILBOX.recv();
+    GOT[increment(&NEXT)].store(who * 10 + msg, Ordering::Relaxed);
+    0
+}
+
+fn peek_then_consume(who: usize) -> i32 {
+    let peeked = MAILBOX.peek();
+    let msg = MAILBOX.recv();
+    GOT[increment(&NEXT)].store(who * 10 + msg, Ordering::Relaxed);
+    if peeked == msg {
+        0
+    } else {
+        -1
+    }
+}
+
+/// The most urgent waiting receiver gets the message, and a peeker leaves
+/// it for the others
+pub fn queue_priority() -> TestResult {
+    NEXT.store(0, Ordering::Relaxed);
+    let low = spawn("low", DEFAULT_PRIORITY + 1, consume, 1);
+    let high = spawn("high", DEFAULT_PRIORITY + 2, peek_then_consume, 2);
+
+    MAILBOX.send(5);
+    ktest_assert!(
+        NEXT.load(Ordering::Relaxed) == 1 && GOT[0].load(Ordering::Relaxed) == 25,
+        "less urgent receiver got the message"
+    );
+    MAILBOX.send(6);
+    ktest_assert!(GOT[1].load(Ordering::Relaxed) == 16, "second message lost");
+
+    ktest_assert!(thread::join(high) == Ok(0), "peeked another message");
+    ktest_assert!(thread::join(low) == Ok(0), "join failed");
+    Ok(())
+}
+
+static IRQ_EVENTS: Queue<u64, 4> = Queue::new();
+static mut SENDER: Timer = Timer::new();
+static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+fn wait_for_event(_: usize) -> i32 {
+    let now = IRQ_EVENTS.recv();
+    RECEIVED.store(now as usize, Ordering::Relaxed);
+    0
+}
+
+fn send_event(now: u64, _arg: usize) {
+    let _ = IRQ_EVENTS.send_from_isr(now);
+}
+
+/// A message an interrupt handler sends wakes the receiver, which preempts
+/// the spinning thread
+pub fn queue_from_isr() -> TestResult {
+    RECEIVED.store(0, Ordering::Relaxed);
+    let receiver = spawn("receiver", DEFAULT_PRIORITY + 1, wait_for_event, 0);
+    unsafe { SENDER.set_oneshot(2, send_event, 0) };
+
+    let deadline = timer::current_ticks() + 100;
+    while RECEIVED.load(Ordering::Relaxed) == 0 {
+        ktest_assert!(
+            timer::current_ticks() < deadline,
+            "receiver did not preempt"
+        );
+    }
+    ktest_assert!(thread::join(receiver) == Ok(0), "join failed");
+    ktest_assert!(
+        IRQ_EVENTS.recv_from_isr() == Err(Error::Empty),
+        "message left over"
+    );
+    Ok(())
+}
+
+/// A queue on the heap works like a static one and drops what it holds
+pub fn boxed_queue() -> TestResult {
+    let queue = Queue::<Box<usize>, 64>::boxed();
+    ktest_assert!(queue.capacity() == 64, "wrong capacity");
+    for msg in 0..10 {
+        queue.send(Box::new(msg));
+    }
+    ktest_assert!(*queue.recv() == 0, "received out of order");
+    drop(queue);
+    Ok(())
+}
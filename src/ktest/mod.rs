@@ -0,0 +1,137 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! In-kernel test suite
+//!
+//! Built with the `ktest` feature. kmain() runs every test once the kernel
+//! is up and reports the result through the QEMU exit code, so the suite
+//! runs with `cargo run --features ktest` (see scripts/ktest.sh). On the
+//! hosted port it also runs as a regular `cargo test`.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::arch;
+use crate::thread::{ThreadHandle, DEFAULT_STACK_SIZE};
+
+pub type TestResult = Result<(), &'static str>;
+
+type Test = (&'static str, fn() -> TestResult);
+
+/// Fail the current test unless `cond` holds
+macro_rules! ktest_assert {
+    ($cond:expr, $msg:expr) => {
+        if !$cond {
+            return Err($msg);
+        }
+    };
+}
+
+/// Add 1 to `counter`, returns the value before
+///
+/// Threads and interrupt handlers may share the counter, interrupts are
+/// disabled instead of using read-modify-write, which ARMv6-M doesn't have.
+pub(super) fn increment(counter: &AtomicUsize) -> usize {
+    let state = arch::irq_save();
+    let value = counter.load(Ordering::Relaxed);
+    counter.store(value + 1, Ordering::Relaxed);
+    arch::irq_restore(state);
+    value
+}
+
+/// Spawn a thread and let it run
+pub(super) fn spawn(
+    name: &'static str,
+    priority: u8,
+    entry: fn(usize) -> i32,
+    arg: usize,
+) -> ThreadHandle {
+    let handle = crate::thread::spawn(name, priority, DEFAULT_STACK_SIZE, entry, arg).unwrap();
+    crate::thread::resume(handle).unwrap();
+    handle
+}
+
+mod ipc;
+mod switch;
+mod sync;
+mod thread;
+mod timer;
+mod wait;
+
+static TESTS: &[Test] = &[
+    ("ipc::queue_order", ipc::queue_order),
+    ("ipc::queue_blocking", ipc::queue_blocking),
+    ("ipc::queue_priority", ipc::queue_priority),
+    ("ipc::queue_from_isr", ipc::queue_from_isr),
+    ("ipc::boxed_queue", ipc::boxed_queue),
+    ("switch::ping_pong", switch::ping_pong),
+    ("sync::mutex_excludes", sync::mutex_excludes),
+    (
+        "sync::mutex_priority_inheritance",
+        sync::mutex_priority_inheritance,
+    ),
+    ("sync::mutex_timeout", sync::mutex_timeout),
+    ("sync::recursive_mutex", sync::recursive_mutex),
+    ("sync::ceiling_mutex", sync::ceiling_mutex),
+    ("sync::mutex_forgotten_guard", sync::mutex_forgotten_guard),
+    ("sync::semaphore_counts", sync::semaphore_counts),
+    ("sync::semaphore_from_isr", sync::semaphore_from_isr),
+    ("sync::event_any_all", sync::event_any_all),
+    ("sync::event_wakes_together", sync::event_wakes_together),
+    ("sync::condvar_notify", sync::condvar_notify),
+    ("sync::condvar_timeout", sync::condvar_timeout),
+    ("sync::condvar_spin", sync::condvar_spin),
+    ("thread::spawn_runs_entry", thread::spawn_runs_entry),
+    ("thread::spawn_errors", thread::spawn_errors),
+    ("thread::priority_order", thread::priority_order),
+    ("thread::round_robin", thread::round_robin),
+    ("thread::isr_preempts", thread::isr_preempts),
+    (
+        "thread::cpu_load_while_spinning",
+        thread::cpu_load_while_spinning,
+    ),
+    ("thread::sleep_duration", thread::sleep_duration),
+    (
+        "thread::sleepers_wake_in_order",
+        thread::sleepers_wake_in_order,
+    ),
+    ("thread::suspend_resume", thread::suspend_resume),
+    ("thread::join_exit_code", thread::join_exit_code),
+    ("thread::detached_reaped", thread::detached_reaped),
+    ("thread::state_machine", thread::state_machine),
+    ("thread::snapshot_contents", thread::snapshot_contents),
+    ("thread::stack_watermark", thread::stack_watermark),
+    ("timer::oneshot", timer::oneshot),
+    ("timer::clock_accuracy", timer::clock_accuracy),
+    ("wait::wake_order", wait::wake_order),
+    ("wait::wake_all_reason", wait::wake_all_reason),
+];
+
+/// Run all tests, returns whether every test passed
+pub fn run() -> bool {
+    let mut failed = 0;
+
+    for (name, test) in TESTS {
+        match test() {
+            Ok(()) => {
+                println!("test {} ... ok", name);
+            }
+            Err(msg) => {
+                println!("test {} ... FAILED: {}", name, msg);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("{} passed; {} failed", TESTS.len() - failed, failed);
+    failed == 0
+}
+
+#[cfg(test)]
+#[test]
+fn ktest_suite() {
+    crate::kernel_init();
+    assert!(run());
+}
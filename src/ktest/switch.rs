@@ -0,0 +1,68 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+use super::TestResult;
+use crate::arch;
+
+const ROUNDS: usize = 100;
+
+#[cfg(not(host))]
+static mut STACK: [u64; 128] = [0; 128];
+// A ucontext alone takes about 1K, and signal handlers run on this stack
+#[cfg(host)]
+static mut STACK: [u64; 8192] = [0; 8192];
+
+static mut MAIN_SP: usize = 0;
+static mut PEER_SP: usize = 0;
+static mut COUNTER: usize = 0;
+#[cfg(armv8m)]
+static mut PEER_PSPLIM: u32 = 0;
+
+extern "C" fn peer(step: usize) -> ! {
+    loop {
+        unsafe {
+            COUNTER += step;
+            #[cfg(armv8m)]
+            {
+                PEER_PSPLIM = cortex_m::register::psplim::read();
+            }
+            arch::arch_context_switch(&mut PEER_SP, MAIN_SP);
+        }
+    }
+}
+
+/// Switch back and forth between the boot stack and a second stack, the
+/// callee saved registers of both sides must survive every round trip
+pub fn ping_pong() -> TestResult {
+    unsafe {
+        let stack_base = STACK.as_ptr() as usize;
+        let stack_top = stack_base + STACK.len() * 8;
+        PEER_SP = arch::arch_init_context(stack_base, stack_top, peer, 3);
+        COUNTER = 0;
+
+        let mut local = 0;
+        for i in 0..ROUNDS {
+            local += i;
+            arch::arch_context_switch(&mut MAIN_SP, PEER_SP);
+            ktest_assert!(COUNTER == (i + 1) * 3, "peer did not run");
+        }
+
+        ktest_assert!(local == ROUNDS * (ROUNDS - 1) / 2, "registers corrupted");
+        ktest_assert!(STACK[0] == 0, "peer overflowed its stack");
+
+        #[cfg(armv8m)]
+        ktest_assert!(
+            PEER_PSPLIM as usize == stack_base,
+            "peer did not run with its own stack limit"
+        );
+        #[cfg(armv8m)]
+        ktest_assert!(
+            cortex_m::register::psplim::read() == 0,
+            "boot stack limit not restored"
+        );
+    }
+    Ok(())
+}
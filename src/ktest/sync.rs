@@ -0,0 +1,500 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+use core::cell::Cell;
+use core::mem;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use super::{increment, spawn, TestResult};
+use crate::kernel::sync::{
+    CeilingMutex, Condvar, Error, EventGroup, Mutex, RecursiveMutex, Semaphore, WaitFor,
+};
+use crate::kernel::timer::{self, Timer};
+use crate::thread::{self, ThreadHandle, DEFAULT_PRIORITY, NUM_PRIORITIES};
+
+fn priority_of(handle: ThreadHandle) -> u8 {
+    thread::info(handle).map(|info| info.priority).unwrap_or(0)
+}
+
+static COUNTER: Mutex<usize> = Mutex::new(0);
+
+fn add_slowly(rounds: usize) -> i32 {
+    for _ in 0..rounds {
+        let mut counter = COUNTER.lock();
+        let seen = *counter;
+        // Let the other thread find the mutex taken
+        thread::yield_now();
+        *counter = seen + 1;
+    }
+    0
+}
+
+/// Threads that yield while holding the mutex still don't lose updates
+pub fn mutex_excludes() -> TestResult {
+    *COUNTER.lock() = 0;
+    let a = spawn("adder", DEFAULT_PRIORITY, add_slowly, 50);
+    let b = spawn("adder", DEFAULT_PRIORITY, add_slowly, 50);
+    ktest_assert!(thread::join(a) == Ok(0), "join failed");
+    ktest_assert!(thread::join(b) == Ok(0), "join failed");
+    ktest_assert!(*COUNTER.lock() == 100, "updates lost");
+    Ok(())
+}
+
+static OUTER: Mutex<()> = Mutex::new(());
+static INNER: Mutex<()> = Mutex::new(());
+static TAKEN: [AtomicUsize; 3] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+fn log_taken(who: usize) {
+    TAKEN[increment(&NEXT)].store(who, Ordering::Relaxed);
+}
+
+/// Holds INNER until resumed
+fn hold_inner(_: usize) -> i32 {
+    let _inner = INNER.lock();
+    let _ = thread::suspend(thread::current());
+    log_taken(1);
+    0
+}
+
+/// Holds OUTER while it waits for INNER
+fn hold_outer(_: usize) -> i32 {
+    let _outer = OUTER.lock();
+    let _inner = INNER.lock();
+    log_taken(2);
+    0
+}
+
+fn want_outer(_: usize) -> i32 {
+    let _outer = OUTER.lock();
+    log_taken(3);
+    0
+}
+
+/// Holders run at the priority of the most urgent thread waiting on them,
+/// through a chain of mutexes, until they unlock
+pub fn mutex_priority_inheritance() -> TestResult {
+    NEXT.store(0, Ordering::Relaxed);
+
+    // Sleep so the less urgent threads run
+    let low = spawn("low", DEFAULT_PRIORITY - 6, hold_inner, 0);
+    thread::sleep(1);
+    ktest_assert!(INNER.try_lock().is_err(), "low does not hold INNER");
+    let mid = spawn("mid", DEFAULT_PRIORITY - 4, hold_outer, 0);
+    thread::sleep(1);
+    ktest_assert!(OUTER.try_lock().is_err(), "mid does not hold OUTER");
+    ktest_assert!(
+        priority_of(low) == DEFAULT_PRIORITY - 4,
+        "low did not inherit from mid"
+    );
+
+    let high = spawn("high", DEFAULT_PRIORITY + 2, want_outer, 0);
+    ktest_assert!(
+        priority_of(mid) == DEFAULT_PRIORITY + 2,
+        "mid did not inherit from high"
+    );
+    ktest_assert!(
+        priority_of(low) == DEFAULT_PRIORITY + 2,
+        "inheritance did not follow the chain"
+    );
+
+    // Low preempts this thread and everybody finishes before it returns
+    thread::resume(low).map_err(|_| "resume failed")?;
+    ktest_assert!(NEXT.load(Ordering::Relaxed) == 3, "threads did not finish");
+    ktest_assert!(
+        TAKEN[0].load(Ordering::Relaxed) == 1
+            && TAKEN[1].load(Ordering::Relaxed) == 2
+            && TAKEN[2].load(Ordering::Relaxed) == 3,
+        "mutexes taken out of order"
+    );
+    ktest_assert!(
+        priority_of(low) == DEFAULT_PRIORITY - 6 && priority_of(mid) == DEFAULT_PRIORITY - 4,
+        "inherited priority kept after unlocking"
+    );
+
+    for &handle in &[low, mid, high] {
+        ktest_assert!(thread::join(handle) == Ok(0), "join failed");
+    }
+    Ok(())
+}
+
+static GUARDED: Mutex<()> = Mutex::new(());
+
+fn lock_briefly(_: usize) -> i32 {
+    match (GUARDED.try_lock(), GUARDED.lock_timeout(5)) {
+        (Err(Error::WouldBlock), Err(Error::TimedOut)) => 0,
+        _ => -1,
+    }
+}
+
+/// Waiting for a mutex gives up after the timeout, and so does the
+/// inherited priority
+pub fn mutex_timeout() -> TestResult {
+    let guard = GUARDED.lock();
+    ktest_assert!(
+        GUARDED.try_lock().err() == Some(Error::Deadlock),
+        "locked a mutex twice"
+    );
+
+    let waiter = spawn("waiter", DEFAULT_PRIORITY + 1, lock_briefly, 0);
+    ktest_assert!(
+        priority_of(thread::current()) == DEFAULT_PRIORITY + 1,
+        "holder did not inherit"
+    );
+    thread::sleep(10);
+    ktest_assert!(thread::join(waiter) == Ok(0), "lock did not time out");
+    ktest_assert!(
+        priority_of(thread::current()) == DEFAULT_PRIORITY,
+        "inherited priority kept after the timeout"
+    );
+
+    drop(guard);
+    ktest_assert!(GUARDED.try_lock().is_ok(), "mutex not free");
+    Ok(())
+}
+
+static NESTED: RecursiveMutex<Cell<usize>> = RecursiveMutex::new(Cell::new(0));
+
+fn lock_nested(_: usize) -> i32 {
+    match NESTED.try_lock() {
+        Ok(_) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// The holder of a recursive mutex locks it again, others only get it once
+/// every guard is gone
+pub fn recursive_mutex() -> TestResult {
+    let outer = NESTED.lock();
+    let inner = NESTED.try_lock().map_err(|_| "relock failed")?;
+    inner.set(inner.get() + 1);
+    drop(inner);
+
+    let other = spawn("other", DEFAULT_PRIORITY + 1, lock_nested, 0);
+    ktest_assert!(
+        thread::join(other) == Ok(-1),
+        "taken while locked by another thread"
+    );
+    drop(outer);
+
+    let other = spawn("other", DEFAULT_PRIORITY + 1, lock_nested, 0);
+    ktest_assert!(thread::join(other) == Ok(0), "not freed by the last guard");
+    Ok(())
+}
+
+static CEILED: CeilingMutex<()> = CeilingMutex::new(DEFAULT_PRIORITY + 4, ());
+static CEILED_TAKEN: AtomicBool = AtomicBool::new(false);
+
+fn lock_ceiled(_: usize) -> i32 {
+    match CEILED.try_lock() {
+        Ok(_) => {
+            CEILED_TAKEN.store(true, Ordering::Relaxed);
+            0
+        }
+        Err(Error::AboveCeiling) => 1,
+        Err(_) => -1,
+    }
+}
+
+/// The holder of a ceiling mutex runs at the ceiling until it unlocks, and
+/// threads above the ceiling can't take it
+pub fn ceiling_mutex() -> TestResult {
+    CEILED_TAKEN.store(false, Ordering::Relaxed);
+    let guard = CEILED.lock();
+    ktest_assert!(
+        priority_of(thread::current()) == DEFAULT_PRIORITY + 4,
+        "holder not raised to the ceiling"
+    );
+
+    // Less urgent than the ceiling, it can't preempt the holder
+    let user = spawn("user", DEFAULT_PRIORITY + 2, lock_ceiled, 0);
+    ktest_assert!(!CEILED_TAKEN.load(Ordering::Relaxed), "holder preempted");
+
+    drop(guard);
+    ktest_assert!(
+        CEILED_TAKEN.load(Ordering::Relaxed),
+        "user did not run at the unlock"
+    );
+    ktest_assert!(
+        priority_of(thread::current()) == DEFAULT_PRIORITY,
+        "ceiling kept after unlocking"
+    );
+    ktest_assert!(thread::join(user) == Ok(0), "join failed");
+
+    let urgent = spawn("urgent", DEFAULT_PRIORITY + 6, lock_ceiled, 0);
+    ktest_assert!(
+        thread::join(urgent) == Ok(1),
+        "taken by a thread above the ceiling"
+    );
+
+    let invalid = CeilingMutex::new(NUM_PRIORITIES as u8, ());
+    ktest_assert!(
+        invalid.try_lock().err() == Some(Error::InvalidCeiling),
+        "taken with a ceiling above every priority"
+    );
+    Ok(())
+}
+
+/// Dropping a mutex whose guard was forgotten lets the holder go
+pub fn mutex_forgotten_guard() -> TestResult {
+    let mutex = CeilingMutex::new(DEFAULT_PRIORITY + 4, ());
+    mem::forget(mutex.lock());
+    ktest_assert!(
+        priority_of(thread::current()) == DEFAULT_PRIORITY + 4,
+        "holder not raised to the ceiling"
+    );
+
+    drop(mutex);
+    ktest_assert!(
+        priority_of(thread::current()) == DEFAULT_PRIORITY,
+        "ceiling kept after the mutex was dropped"
+    );
+    Ok(())
+}
+
+/// A semaphore counts the units given back, up to its maximum
+pub fn semaphore_counts() -> TestResult {
+    let sem = Semaphore::new(2, 3);
+    ktest_assert!(
+        sem.try_take().is_ok() && sem.try_take().is_ok(),
+        "units missing"
+    );
+    ktest_assert!(
+        sem.try_take() == Err(Error::WouldBlock),
+        "took a third unit"
+    );
+
+    let start = timer::current_ticks();
+    ktest_assert!(
+        sem.take_timeout(5) == Err(Error::TimedOut),
+        "take did not time out"
+    );
+    ktest_assert!(timer::current_ticks() - start >= 5, "timed out early");
+
+    for _ in 0..5 {
+        sem.give();
+    }
+    ktest_assert!(sem.count() == 3, "count above the maximum");
+    Ok(())
+}
+
+static IRQ_HAPPENED: Semaphore = Semaphore::binary();
+static mut GIVER: Timer = Timer::new();
+static SIGNALLED: AtomicBool = AtomicBool::new(false);
+
+fn wait_for_irq(_: usize) -> i32 {
+    IRQ_HAPPENED.take();
+    SIGNALLED.store(true, Ordering::Relaxed);
+    0
+}
+
+fn give_irq(_now: u64, _arg: usize) {
+    IRQ_HAPPENED.give_from_isr();
+}
+
+/// A thread waiting for an interrupt handler to give a binary semaphore
+/// preempts the spinning thread
+pub fn semaphore_from_isr() -> TestResult {
+    SIGNALLED.store(false, Ordering::Relaxed);
+    let waiter = spawn("waiter", DEFAULT_PRIORITY + 1, wait_for_irq, 0);
+    unsafe { GIVER.set_oneshot(2, give_irq, 0) };
+
+    let deadline = timer::current_ticks() + 100;
+    while !SIGNALLED.load(Ordering::Relaxed) {
+        ktest_assert!(
+            timer::current_ticks() < deadline,
+            "woken thread did not preempt"
+        );
+    }
+    ktest_assert!(thread::join(waiter) == Ok(0), "join failed");
+    ktest_assert!(IRQ_HAPPENED.count() == 0, "unit given twice");
+    Ok(())
+}
+
+/// Waiting for any or all flags of a mask, and clearing them on the way out
+pub fn event_any_all() -> TestResult {
+    let events = EventGroup::new();
+    ktest_assert!(
+        events.try_wait(0b11, WaitFor::Any, false) == Err(Error::WouldBlock),
+        "no flags set yet"
+    );
+
+    events.set(0b01);
+    ktest_assert!(
+        events.try_wait(0b11, WaitFor::Any, false) == Ok(0b01),
+        "any flag not enough"
+    );
+    ktest_assert!(
+        events.try_wait(0b11, WaitFor::All, false) == Err(Error::WouldBlock),
+        "one flag is all"
+    );
+
+    events.set(0b10);
+    ktest_assert!(
+        events.try_wait(0b11, WaitFor::All, true) == Ok(0b11),
+        "all flags not enough"
+    );
+    ktest_assert!(events.flags() == 0, "flags not cleared on exit");
+    ktest_assert!(
+        events.wait_timeout(0b100, WaitFor::Any, false, 5) == Err(Error::TimedOut),
+        "wait did not time out"
+    );
+    Ok(())
+}
+
+static EVENTS: EventGroup = EventGroup::new();
+static MATCHED: [AtomicUsize; 3] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+static NEXT_MATCH: AtomicUsize = AtomicUsize::new(0);
+
+/// Waits for the flags `arg` says, then logs which waiter it is and what
+/// matched
+fn wait_events(arg: usize) -> i32 {
+    let (mask, wait_for, clear) = match arg {
+        0 => (0b001, WaitFor::Any, true),
+        1 => (0b011, WaitFor::All, false),
+        _ => (0b100, WaitFor::Any, false),
+    };
+    let matched = EVENTS.wait(mask, wait_for, clear) as usize;
+    MATCHED[increment(&NEXT_MATCH)].store(arg << 8 | matched, Ordering::Relaxed);
+    0
+}
+
+static mut SETTER: Timer = Timer::new();
+
+fn set_events(_now: u64, _arg: usize) {
+    EVENTS.set_from_isr(0b100);
+}
+
+/// Setting flags wakes every waiter they satisfy, most urgent first, before
+/// clearing the flags
+pub fn event_wakes_together() -> TestResult {
+    EVENTS.clear(!0);
+    NEXT_MATCH.store(0, Ordering::Relaxed);
+    let waiters = [
+        spawn("any", DEFAULT_PRIORITY + 1, wait_events, 0),
+        spawn("all", DEFAULT_PRIORITY + 3, wait_events, 1),
+        spawn("other", DEFAULT_PRIORITY + 2, wait_events, 2),
+    ];
+
+    ktest_assert!(EVENTS.set(0b010) == 0b010, "flag cleared");
+    ktest_assert!(NEXT_MATCH.load(Ordering::Relaxed) == 0, "woken too early");
+    ktest_assert!(EVENTS.set(0b001) == 0b010, "flag not cleared on exit");
+    ktest_assert!(
+        NEXT_MATCH.load(Ordering::Relaxed) == 2
+            && MATCHED[0].load(Ordering::Relaxed) == 0x103
+            && MATCHED[1].load(Ordering::Relaxed) == 0x001,
+        "not woken together by priority"
+    );
+
+    unsafe { SETTER.set_oneshot(2, set_events, 0) };
+    let deadline = timer::current_ticks() + 100;
+    while NEXT_MATCH.load(Ordering::Relaxed) < 3 {
+        ktest_assert!(timer::current_ticks() < deadline, "waiter did not preempt");
+    }
+    ktest_assert!(
+        MATCHED[2].load(Ordering::Relaxed) == 0x204,
+        "wrong flags from the interrupt"
+    );
+    for &handle in &waiters {
+        ktest_assert!(thread::join(handle) == Ok(0), "join failed");
+    }
+    Ok(())
+}
+
+static TOKENS: Mutex<usize> = Mutex::new(0);
+static TOKEN_READY: Condvar = Condvar::new();
+static CONSUMED: AtomicUsize = AtomicUsize::new(0);
+
+fn consume_token(_: usize) -> i32 {
+    let mut tokens = TOKENS.lock();
+    while *tokens == 0 {
+        tokens = TOKEN_READY.wait(tokens);
+    }
+    *tokens -= 1;
+    increment(&CONSUMED);
+    0
+}
+
+/// Waiters release the mutex while they wait, and get it back to consume
+/// what they were notified of
+pub fn condvar_notify() -> TestResult {
+    CONSUMED.store(0, Ordering::Relaxed);
+    let consumers = [
+        spawn("consumer", DEFAULT_PRIORITY + 1, consume_token, 0),
+        spawn("consumer", DEFAULT_PRIORITY + 2, consume_token, 0),
+        spawn("consumer", DEFAULT_PRIORITY + 3, consume_token, 0),
+    ];
+    ktest_assert!(TOKENS.try_lock().is_ok(), "mutex held while waiting");
+
+    *TOKENS.lock() = 1;
+    TOKEN_READY.notify_one();
+    ktest_assert!(CONSUMED.load(Ordering::Relaxed) == 1, "notify_one lost");
+    ktest_assert!(
+        thread::join(consumers[2]) == Ok(0),
+        "most urgent waiter not woken first"
+    );
+
+    *TOKENS.lock() = 2;
+    TOKEN_READY.notify_all();
+    ktest_assert!(CONSUMED.load(Ordering::Relaxed) == 3, "notify_all lost");
+    for &handle in &consumers[..2] {
+        ktest_assert!(thread::join(handle) == Ok(0), "join failed");
+    }
+    Ok(())
+}
+
+/// A wait that times out still returns with the mutex held
+pub fn condvar_timeout() -> TestResult {
+    let tokens = TOKENS.lock();
+    let start = timer::current_ticks();
+    let (tokens, result) = TOKEN_READY.wait_timeout(tokens, 5);
+    ktest_assert!(result.timed_out(), "wait did not time out");
+    ktest_assert!(timer::current_ticks() - start >= 5, "timed out early");
+    ktest_assert!(
+        TOKENS.try_lock().err() == Some(Error::Deadlock),
+        "mutex not held again"
+    );
+    drop(tokens);
+    Ok(())
+}
+
+static SPIN_FLAG: spin::Mutex<bool> = spin::Mutex::new(false);
+static FLAG_SET: Condvar = Condvar::new();
+
+fn wait_flag(_: usize) -> i32 {
+    let flag = FLAG_SET.wait_spin_while(&SPIN_FLAG, |flag| !*flag);
+    drop(flag);
+    0
+}
+
+/// The spinning variant parks the waiter and releases the `spin::Mutex`
+pub fn condvar_spin() -> TestResult {
+    *SPIN_FLAG.lock() = false;
+    let waiter = spawn("waiter", DEFAULT_PRIORITY + 1, wait_flag, 0);
+    ktest_assert!(
+        thread::info(waiter).map(|info| info.state) == Ok(thread::ThreadState::Blocked),
+        "waiter not blocked"
+    );
+
+    *SPIN_FLAG.lock() = true;
+    FLAG_SET.notify_one();
+    ktest_assert!(thread::join(waiter) == Ok(0), "join failed");
+
+    let (flag, result) = FLAG_SET.wait_spin_timeout_while(&SPIN_FLAG, 5, |flag| *flag);
+    ktest_assert!(result.timed_out() && *flag, "wait did not time out");
+    drop(flag);
+    let (_, result) = FLAG_SET.wait_spin_timeout_while(&SPIN_FLAG, 5, |flag| !*flag);
+    ktest_assert!(!result.timed_out(), "timed out with the condition false");
+    Ok(())
+}
@@ -0,0 +1,510 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use super::{increment, TestResult};
+use crate::kernel::timer::{self, Timer};
+use crate::thread::{
+    self, BlockedOn, Error, ThreadHandle, ThreadState, DEFAULT_PRIORITY, DEFAULT_STACK_SIZE,
+    HIGHEST_PRIORITY, LOWEST_PRIORITY, MIN_STACK_SIZE, NUM_PRIORITIES,
+};
+
+static SEEN: AtomicUsize = AtomicUsize::new(0);
+
+fn record(arg: usize) -> i32 {
+    SEEN.store(arg, Ordering::Relaxed);
+    0
+}
+
+/// A more urgent thread runs `entry(arg)` as soon as it is resumed, and
+/// can't be resumed again once it returned
+pub fn spawn_runs_entry() -> TestResult {
+    SEEN.store(0, Ordering::Relaxed);
+    let me = thread::current();
+
+    let handle = thread::spawn(
+        "record",
+        DEFAULT_PRIORITY + 1,
+        DEFAULT_STACK_SIZE,
+        record,
+        42,
+    )
+    .map_err(|_| "spawn failed")?;
+    ktest_assert!(handle != me, "spawned thread has the id of its parent");
+    ktest_assert!(
+        SEEN.load(Ordering::Relaxed) == 0,
+        "thread ran before it was resumed"
+    );
+
+    thread::resume(handle).map_err(|_| "resume failed")?;
+    ktest_assert!(
+        SEEN.load(Ordering::Relaxed) == 42,
+        "entry did not get its argument"
+    );
+    ktest_assert!(thread::current() == me, "did not return to the parent");
+    ktest_assert!(
+        thread::resume(handle) == Err(Error::NotFound),
+        "exited thread ran again"
+    );
+    Ok(())
+}
+
+/// Bad arguments are refused, and every thread gets its own id
+pub fn spawn_errors() -> TestResult {
+    ktest_assert!(
+        thread::spawn("bad", NUM_PRIORITIES as u8, DEFAULT_STACK_SIZE, record, 0)
+            == Err(Error::InvalidPriority),
+        "priority out of range accepted"
+    );
+    ktest_assert!(
+        thread::spawn("bad", DEFAULT_PRIORITY, MIN_STACK_SIZE - 1, record, 0)
+            == Err(Error::StackTooSmall),
+        "stack too small accepted"
+    );
+
+    let a = thread::spawn("a", LOWEST_PRIORITY, MIN_STACK_SIZE, record, 0)
+        .map_err(|_| "spawn failed")?;
+    let b = thread::spawn("b", HIGHEST_PRIORITY, MIN_STACK_SIZE, record, 0)
+        .map_err(|_| "spawn failed")?;
+    ktest_assert!(a.id() != b.id(), "ids reused");
+    Ok(())
+}
+
+static ORDER: [AtomicUsize; 3] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+static RAN: AtomicUsize = AtomicUsize::new(0);
+
+fn log_order(arg: usize) -> i32 {
+    let n = increment(&RAN);
+    ORDER[n].store(arg, Ordering::Relaxed);
+    0
+}
+
+fn resume_all(_: usize) -> i32 {
+    // Resumed from least to most urgent, none of them preempts this thread
+    for &level in &[1, 2, 3] {
+        let handle = match thread::spawn(
+            "level",
+            DEFAULT_PRIORITY + level as u8,
+            DEFAULT_STACK_SIZE,
+            log_order,
+            level,
+        ) {
+            Ok(handle) => handle,
+            Err(_) => return -1,
+        };
+        if thread::resume(handle).is_err() {
+            return -1;
+        }
+    }
+    0
+}
+
+/// Ready threads run most urgent first
+pub fn priority_order() -> TestResult {
+    RAN.store(0, Ordering::Relaxed);
+
+    let starter = thread::spawn(
+        "starter",
+        HIGHEST_PRIORITY,
+        DEFAULT_STACK_SIZE,
+        resume_all,
+        0,
+    )
+    .map_err(|_| "spawn failed")?;
+    thread::resume(starter).map_err(|_| "resume failed")?;
+
+    ktest_assert!(RAN.load(Ordering::Relaxed) == 3, "threads did not run");
+    ktest_assert!(
+        ORDER[0].load(Ordering::Relaxed) == 3
+            && ORDER[1].load(Ordering::Relaxed) == 2
+            && ORDER[2].load(Ordering::Relaxed) == 1,
+        "threads ran out of priority order"
+    );
+    Ok(())
+}
+
+static COUNT: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+static SAW_OTHER: [AtomicBool; 2] = [AtomicBool::new(false), AtomicBool::new(false)];
+static DONE: AtomicUsize = AtomicUsize::new(0);
+static END: AtomicUsize = AtomicUsize::new(0);
+
+fn spin_until_end(me: usize) -> i32 {
+    let other = COUNT[1 - me].load(Ordering::Relaxed);
+    while (timer::current_ticks() as usize) < END.load(Ordering::Relaxed) {
+        increment(&COUNT[me]);
+    }
+    SAW_OTHER[me].store(
+        COUNT[1 - me].load(Ordering::Relaxed) != other,
+        Ordering::Relaxed,
+    );
+    increment(&DONE);
+    0
+}
+
+/// Threads of the same priority share the core, none of them yields
+pub fn round_robin() -> TestResult {
+    let slice = thread::timeslice() as usize;
+    let start = timer::current_ticks() as usize;
+    END.store(start + 5 * slice, Ordering::Relaxed);
+    DONE.store(0, Ordering::Relaxed);
+    for i in 0..2 {
+        COUNT[i].store(0, Ordering::Relaxed);
+        SAW_OTHER[i].store(false, Ordering::Relaxed);
+    }
+
+    for i in 0..2 {
+        let handle = thread::spawn(
+            "spinner",
+            DEFAULT_PRIORITY,
+            DEFAULT_STACK_SIZE,
+            spin_until_end,
+            i,
+        )
+        .map_err(|_| "spawn failed")?;
+        thread::resume(handle).map_err(|_| "resume failed")?;
+    }
+
+    while DONE.load(Ordering::Relaxed) < 2 {
+        ktest_assert!(
+            (timer::current_ticks() as usize) < start + 20 * slice,
+            "equal priority threads starved"
+        );
+    }
+    ktest_assert!(
+        SAW_OTHER[0].load(Ordering::Relaxed) && SAW_OTHER[1].load(Ordering::Relaxed),
+        "threads did not take turns"
+    );
+    Ok(())
+}
+
+static mut WAKER: Timer = Timer::new();
+static mut SLEEPER: Option<ThreadHandle> = None;
+static WOKEN: AtomicBool = AtomicBool::new(false);
+
+fn note_wakeup(_: usize) -> i32 {
+    WOKEN.store(true, Ordering::Relaxed);
+    0
+}
+
+fn wake_sleeper(_now: u64, _arg: usize) {
+    unsafe {
+        let _ = thread::resume_from_isr(SLEEPER.unwrap());
+    }
+}
+
+/// A thread an interrupt handler resumes preempts the spinning thread
+pub fn isr_preempts() -> TestResult {
+    WOKEN.store(false, Ordering::Relaxed);
+
+    let handle = thread::spawn(
+        "sleeper",
+        DEFAULT_PRIORITY + 1,
+        DEFAULT_STACK_SIZE,
+        note_wakeup,
+        0,
+    )
+    .map_err(|_| "spawn failed")?;
+    unsafe {
+        SLEEPER = Some(handle);
+        WAKER.set_oneshot(2, wake_sleeper, 0);
+    }
+
+    let deadline = timer::current_ticks() + 100;
+    while !WOKEN.load(Ordering::Relaxed) {
+        ktest_assert!(
+            timer::current_ticks() < deadline,
+            "resumed thread did not preempt"
+        );
+    }
+    Ok(())
+}
+
+/// A thread that never blocks keeps the idle thread, and its ticks, away
+pub fn cpu_load_while_spinning() -> TestResult {
+    let idle_before = thread::idle_ticks();
+
+    // The load covers the last complete second, spin through two
+    let end = timer::current_ticks() + 2 * timer::TICK_RATE_HZ as u64 + 1;
+    while timer::current_ticks() < end {}
+
+    ktest_assert!(
+        thread::cpu_load() == 100,
+        "spinning thread not counted as busy"
+    );
+    ktest_assert!(
+        thread::idle_ticks() == idle_before,
+        "idle thread ran while a thread was ready"
+    );
+    Ok(())
+}
+
+/// Sleeping takes at least the time asked for, and not much more
+pub fn sleep_duration() -> TestResult {
+    let start = timer::current_ticks();
+    thread::sleep(10);
+    let slept = timer::current_ticks() - start;
+    ktest_assert!(slept >= 10, "woke up early");
+    ktest_assert!(slept <= 12, "woke up late");
+
+    let start = timer::current_ticks();
+    thread::sleep_until(start);
+    ktest_assert!(
+        timer::current_ticks() == start,
+        "slept until a deadline that passed"
+    );
+    Ok(())
+}
+
+static WAKEUPS: [AtomicUsize; 3] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+static WOKE: AtomicUsize = AtomicUsize::new(0);
+static DEADLINE: AtomicUsize = AtomicUsize::new(0);
+
+fn sleep_then_log(delay: usize) -> i32 {
+    thread::sleep_until((DEADLINE.load(Ordering::Relaxed) + delay) as u64);
+    let n = increment(&WOKE);
+    WAKEUPS[n].store(delay, Ordering::Relaxed);
+    0
+}
+
+/// Sleepers wake up by deadline, not in the order they went to sleep, and
+/// the idle thread runs meanwhile
+pub fn sleepers_wake_in_order() -> TestResult {
+    WOKE.store(0, Ordering::Relaxed);
+    DEADLINE.store(timer::current_ticks() as usize, Ordering::Relaxed);
+    let idle_before = thread::idle_ticks();
+
+    let mut handles = [None; 3];
+    for (handle, &delay) in handles.iter_mut().zip(&[6, 2, 4]) {
+        let spawned = thread::spawn(
+            "sleeper",
+            DEFAULT_PRIORITY + 1,
+            DEFAULT_STACK_SIZE,
+            sleep_then_log,
+            delay,
+        )
+        .map_err(|_| "spawn failed")?;
+        thread::resume(spawned).map_err(|_| "resume failed")?;
+        *handle = Some(spawned);
+    }
+    for handle in handles.iter().filter_map(|handle| *handle) {
+        ktest_assert!(thread::join(handle) == Ok(0), "join failed");
+    }
+
+    ktest_assert!(
+        WAKEUPS[0].load(Ordering::Relaxed) == 2
+            && WAKEUPS[1].load(Ordering::Relaxed) == 4
+            && WAKEUPS[2].load(Ordering::Relaxed) == 6,
+        "sleepers woke up out of order"
+    );
+    ktest_assert!(
+        thread::idle_ticks() > idle_before,
+        "idle thread did not run while everybody slept"
+    );
+    Ok(())
+}
+
+static STEPS: AtomicUsize = AtomicUsize::new(0);
+
+fn suspend_self(_: usize) -> i32 {
+    increment(&STEPS);
+    let _ = thread::suspend(thread::current());
+    increment(&STEPS);
+    0
+}
+
+fn count_step(_: usize) -> i32 {
+    increment(&STEPS);
+    0
+}
+
+/// Suspended threads, running or ready when suspended, only go on once
+/// they are resumed
+pub fn suspend_resume() -> TestResult {
+    STEPS.store(0, Ordering::Relaxed);
+    let handle = thread::spawn(
+        "suspender",
+        DEFAULT_PRIORITY + 1,
+        DEFAULT_STACK_SIZE,
+        suspend_self,
+        0,
+    )
+    .map_err(|_| "spawn failed")?;
+    thread::resume(handle).map_err(|_| "resume failed")?;
+    ktest_assert!(
+        STEPS.load(Ordering::Relaxed) == 1,
+        "thread did not suspend itself"
+    );
+    thread::resume(handle).map_err(|_| "resume failed")?;
+    ktest_assert!(STEPS.load(Ordering::Relaxed) == 2, "thread did not go on");
+    ktest_assert!(thread::join(handle) == Ok(0), "join failed");
+
+    // Same priority, so it is ready but doesn't run until this one yields
+    STEPS.store(0, Ordering::Relaxed);
+    let handle = thread::spawn("ready", DEFAULT_PRIORITY, DEFAULT_STACK_SIZE, count_step, 0)
+        .map_err(|_| "spawn failed")?;
+    thread::resume(handle).map_err(|_| "resume failed")?;
+    thread::suspend(handle).map_err(|_| "suspend failed")?;
+    thread::yield_now();
+    ktest_assert!(STEPS.load(Ordering::Relaxed) == 0, "suspended thread ran");
+    ktest_assert!(
+        thread::suspend(handle) == Err(Error::InvalidState),
+        "suspended twice"
+    );
+    thread::resume(handle).map_err(|_| "resume failed")?;
+    ktest_assert!(thread::join(handle) == Ok(0), "join failed");
+    ktest_assert!(
+        STEPS.load(Ordering::Relaxed) == 1,
+        "resumed thread did not run"
+    );
+    Ok(())
+}
+
+fn exit_early(code: usize) -> i32 {
+    thread::exit(code as i32);
+}
+
+/// `join()` returns the exit code once, and refuses what would never end
+pub fn join_exit_code() -> TestResult {
+    let handle = thread::spawn("exit", DEFAULT_PRIORITY, DEFAULT_STACK_SIZE, exit_early, 9)
+        .map_err(|_| "spawn failed")?;
+    thread::resume(handle).map_err(|_| "resume failed")?;
+    ktest_assert!(thread::join(handle) == Ok(9), "wrong exit code");
+    ktest_assert!(thread::join(handle) == Err(Error::NotFound), "joined twice");
+    ktest_assert!(
+        thread::join(thread::current()) == Err(Error::InvalidState),
+        "joined itself"
+    );
+    Ok(())
+}
+
+/// A detached thread disappears once it exited and the idle thread ran
+pub fn detached_reaped() -> TestResult {
+    let handle = thread::spawn(
+        "detached",
+        DEFAULT_PRIORITY + 1,
+        DEFAULT_STACK_SIZE,
+        record,
+        0,
+    )
+    .map_err(|_| "spawn failed")?;
+    thread::detach(handle).map_err(|_| "detach failed")?;
+    ktest_assert!(
+        thread::join(handle) == Err(Error::InvalidState),
+        "joined a detached thread"
+    );
+    thread::resume(handle).map_err(|_| "resume failed")?;
+
+    thread::sleep(2);
+    ktest_assert!(
+        thread::detach(handle) == Err(Error::NotFound),
+        "dead thread not reaped"
+    );
+    Ok(())
+}
+
+/// Threads only change state along the state machine
+pub fn state_machine() -> TestResult {
+    use self::ThreadState::*;
+
+    ktest_assert!(Suspended.can_become(Ready), "can't resume");
+    ktest_assert!(Running.can_become(Sleeping), "can't sleep");
+    ktest_assert!(Sleeping.can_become(Ready), "can't wake up");
+    ktest_assert!(Ready.can_become(Suspended), "can't suspend");
+    ktest_assert!(!Sleeping.can_become(Running), "ran without a run queue");
+    ktest_assert!(!Blocked.can_become(Death), "exited while blocked");
+    ktest_assert!(!Death.can_become(Ready), "came back from the dead");
+    Ok(())
+}
+
+fn nap(ticks: usize) -> i32 {
+    thread::sleep(ticks as u64);
+    0
+}
+
+/// Snapshots show what every thread is doing
+pub fn snapshot_contents() -> TestResult {
+    let handle = thread::spawn("napper", DEFAULT_PRIORITY + 1, DEFAULT_STACK_SIZE, nap, 20)
+        .map_err(|_| "spawn failed")?;
+    thread::resume(handle).map_err(|_| "resume failed")?;
+
+    let threads = thread::snapshot();
+    let napper = threads
+        .iter()
+        .find(|info| info.id == handle.id())
+        .ok_or("thread missing from the snapshot")?;
+    ktest_assert!(napper.name == "napper", "wrong name");
+    ktest_assert!(napper.priority == DEFAULT_PRIORITY + 1, "wrong priority");
+    ktest_assert!(napper.state == ThreadState::Sleeping, "wrong state");
+    match napper.blocked_on {
+        BlockedOn::Sleep(_) => {}
+        _ => return Err("sleep not reported"),
+    }
+    ktest_assert!(napper.context_switches == 1, "wrong switch count");
+    ktest_assert!(
+        napper.stack_size >= DEFAULT_STACK_SIZE
+            && napper.stack_used > 0
+            && napper.stack_used < napper.stack_size,
+        "wrong stack usage"
+    );
+    ktest_assert!(
+        thread::info(handle) == Ok(*napper),
+        "info differs from the snapshot"
+    );
+
+    let me = threads
+        .iter()
+        .find(|info| info.id == thread::current().id())
+        .ok_or("caller missing from the snapshot")?;
+    ktest_assert!(me.state == ThreadState::Running, "caller not running");
+    ktest_assert!(me.cpu_ticks > 0, "caller ran for no time");
+    ktest_assert!(
+        threads.iter().any(|info| info.name == "idle"),
+        "idle thread missing"
+    );
+
+    ktest_assert!(thread::join(handle) == Ok(0), "join failed");
+    Ok(())
+}
+
+fn dig(depth: usize) -> i32 {
+    // Volatile, so the buffer is really on the stack
+    let mut buffer = [0u8; 512];
+    for byte in buffer.iter_mut().take(depth) {
+        unsafe { core::ptr::write_volatile(byte, 1) };
+    }
+    let _ = thread::suspend(thread::current());
+    0
+}
+
+/// The watermark tells how deep a thread went into its painted stack
+pub fn stack_watermark() -> TestResult {
+    let handle = thread::spawn("digger", DEFAULT_PRIORITY + 1, DEFAULT_STACK_SIZE, dig, 512)
+        .map_err(|_| "spawn failed")?;
+    let fresh = thread::stack_high_watermark(handle).map_err(|_| "no watermark")?;
+    thread::resume(handle).map_err(|_| "resume failed")?;
+    let peak = thread::stack_high_watermark(handle).map_err(|_| "no watermark")?;
+
+    // The initial context is already on the stack
+    ktest_assert!(fresh > 0, "initial context missing from the watermark");
+    ktest_assert!(peak >= fresh + 512, "buffer missing from the watermark");
+    ktest_assert!(peak < DEFAULT_STACK_SIZE, "stack looks full");
+    let info = thread::info(handle).map_err(|_| "no info")?;
+    ktest_assert!(info.stack_peak == peak, "info disagrees with the watermark");
+    ktest_assert!(info.stack_used <= peak, "used more than the peak");
+
+    thread::resume(handle).map_err(|_| "resume failed")?;
+    ktest_assert!(thread::join(handle) == Ok(0), "join failed");
+    Ok(())
+}
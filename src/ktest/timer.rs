@@ -0,0 +1,94 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(cortex_m)]
+use cortex_m_semihosting::syscall;
+
+use super::TestResult;
+use crate::kernel::idle;
+use crate::kernel::timer::{self, Timer};
+
+static FIRED: AtomicUsize = AtomicUsize::new(0);
+
+fn count(_now: u64, _arg: usize) {
+    // Only the tick interrupt writes FIRED, so this needs no
+    // read-modify-write, which ARMv6-M doesn't have.
+    FIRED.store(FIRED.load(Ordering::SeqCst) + 1, Ordering::SeqCst);
+}
+
+/// Host time in milliseconds, from the semihosting SYS_CLOCK call
+#[cfg(cortex_m)]
+fn host_ms() -> u64 {
+    unsafe { syscall!(CLOCK) as u64 * 10 }
+}
+
+#[cfg(any(arm64, riscv))]
+fn host_ms() -> u64 {
+    crate::arch::semihosting::sys_clock() * 10
+}
+
+/// Wall clock time in milliseconds
+#[cfg(host)]
+fn host_ms() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+pub fn oneshot() -> TestResult {
+    FIRED.store(0, Ordering::SeqCst);
+
+    let mut t = Timer::new();
+    let start = timer::current_ticks();
+    unsafe { t.set_oneshot(timer::ms_to_ticks(50), count, 0) };
+    ktest_assert!(t.is_armed(), "timer not armed");
+
+    while FIRED.load(Ordering::SeqCst) == 0 {
+        idle::idle();
+    }
+
+    let elapsed = timer::current_ticks() - start;
+    ktest_assert!(!t.is_armed(), "one shot timer still armed");
+    ktest_assert!(elapsed >= timer::ms_to_ticks(50), "timer fired early");
+    ktest_assert!(elapsed <= timer::ms_to_ticks(50) + 1, "timer fired late");
+    Ok(())
+}
+
+/// Idle through several long timer intervals and check that the kernel
+/// clock agrees with the host. In tickless mode this exercises the clock
+/// correction after each long sleep.
+pub fn clock_accuracy() -> TestResult {
+    const PERIOD_MS: u64 = 700;
+    const ROUNDS: usize = 4;
+
+    FIRED.store(0, Ordering::SeqCst);
+
+    let mut t = Timer::new();
+    let host_start = host_ms();
+    let start = timer::current_ticks();
+    unsafe { t.set_periodic(timer::ms_to_ticks(PERIOD_MS), count, 0) };
+
+    while FIRED.load(Ordering::SeqCst) < ROUNDS {
+        idle::idle();
+    }
+    t.cancel();
+
+    let kernel = timer::ticks_to_ms(timer::current_ticks() - start);
+    let host = host_ms() - host_start;
+    let expected = PERIOD_MS * ROUNDS as u64;
+
+    ktest_assert!(kernel >= expected, "kernel clock behind its timers");
+    ktest_assert!(kernel <= expected + 2, "timers fired late");
+    // SYS_CLOCK has a resolution of 10ms, allow 5% on top
+    let drift = if kernel > host { kernel - host } else { host - kernel };
+    ktest_assert!(drift <= 20 + expected / 20, "kernel clock drifted from host clock");
+    Ok(())
+}
@@ -0,0 +1,125 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{increment, TestResult};
+use crate::kernel::irq::IrqLock;
+use crate::kernel::wait::{self, BlockedOn, WaitQueue, WakeReason};
+use crate::thread::{self, ThreadHandle, DEFAULT_PRIORITY, DEFAULT_STACK_SIZE};
+
+static BY_PRIORITY: IrqLock<WaitQueue> = IrqLock::new(WaitQueue::new());
+static BY_ARRIVAL: IrqLock<WaitQueue> = IrqLock::new(WaitQueue::fifo());
+
+static WOKEN: [AtomicUsize; 3] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+fn encode(reason: WakeReason) -> usize {
+    match reason {
+        WakeReason::Woken => 0,
+        WakeReason::TimedOut => 1,
+        WakeReason::Deleted => 2,
+    }
+}
+
+/// Waits on the queue `arg` picks, then logs its number and wake reason
+fn waiter(arg: usize) -> i32 {
+    let queue = if arg & 0x100 != 0 {
+        &BY_ARRIVAL
+    } else {
+        &BY_PRIORITY
+    };
+    let reason = wait::wait(queue.lock(), |queue| queue, None, BlockedOn::Nothing);
+    let entry = (arg & 0xff) | encode(reason) << 8;
+    WOKEN[increment(&NEXT)].store(entry, Ordering::Relaxed);
+    0
+}
+
+/// Spawn waiters more urgent than this thread, so they block right away
+fn spawn_waiters(queue: usize) -> [ThreadHandle; 3] {
+    NEXT.store(0, Ordering::Relaxed);
+    let mut handles = [thread::current(); 3];
+    for (i, &boost) in [1, 3, 2].iter().enumerate() {
+        let handle = thread::spawn(
+            "waiter",
+            DEFAULT_PRIORITY + boost,
+            DEFAULT_STACK_SIZE,
+            waiter,
+            queue | i,
+        )
+        .unwrap();
+        thread::resume(handle).unwrap();
+        handles[i] = handle;
+    }
+    handles
+}
+
+fn woken_order() -> [usize; 3] {
+    [
+        WOKEN[0].load(Ordering::Relaxed),
+        WOKEN[1].load(Ordering::Relaxed),
+        WOKEN[2].load(Ordering::Relaxed),
+    ]
+}
+
+fn wake_each(queue: &IrqLock<WaitQueue>) {
+    for _ in 0..3 {
+        let mut queue = queue.lock();
+        let preempt = queue.wake_one(WakeReason::Woken);
+        drop(queue);
+        wait::preempt(preempt);
+    }
+}
+
+/// A queue wakes its most urgent waiter first, or the first to come
+pub fn wake_order() -> TestResult {
+    let handles = spawn_waiters(0);
+    ktest_assert!(NEXT.load(Ordering::Relaxed) == 0, "waiter did not block");
+    wake_each(&BY_PRIORITY);
+    ktest_assert!(woken_order() == [1, 2, 0], "not woken by priority");
+    for &handle in &handles {
+        ktest_assert!(thread::join(handle) == Ok(0), "join failed");
+    }
+
+    let handles = spawn_waiters(0x100);
+    wake_each(&BY_ARRIVAL);
+    ktest_assert!(woken_order() == [0, 1, 2], "not woken in order");
+    for &handle in &handles {
+        ktest_assert!(thread::join(handle) == Ok(0), "join failed");
+    }
+    Ok(())
+}
+
+/// Every waiter gets the reason it was woken up with, and waiting times out
+pub fn wake_all_reason() -> TestResult {
+    let handles = spawn_waiters(0);
+    let mut queue = BY_PRIORITY.lock();
+    let preempt = queue.delete();
+    drop(queue);
+    ktest_assert!(preempt, "woken waiters don't preempt");
+    wait::preempt(preempt);
+    ktest_assert!(
+        woken_order() == [0x201, 0x202, 0x200],
+        "not all woken as deleted"
+    );
+    for &handle in &handles {
+        ktest_assert!(thread::join(handle) == Ok(0), "join failed");
+    }
+
+    let reason = wait::wait(
+        BY_PRIORITY.lock(),
+        |queue| queue,
+        Some(5),
+        BlockedOn::Nothing,
+    );
+    ktest_assert!(reason == WakeReason::TimedOut, "wait did not time out");
+    ktest_assert!(BY_PRIORITY.lock().is_empty(), "timed out waiter queued");
+    Ok(())
+}
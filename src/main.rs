@@ -4,42 +4,92 @@
 // license that can be found in the LICENSE file or at
 // https://opensource.org/licenses/MIT
 
-#![no_main]
-#![no_std]
+#![feature(const_generics)]
+#![feature(global_asm)]
+#![cfg_attr(not(host), feature(alloc_error_handler))]
+#![cfg_attr(not(host), no_main)]
+#![cfg_attr(not(host), no_std)]
 
-//extern crate alloc;
+extern crate alloc;
 extern crate spin;
 
+#[cfg(not(host))]
 extern crate panic_halt;
 
+#[macro_use]
+mod console;
+
 mod arch;
 
 mod mm;
 
-pub mod allocator;
+pub mod kernel;
 
-//pub mod thread;
+pub mod allocator;
 
-//#[global_allocator]
-//static ALLOCATOR: allocator::Allocator = allocator::Allocator;
+#[cfg(any(feature = "ktest", test))]
+mod ktest;
 
-use cortex_m_semihosting::{debug, hprintln};
+pub mod thread;
 
-pub fn kmain() -> ! {
-    arch::arch_early_init();
+// The hosted port keeps the allocator of the process, which std needs
+// before the kernel is up
+#[cfg(not(host))]
+#[global_allocator]
+static ALLOCATOR: allocator::Allocator = allocator::Allocator;
 
-    //allocator::heap_init();
+#[cfg(not(host))]
+#[alloc_error_handler]
+fn alloc_error(layout: core::alloc::Layout) -> ! {
+    panic!("out of memory allocating {} bytes", layout.size())
+}
 
-    //thread::thread_early_init();
+/// Bring up the architecture, the heap and the kernel services
+fn kernel_init() {
+    arch::arch_early_init();
 
-    hprintln!("Welcome to Particle!").unwrap();
+    println!("Welcome to Particle!");
 
+    #[cfg(novm)]
     unsafe {
         mm::novm::novm_init();
     }
+    #[cfg(host)]
+    unsafe {
+        mm::host::host_init();
+    }
+
+    thread::thread_early_init();
+
+    kernel::timer::timer_init();
+}
+
+#[cfg_attr(test, allow(dead_code))]
+#[cfg_attr(cortex_m, particle_macros::entry)]
+pub fn kmain() -> ! {
+    kernel_init();
+
+    #[cfg(feature = "ktest")]
+    let success = ktest::run();
+    #[cfg(not(feature = "ktest"))]
+    let success = true;
+
+    // The non-secure image decides when the system is done, unless the
+    // secure side already failed
+    #[cfg(feature = "trustzone")]
+    {
+        if !success {
+            arch::arch_exit(false);
+        }
+        arch::tz::tz_init();
+        unsafe { arch::tz::tz_boot_nonsecure() }
+    }
 
-    // NOTE do not run this on hardware; it can corrupt OpenOCD state
-    debug::exit(debug::EXIT_SUCCESS);
+    arch::arch_exit(success)
+}
 
-    loop {}
+/// The hosted port starts as a regular process
+#[cfg(all(host, not(test)))]
+fn main() {
+    kmain()
 }
@@ -0,0 +1,24 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+use crate::allocator;
+
+/// Size of the block of host memory the hosted port uses as its heap
+const HEAP_SIZE: usize = 4 << 20;
+
+#[repr(align(4096))]
+struct Heap([u8; HEAP_SIZE]);
+
+static mut HEAP: Heap = Heap([0; HEAP_SIZE]);
+
+pub unsafe fn host_init() {
+    let mem_start = HEAP.0.as_mut_ptr() as usize;
+    let mem_end = mem_start + HEAP_SIZE;
+
+    println!("start={:X}, end={:X}, size={}", mem_start, mem_end, HEAP_SIZE);
+
+    allocator::heap_init(mem_start, HEAP_SIZE);
+}
@@ -6,3 +6,6 @@
 
 #[cfg(novm)]
 pub mod novm;
+
+#[cfg(host)]
+pub mod host;
@@ -7,8 +7,6 @@
 use crate::arch::PAGE_SIZE_SHIFT;
 use crate::allocator;
 
-use cortex_m_semihosting::{hprintln};
-
 pub unsafe fn novm_init() {
     extern "C" {
         static __end: usize;
@@ -19,7 +17,7 @@ pub unsafe fn novm_init() {
     let mem_end = &__end_of_ram as *const usize as usize;
     let mem_size = mem_end - mem_start;
 
-    hprintln!("start={:X}, end={:X}, size={}", mem_start, mem_end, mem_size);
+    println!("start={:X}, end={:X}, size={}", mem_start, mem_end, mem_size);
 
     allocator::heap_init(mem_start, mem_size);
 }
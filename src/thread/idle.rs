@@ -0,0 +1,114 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! The idle thread
+//!
+//! It runs at the lowest priority, so it only gets the core when no other
+//! thread is ready. Every round it runs the idle hooks, then waits for an
+//! interrupt with `kernel::idle::idle()` unless a thread of its priority is
+//! ready.
+//!
+//! Each tick is charged to the thread it interrupted. The ticks charged to
+//! the idle thread give the CPU load.
+
+#![deny(warnings)]
+
+use super::sched;
+use super::Error;
+use crate::arch;
+use crate::kernel::idle;
+use crate::kernel::irq::IrqLock;
+use crate::kernel::timer::TICK_RATE_HZ;
+
+/// Work for the idle thread: housekeeping, power management
+///
+/// Hooks run with interrupts enabled, and must return without blocking.
+pub type IdleHook = fn();
+
+pub const MAX_IDLE_HOOKS: usize = 8;
+
+static IDLE_HOOKS: IrqLock<[Option<IdleHook>; MAX_IDLE_HOOKS]> =
+    IrqLock::new([None; MAX_IDLE_HOOKS]);
+
+/// The CPU load is measured over windows of this many ticks
+const LOAD_WINDOW: u64 = TICK_RATE_HZ as u64;
+
+struct Load {
+    /// Ticks charged to the idle thread since `thread_early_init()`
+    idle_ticks: u64,
+    /// Ticks of the current window, and how many of them were idle
+    window_ticks: u64,
+    window_idle: u64,
+    /// Busy percentage of the last complete window
+    load: u32,
+}
+
+static LOAD: IrqLock<Load> = IrqLock::new(Load {
+    idle_ticks: 0,
+    window_ticks: 0,
+    window_idle: 0,
+    load: 0,
+});
+
+/// Run `hook` on every round of the idle thread
+pub fn idle_hook_register(hook: IdleHook) -> Result<(), Error> {
+    let mut hooks = IDLE_HOOKS.lock();
+    match hooks.iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => {
+            *slot = Some(hook);
+            Ok(())
+        }
+        None => Err(Error::NoSpace),
+    }
+}
+
+/// Ticks the idle thread ran since the kernel started
+pub fn idle_ticks() -> u64 {
+    LOAD.lock().idle_ticks
+}
+
+/// Percentage of the last second the CPU was busy, 0 during the first one
+pub fn cpu_load() -> u32 {
+    LOAD.lock().load
+}
+
+/// Charge `ticks` to the idle time if the idle thread ran, called from the
+/// tick interrupt
+pub(super) fn account(ticks: u64, idle: bool) {
+    let mut load = LOAD.lock();
+    load.window_ticks += ticks;
+    if idle {
+        load.idle_ticks += ticks;
+        load.window_idle += ticks;
+    }
+
+    if load.window_ticks >= LOAD_WINDOW {
+        load.load = (100 - load.window_idle * 100 / load.window_ticks) as u32;
+        load.window_ticks = 0;
+        load.window_idle = 0;
+    }
+}
+
+pub(super) fn idle_thread(_: usize) -> i32 {
+    loop {
+        // Run the hooks unlocked, they may register more
+        let hooks = *IDLE_HOOKS.lock();
+        for hook in hooks.iter().filter_map(|hook| *hook) {
+            hook();
+        }
+
+        // A thread made ready after the check wakes the core up
+        let state = arch::irq_save();
+        if !sched::has_ready() {
+            idle::idle();
+        }
+        arch::irq_restore(state);
+
+        // Let the threads the interrupts woke up run, or the ones sharing
+        // the lowest priority
+        sched::yield_current();
+    }
+}
@@ -0,0 +1,152 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! What the threads are up to
+//!
+//! `snapshot()` copies the state of every thread, `ps()` prints it. For
+//! debuggers, which can't walk `THREAD_LIST`, `PARTICLE_THREADS` keeps the
+//! address of every control block at a fixed symbol.
+
+#![deny(warnings)]
+
+use alloc::vec::Vec;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::Ordering;
+
+use super::thread::{BlockedOn, Thread, ThreadId, ThreadState};
+use super::{thread_list, Error, ThreadHandle, CURRENT_THREAD};
+
+/// Threads past this many are missing from `PARTICLE_THREADS`
+pub const DEBUG_TABLE_SIZE: usize = 32;
+
+/// The control blocks of the threads, free slots are null
+///
+/// Only written with the thread list locked, a debugger reads it while the
+/// core is halted.
+#[no_mangle]
+static mut PARTICLE_THREADS: [*const Thread; DEBUG_TABLE_SIZE] = [ptr::null(); DEBUG_TABLE_SIZE];
+
+/// A copy of the state of a thread
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ThreadInfo {
+    pub id: ThreadId,
+    pub name: &'static str,
+    /// The priority it runs at, above `base_priority` while it holds a
+    /// lock a more urgent thread waits for
+    pub priority: u8,
+    pub base_priority: u8,
+    pub state: ThreadState,
+    pub blocked_on: BlockedOn,
+    /// Bytes of stack, 0 for the bootstrap thread and the threads whose
+    /// stack was freed
+    pub stack_size: usize,
+    /// Bytes of stack in use when the thread last stopped running, or now
+    /// for the thread taking the snapshot
+    pub stack_used: usize,
+    /// The most bytes of stack the thread ever used
+    pub stack_peak: usize,
+    /// Ticks the thread ran
+    pub cpu_ticks: u64,
+    /// How many times the thread was switched to
+    pub context_switches: u64,
+}
+
+impl ThreadInfo {
+    fn of(thread: &Thread) -> ThreadInfo {
+        let (stack_size, stack_used) = match thread.stack_bounds() {
+            Some((base, top)) => {
+                let current = CURRENT_THREAD.load(Ordering::Relaxed) as *const Thread;
+                let sp = if thread as *const Thread == current {
+                    stack_pointer()
+                } else {
+                    thread.sp
+                };
+                let used = if sp > base && sp <= top { top - sp } else { 0 };
+                (top - base, used)
+            }
+            None => (0, 0),
+        };
+
+        ThreadInfo {
+            id: thread.id,
+            name: thread.name,
+            priority: thread.priority,
+            base_priority: thread.base_priority,
+            state: thread.state,
+            blocked_on: thread.blocked_on,
+            stack_size,
+            stack_used,
+            stack_peak: thread.stack_peak(),
+            cpu_ticks: thread.cpu_ticks,
+            context_switches: thread.switches,
+        }
+    }
+}
+
+/// Roughly the stack pointer of the caller
+#[inline(never)]
+fn stack_pointer() -> usize {
+    let marker = 0u8;
+    &marker as *const u8 as usize
+}
+
+/// The state of every thread, in the order they were created
+pub fn snapshot() -> Vec<ThreadInfo> {
+    let list = thread_list().lock();
+    list.iter()
+        .map(|thread| ThreadInfo::of(unsafe { thread.as_ref() }))
+        .collect()
+}
+
+/// The state of one thread
+pub fn info(handle: ThreadHandle) -> Result<ThreadInfo, Error> {
+    let list = thread_list().lock();
+    let thread = list.get(handle.id).ok_or(Error::NotFound)?;
+    Ok(ThreadInfo::of(unsafe { thread.as_ref() }))
+}
+
+/// Print a line per thread on the console
+pub fn ps() {
+    println!(
+        "{:>4} {:<16} {:>3} {:<9} {:<17} {:>10} {:>8} BLOCKED ON",
+        "ID", "NAME", "PRI", "STATE", "USED/PEAK/SIZE", "TICKS", "SWITCHES"
+    );
+    for info in snapshot() {
+        println!(
+            "{:>4} {:<16} {:>3} {:<9} {:>5}/{:>5}/{:<5} {:>10} {:>8} {}",
+            info.id,
+            info.name,
+            info.priority,
+            info.state,
+            info.stack_used,
+            info.stack_peak,
+            info.stack_size,
+            info.cpu_ticks,
+            info.context_switches,
+            info.blocked_on
+        );
+    }
+}
+
+/// List a new control block in `PARTICLE_THREADS`
+pub(super) fn debug_table_insert(thread: NonNull<Thread>) {
+    unsafe {
+        if let Some(slot) = PARTICLE_THREADS.iter_mut().find(|slot| slot.is_null()) {
+            *slot = thread.as_ptr();
+        }
+    }
+}
+
+/// Forget a control block about to be freed
+pub(super) fn debug_table_remove(thread: NonNull<Thread>) {
+    unsafe {
+        for slot in PARTICLE_THREADS.iter_mut() {
+            if *slot == thread.as_ptr() as *const Thread {
+                *slot = ptr::null();
+            }
+        }
+    }
+}
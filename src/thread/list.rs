@@ -6,11 +6,99 @@
 
 #![deny(warnings)]
 
-use alloc::sync::Arc;
+use alloc::boxed::Box;
 use alloc::collections::VecDeque;
-use spin::RwLock;
+use core::ptr::NonNull;
 
-use super::thread::Thread;
+use super::info;
+use super::thread::{Thread, ThreadId, ThreadState};
 
-/// Thread list type
-pub type ThreadList = VecDeque<(usize, Arc<RwLock<Thread>>)>;
+/// Every thread of the kernel, which owns their control blocks
+///
+/// Control blocks don't move once inserted, the scheduler links them by
+/// address. The list is only touched with interrupts disabled.
+pub struct ThreadList {
+    threads: VecDeque<(ThreadId, NonNull<Thread>)>,
+    /// The id of the next thread created
+    next_id: ThreadId,
+}
+
+unsafe impl Send for ThreadList {}
+
+impl Default for ThreadList {
+    fn default() -> ThreadList {
+        ThreadList::new()
+    }
+}
+
+impl ThreadList {
+    pub fn new() -> ThreadList {
+        ThreadList {
+            threads: VecDeque::new(),
+            next_id: 0,
+        }
+    }
+
+    /// An id for a new thread
+    ///
+    /// Taken with the list locked, so it needs no read-modify-write, which
+    /// ARMv6-M doesn't have.
+    pub fn allocate_id(&mut self) -> ThreadId {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Take ownership of `thread`, returns where it lives from now on
+    pub fn insert(&mut self, thread: Box<Thread>) -> NonNull<Thread> {
+        let id = thread.id;
+        let ptr = unsafe { NonNull::new_unchecked(Box::into_raw(thread)) };
+        self.threads.push_back((id, ptr));
+        info::debug_table_insert(ptr);
+        ptr
+    }
+
+    /// Give up ownership of a thread
+    pub fn remove(&mut self, id: ThreadId) -> Option<Box<Thread>> {
+        let index = self.threads.iter().position(|&(tid, _)| tid == id)?;
+        let (_, ptr) = self.threads.remove(index).unwrap();
+        info::debug_table_remove(ptr);
+        Some(unsafe { Box::from_raw(ptr.as_ptr()) })
+    }
+
+    /// Give up ownership of a detached thread that exited
+    pub fn remove_dead(&mut self) -> Option<Box<Thread>> {
+        let id = self
+            .threads
+            .iter()
+            .map(|&(_, ptr)| unsafe { ptr.as_ref() })
+            .find(|thread| thread.state == ThreadState::Death && thread.detached)?
+            .id;
+        self.remove(id)
+    }
+
+    /// Take the stack of a thread that exited, its control block stays until
+    /// it is joined
+    pub fn take_dead_stack(&mut self) -> Option<Box<[u64]>> {
+        self.threads.iter().find_map(|&(_, ptr)| unsafe {
+            let thread = &mut *ptr.as_ptr();
+            if thread.state == ThreadState::Death {
+                thread.stack.take()
+            } else {
+                None
+            }
+        })
+    }
+
+    /// The threads, oldest first
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = NonNull<Thread>> + 'a {
+        self.threads.iter().map(|&(_, ptr)| ptr)
+    }
+
+    pub fn get(&self, id: ThreadId) -> Option<NonNull<Thread>> {
+        self.threads
+            .iter()
+            .find(|&&(tid, _)| tid == id)
+            .map(|&(_, ptr)| ptr)
+    }
+}
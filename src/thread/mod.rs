@@ -4,7 +4,30 @@
 // license that can be found in the LICENSE file or at
 // https://opensource.org/licenses/MIT
 
-use spin::{Once, RwLock};
+//! Kernel threads
+//!
+//! Every thread has a control block in `THREAD_LIST` and a stack from the
+//! heap, set up so that the first switch to it calls `entry(arg)`. Threads
+//! are referred to by id, through a `ThreadHandle`.
+//!
+//! A new thread is suspended until `resume()` hands it to the scheduler
+//! (see sched.rs). The code that called `thread_early_init()` goes on as the
+//! bootstrap thread.
+//!
+//! Every state change follows `ThreadState::can_become()`. A running thread
+//! may sleep, suspend itself, block or exit; it comes back as a ready thread.
+//! A thread that exited keeps its control block, with its exit code, until
+//! `join()` collects it, unless it was detached. The idle thread frees the
+//! stacks of dead threads, and the control blocks of detached ones.
+
+use alloc::boxed::Box;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicPtr, Ordering};
+use spin::Once;
+
+use crate::arch;
+use crate::kernel::irq::IrqLock;
+use crate::kernel::timer;
 
 /// Thread struct
 mod thread;
@@ -12,17 +35,357 @@ mod thread;
 /// Thread struct list
 mod list;
 
-pub use self::thread::Thread;
+/// Intrusive thread queues
+mod queue;
+
+/// Run queues and preemption
+mod sched;
+
+/// The idle thread and CPU load
+mod idle;
+
+/// Thread snapshots for the console and debuggers
+mod info;
+
+/// Stack painting, canaries and guards
+mod stack;
+
+/// Wait queues with priority inheritance, what kernel objects and sleeping
+/// threads block on, see kernel::wait
+pub(crate) mod wait;
+
+pub use self::idle::{cpu_load, idle_hook_register, idle_ticks, IdleHook, MAX_IDLE_HOOKS};
+pub use self::info::{info, ps, snapshot, ThreadInfo, DEBUG_TABLE_SIZE};
 pub use self::list::ThreadList;
+pub use self::queue::ThreadQueue;
+pub use self::sched::{set_timeslice, thread_preempt, thread_tick, timeslice, DEFAULT_TIMESLICE};
+pub use self::stack::{fault_report, stack_high_watermark, STACK_CANARY, STACK_PAINT};
+pub use self::thread::{
+    BlockedOn, Thread, ThreadEntry, ThreadId, ThreadState, DEFAULT_PRIORITY, DEFAULT_STACK_SIZE,
+    HIGHEST_PRIORITY, LOWEST_PRIORITY, MIN_STACK_SIZE, NUM_PRIORITIES,
+};
 
-static IDLE_THREAD: Once<Thread> = Once::new();
+/// The thread that runs when no other one is ready
+static IDLE_THREAD: AtomicPtr<Thread> = AtomicPtr::new(ptr::null_mut());
 
 /// Threads list
-static THREAD_LIST: Once<RwLock<ThreadList>> = Once::new();
+static THREAD_LIST: Once<IrqLock<ThreadList>> = Once::new();
+
+/// The thread running on the core
+static CURRENT_THREAD: AtomicPtr<Thread> = AtomicPtr::new(ptr::null_mut());
+
+/// The bootstrap thread gets the first id
+const BOOTSTRAP_THREAD_ID: ThreadId = 0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The priority is not below `NUM_PRIORITIES`
+    InvalidPriority,
+    /// The stack is smaller than `MIN_STACK_SIZE`
+    StackTooSmall,
+    /// The thread does not exist, or can't run anymore
+    NotFound,
+    /// The thread is not in a state that allows this
+    InvalidState,
+    /// A table of fixed size is full
+    NoSpace,
+}
+
+/// Refers to a thread by id, it does not keep the thread alive
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ThreadHandle {
+    id: ThreadId,
+}
+
+impl ThreadHandle {
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+}
+
+fn thread_list() -> &'static IrqLock<ThreadList> {
+    THREAD_LIST.call_once(|| IrqLock::new(ThreadList::new()))
+}
 
 /// Initialize threading system
 ///
 /// This function is called once, from kmain()
 pub fn thread_early_init() {
     // create a thread to cover the curring running state
+    let id = thread_list().lock().allocate_id();
+    debug_assert!(id == BOOTSTRAP_THREAD_ID);
+
+    sched::sched_init();
+
+    let mut thread = Box::new(Thread::bootstrap(id));
+    thread.timeslice = sched::timeslice();
+    let thread = thread_list().lock().insert(thread);
+    CURRENT_THREAD.store(thread.as_ptr(), Ordering::Relaxed);
+
+    let handle = spawn(
+        "idle",
+        LOWEST_PRIORITY,
+        DEFAULT_STACK_SIZE,
+        idle::idle_thread,
+        0,
+    )
+    .expect("can't create the idle thread");
+    let thread = thread_list().lock().get(handle.id).unwrap();
+    IDLE_THREAD.store(thread.as_ptr(), Ordering::Relaxed);
+    idle_hook_register(reap_dead_threads).unwrap();
+    resume(handle).unwrap();
+}
+
+/// Create a thread that runs `entry(arg)` on a stack of `stack_size` bytes
+///
+/// The thread starts out suspended.
+pub fn spawn(
+    name: &'static str,
+    priority: u8,
+    stack_size: usize,
+    entry: ThreadEntry,
+    arg: usize,
+) -> Result<ThreadHandle, Error> {
+    if priority as usize >= NUM_PRIORITIES {
+        return Err(Error::InvalidPriority);
+    }
+    if stack_size < MIN_STACK_SIZE {
+        return Err(Error::StackTooSmall);
+    }
+
+    let id = thread_list().lock().allocate_id();
+    let mut thread = Box::new(Thread::new(id, name, priority, stack_size, entry, arg));
+
+    let (stack_base, stack_top) = thread.stack_bounds().unwrap();
+    let control_block = &mut *thread as *mut Thread as usize;
+    thread.sp =
+        unsafe { arch::arch_init_context(stack_base, stack_top, thread_start, control_block) };
+    thread.timeslice = sched::timeslice();
+
+    thread_list().lock().insert(thread);
+    Ok(ThreadHandle { id })
+}
+
+/// The thread calling this
+pub fn current() -> ThreadHandle {
+    let thread = CURRENT_THREAD.load(Ordering::Relaxed);
+    assert!(!thread.is_null(), "threads are not initialized");
+    ThreadHandle {
+        id: unsafe { (*thread).id },
+    }
+}
+
+/// Let a suspended thread run, it preempts the caller if it is more urgent
+pub fn resume(handle: ThreadHandle) -> Result<(), Error> {
+    if unsafe { wake_suspended(handle)? } {
+        sched::reschedule();
+    }
+    Ok(())
+}
+
+/// `resume()` for interrupt handlers, the preemption waits until they
+/// returned
+pub fn resume_from_isr(handle: ThreadHandle) -> Result<(), Error> {
+    if unsafe { wake_suspended(handle)? } {
+        sched::request_preempt();
+    }
+    Ok(())
+}
+
+/// Make a suspended thread ready, returns whether it should preempt the
+/// current thread
+unsafe fn wake_suspended(handle: ThreadHandle) -> Result<bool, Error> {
+    let list = thread_list().lock();
+    let thread = list.get(handle.id).ok_or(Error::NotFound)?.as_ptr();
+    match (*thread).state {
+        ThreadState::Suspended => Ok(sched::make_ready(thread)),
+        ThreadState::Death => Err(Error::NotFound),
+        _ => Err(Error::InvalidState),
+    }
+}
+
+/// Stop a running or ready thread until `resume()`
+///
+/// A sleeping or blocked thread can't be suspended, nor can the idle thread.
+pub fn suspend(handle: ThreadHandle) -> Result<(), Error> {
+    let state = arch::irq_save();
+    let result = unsafe { stop(handle) };
+    if result == Ok(true) {
+        sched::reschedule();
+    }
+    arch::irq_restore(state);
+    result.map(|_| ())
+}
+
+/// Suspend a thread, returns whether it is the current one, which must give
+/// up the core
+unsafe fn stop(handle: ThreadHandle) -> Result<bool, Error> {
+    let list = thread_list().lock();
+    let thread = list.get(handle.id).ok_or(Error::NotFound)?.as_ptr();
+    if thread == IDLE_THREAD.load(Ordering::Relaxed) {
+        return Err(Error::InvalidState);
+    }
+    match (*thread).state {
+        ThreadState::Running => {
+            (*thread).set_state(ThreadState::Suspended);
+            Ok(true)
+        }
+        ThreadState::Ready => {
+            sched::remove_ready(thread);
+            (*thread).set_state(ThreadState::Suspended);
+            Ok(false)
+        }
+        ThreadState::Death => Err(Error::NotFound),
+        _ => Err(Error::InvalidState),
+    }
+}
+
+/// Give the core to the other ready threads of the same priority
+pub fn yield_now() {
+    sched::yield_current();
+}
+
+/// Let the other threads run for at least `ticks` ticks
+pub fn sleep(ticks: u64) {
+    sleep_until(timer::current_ticks() + ticks);
+}
+
+/// Let the other threads run until the clock reaches `deadline`, returns
+/// right away if it already did
+pub fn sleep_until(deadline: u64) {
+    let state = arch::irq_save();
+    let now = timer::current_ticks();
+    if deadline > now {
+        unsafe {
+            wait::block(
+                ptr::null_mut(),
+                Some(deadline - now),
+                BlockedOn::Sleep(deadline),
+                0,
+            )
+        };
+    }
+    arch::irq_restore(state);
+}
+
+/// Wait until a thread exited, returns its exit code
+///
+/// The thread is gone afterwards. Only one thread may join it, and a thread
+/// can't join itself or a detached thread.
+pub fn join(handle: ThreadHandle) -> Result<i32, Error> {
+    let state = arch::irq_save();
+    let result = unsafe { wait_for_exit(handle) };
+    // Cortex-M only blocks here, once interrupts are enabled
+    arch::irq_restore(state);
+    result?;
+
+    let thread = thread_list()
+        .lock()
+        .remove(handle.id)
+        .expect("joined thread vanished");
+    Ok(thread.exit_code)
+}
+
+/// Block the current thread until `handle` exited, with interrupts disabled
+unsafe fn wait_for_exit(handle: ThreadHandle) -> Result<(), Error> {
+    let thread = thread_list()
+        .lock()
+        .get(handle.id)
+        .ok_or(Error::NotFound)?
+        .as_ptr();
+    let current = CURRENT_THREAD.load(Ordering::Relaxed);
+    if thread == current || (*thread).detached || !(*thread).joiner.is_null() {
+        return Err(Error::InvalidState);
+    }
+
+    if (*thread).state != ThreadState::Death {
+        (*thread).joiner = current;
+        (*current).set_state(ThreadState::Blocked);
+        (*current).blocked_on = BlockedOn::Join(handle.id);
+        sched::reschedule();
+    }
+    Ok(())
+}
+
+/// Free a thread as soon as it exited, instead of keeping it for `join()`
+pub fn detach(handle: ThreadHandle) -> Result<(), Error> {
+    let list = thread_list().lock();
+    let thread = unsafe { &mut *list.get(handle.id).ok_or(Error::NotFound)?.as_ptr() };
+    if !thread.joiner.is_null() {
+        return Err(Error::InvalidState);
+    }
+    thread.detached = true;
+    Ok(())
+}
+
+/// Free the stacks of the threads that exited, and the control blocks of
+/// the detached ones, an idle hook
+fn reap_dead_threads() {
+    loop {
+        // Dropped without the list locked
+        let thread = thread_list().lock().remove_dead();
+        if thread.is_none() {
+            break;
+        }
+    }
+    loop {
+        let stack = thread_list().lock().take_dead_stack();
+        if stack.is_none() {
+            break;
+        }
+    }
+}
+
+/// Where every thread starts, `arg` is its control block
+extern "C" fn thread_start(arg: usize) -> ! {
+    let (entry, arg) = unsafe {
+        let thread = &*(arg as *const Thread);
+        (thread.entry.unwrap(), thread.arg)
+    };
+
+    exit(entry(arg))
+}
+
+/// End the current thread with `code`, as if its entry returned it
+pub fn exit(code: i32) -> ! {
+    let current = CURRENT_THREAD.load(Ordering::Relaxed);
+
+    let state = arch::irq_save();
+    unsafe {
+        assert!(
+            (*current).held == 0,
+            "thread {} exited holding a lock",
+            (*current).id
+        );
+        (*current).exit_code = code;
+        (*current).set_state(ThreadState::Death);
+        let joiner = (*current).joiner;
+        if !joiner.is_null() {
+            sched::make_ready(joiner);
+        }
+    }
+    sched::reschedule();
+    arch::irq_restore(state);
+
+    unreachable!("thread {} ran after it exited", unsafe { (*current).id });
+}
+
+/// Switch from `old` to `new`, which the scheduler took off its run queue
+///
+/// Must be called with interrupts disabled. Cortex-M switches in PendSV, so
+/// there `new` only runs once they are enabled again.
+unsafe fn context_switch(old: *mut Thread, new: NonNull<Thread>) {
+    let new = new.as_ptr();
+    (*new).check();
+    stack::check_stack(&*old);
+
+    if (*old).state == ThreadState::Running {
+        (*old).set_state(ThreadState::Ready);
+    }
+    (*new).set_state(ThreadState::Running);
+    (*new).switches += 1;
+    CURRENT_THREAD.store(new, Ordering::Relaxed);
+
+    arch::arch_stack_guard(stack::guard_base(&*new));
+    arch::arch_context_switch(&mut (*old).sp, (*new).sp);
 }
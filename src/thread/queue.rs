@@ -0,0 +1,147 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+#![deny(warnings)]
+
+use core::ptr;
+
+use super::thread::Thread;
+
+/// A FIFO of threads linked through their control blocks
+///
+/// A thread is on at most one queue at a time, so queueing never allocates
+/// and works from interrupt handlers. Queues are only touched with
+/// interrupts disabled.
+pub struct ThreadQueue {
+    head: *mut Thread,
+    tail: *mut Thread,
+}
+
+unsafe impl Send for ThreadQueue {}
+
+impl Default for ThreadQueue {
+    fn default() -> ThreadQueue {
+        ThreadQueue::new()
+    }
+}
+
+impl ThreadQueue {
+    pub const fn new() -> ThreadQueue {
+        ThreadQueue {
+            head: ptr::null_mut(),
+            tail: ptr::null_mut(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+
+    /// The thread `pop_front()` would return
+    pub fn front(&self) -> *mut Thread {
+        self.head
+    }
+
+    /// Queue `thread` last
+    ///
+    /// # Safety
+    ///
+    /// Interrupts must be disabled, and `thread` must be a live control
+    /// block that is on no queue.
+    pub unsafe fn push_back(&mut self, thread: *mut Thread) {
+        debug_assert!((*thread).queue_next.is_null() && (*thread).queue_prev.is_null());
+
+        (*thread).queue_prev = self.tail;
+        if self.tail.is_null() {
+            self.head = thread;
+        } else {
+            (*self.tail).queue_next = thread;
+        }
+        self.tail = thread;
+    }
+
+    /// Queue `thread` first
+    ///
+    /// # Safety
+    ///
+    /// Interrupts must be disabled, and `thread` must be a live control
+    /// block that is on no queue.
+    pub unsafe fn push_front(&mut self, thread: *mut Thread) {
+        debug_assert!((*thread).queue_next.is_null() && (*thread).queue_prev.is_null());
+
+        (*thread).queue_next = self.head;
+        if self.head.is_null() {
+            self.tail = thread;
+        } else {
+            (*self.head).queue_prev = thread;
+        }
+        self.head = thread;
+    }
+
+    /// Queue `thread` behind the threads of its priority and above
+    ///
+    /// # Safety
+    ///
+    /// Interrupts must be disabled, and `thread` must be a live control
+    /// block that is on no queue.
+    pub unsafe fn insert_by_priority(&mut self, thread: *mut Thread) {
+        let mut next = self.head;
+        while !next.is_null() && (*next).priority >= (*thread).priority {
+            next = (*next).queue_next;
+        }
+        if next.is_null() {
+            self.push_back(thread);
+            return;
+        }
+
+        debug_assert!((*thread).queue_next.is_null() && (*thread).queue_prev.is_null());
+        let prev = (*next).queue_prev;
+        (*thread).queue_prev = prev;
+        (*thread).queue_next = next;
+        (*next).queue_prev = thread;
+        if prev.is_null() {
+            self.head = thread;
+        } else {
+            (*prev).queue_next = thread;
+        }
+    }
+
+    /// Unlink the first thread
+    ///
+    /// # Safety
+    ///
+    /// Interrupts must be disabled.
+    pub unsafe fn pop_front(&mut self) -> Option<*mut Thread> {
+        let thread = self.head;
+        if thread.is_null() {
+            None
+        } else {
+            self.remove(thread);
+            Some(thread)
+        }
+    }
+
+    /// Unlink `thread`
+    ///
+    /// # Safety
+    ///
+    /// Interrupts must be disabled, and `thread` must be on this queue.
+    pub unsafe fn remove(&mut self, thread: *mut Thread) {
+        let (prev, next) = ((*thread).queue_prev, (*thread).queue_next);
+        if prev.is_null() {
+            self.head = next;
+        } else {
+            (*prev).queue_next = next;
+        }
+        if next.is_null() {
+            self.tail = prev;
+        } else {
+            (*next).queue_prev = prev;
+        }
+        (*thread).queue_prev = ptr::null_mut();
+        (*thread).queue_next = ptr::null_mut();
+    }
+}
@@ -0,0 +1,243 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Fixed priority preemptive scheduler
+//!
+//! Every priority has a FIFO of ready threads and bit `n` of a bitmap is set
+//! while the one of priority `n` is not empty, so the most urgent ready
+//! thread is found by counting the leading zeros of the bitmap. The running
+//! thread is not on a run queue.
+//!
+//! A thread that becomes ready preempts a less urgent running thread. When
+//! a thread made it ready that happens right away, when an interrupt handler
+//! did the port calls `thread_preempt()` once the handlers returned. Threads
+//! of the same priority take turns every `timeslice()` ticks.
+
+#![deny(warnings)]
+
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use spin::Once;
+
+use super::queue::ThreadQueue;
+use super::thread::{BlockedOn, Thread, ThreadState, NUM_PRIORITIES};
+use super::{idle, CURRENT_THREAD, IDLE_THREAD};
+use crate::arch;
+use crate::kernel::irq::IrqLock;
+
+/// Ticks a thread runs before the next ready one of its priority
+pub const DEFAULT_TIMESLICE: u32 = 10;
+
+static TIMESLICE: AtomicU32 = AtomicU32::new(DEFAULT_TIMESLICE);
+
+/// An interrupt handler made a thread ready that should preempt the
+/// current one
+static NEED_RESCHED: AtomicBool = AtomicBool::new(false);
+
+static RUN_QUEUE: Once<IrqLock<RunQueue>> = Once::new();
+
+struct RunQueue {
+    /// Bit `n` is set while `queues[n]` is not empty
+    bitmap: u32,
+    queues: [ThreadQueue; NUM_PRIORITIES],
+}
+
+impl RunQueue {
+    fn new() -> RunQueue {
+        RunQueue {
+            bitmap: 0,
+            queues: Default::default(),
+        }
+    }
+
+    /// The priority of the most urgent ready thread
+    fn highest(&self) -> Option<u8> {
+        if self.bitmap == 0 {
+            None
+        } else {
+            Some(31 - self.bitmap.leading_zeros() as u8)
+        }
+    }
+
+    fn has_ready(&self, priority: u8) -> bool {
+        self.bitmap & (1 << priority) != 0
+    }
+
+    /// Queue `thread` behind the ready threads of its priority, or in front
+    /// of them when it was preempted before its timeslice ran out
+    unsafe fn insert(&mut self, thread: *mut Thread, front: bool) {
+        let priority = (*thread).priority;
+        if front {
+            self.queues[priority as usize].push_front(thread);
+        } else {
+            self.queues[priority as usize].push_back(thread);
+        }
+        self.bitmap |= 1 << priority;
+    }
+
+    unsafe fn remove(&mut self, thread: *mut Thread) {
+        let priority = (*thread).priority;
+        let queue = &mut self.queues[priority as usize];
+        queue.remove(thread);
+        if queue.is_empty() {
+            self.bitmap &= !(1 << priority);
+        }
+    }
+
+    /// The thread to switch to, `None` when `current` keeps the core
+    unsafe fn pick_next(&mut self, current: *mut Thread) -> Option<NonNull<Thread>> {
+        let expired = (*current).timeslice == 0;
+        if expired {
+            (*current).timeslice = timeslice();
+        }
+
+        if (*current).state == ThreadState::Running {
+            let priority = (*current).priority;
+            match self.highest() {
+                Some(highest) if highest > priority || (highest == priority && expired) => {}
+                _ => return None,
+            }
+            (*current).set_state(ThreadState::Ready);
+            self.insert(current, !expired);
+        }
+
+        let highest = self.highest().expect("no thread to run");
+        let next = self.queues[highest as usize].front();
+        self.remove(next);
+        Some(NonNull::new_unchecked(next))
+    }
+}
+
+fn run_queue() -> &'static IrqLock<RunQueue> {
+    RUN_QUEUE.call_once(|| IrqLock::new(RunQueue::new()))
+}
+
+/// Ticks a thread runs before the next ready one of its priority
+pub fn timeslice() -> u32 {
+    TIMESLICE.load(Ordering::Relaxed)
+}
+
+/// Change the timeslice, threads pick it up when their current one ran out
+pub fn set_timeslice(ticks: u32) {
+    assert!(ticks > 0, "timeslice of 0 ticks");
+    TIMESLICE.store(ticks, Ordering::Relaxed);
+}
+
+pub(super) fn sched_init() {
+    run_queue();
+}
+
+/// Queue a thread that can run again, returns whether it should preempt the
+/// current thread
+pub(super) unsafe fn make_ready(thread: *mut Thread) -> bool {
+    let mut run_queue = run_queue().lock();
+    (*thread).set_state(ThreadState::Ready);
+    (*thread).blocked_on = BlockedOn::Nothing;
+    run_queue.insert(thread, false);
+
+    let current = CURRENT_THREAD.load(Ordering::Relaxed);
+    (*thread).priority > (*current).priority
+}
+
+/// Schedule `thread` at `priority` from now on, returns whether it is the
+/// current thread and a more urgent one is ready
+pub(super) unsafe fn set_priority(thread: *mut Thread, priority: u8) -> bool {
+    let mut run_queue = run_queue().lock();
+    if (*thread).state == ThreadState::Ready {
+        run_queue.remove(thread);
+        (*thread).priority = priority;
+        run_queue.insert(thread, false);
+    } else {
+        (*thread).priority = priority;
+    }
+
+    thread == CURRENT_THREAD.load(Ordering::Relaxed)
+        && run_queue
+            .highest()
+            .map_or(false, |highest| highest > priority)
+}
+
+/// Take a ready thread off its run queue, the caller gives it a new state
+pub(super) unsafe fn remove_ready(thread: *mut Thread) {
+    debug_assert!((*thread).state == ThreadState::Ready);
+    run_queue().lock().remove(thread);
+}
+
+/// Whether any thread is waiting for the core
+pub(super) fn has_ready() -> bool {
+    run_queue().lock().bitmap != 0
+}
+
+/// Preempt the current thread once the interrupt handlers returned
+pub(super) fn request_preempt() {
+    NEED_RESCHED.store(true, Ordering::Relaxed);
+    arch::arch_request_preempt();
+}
+
+/// Give the core to the most urgent ready thread
+///
+/// A running thread keeps the core unless a more urgent thread is ready, or
+/// one of the same priority and its timeslice ran out. A thread that
+/// stopped running, because it blocked or exited, always gives it up.
+pub(super) fn reschedule() {
+    let state = arch::irq_save();
+    NEED_RESCHED.store(false, Ordering::Relaxed);
+
+    let current = CURRENT_THREAD.load(Ordering::Relaxed);
+    let next = unsafe { run_queue().lock().pick_next(current) };
+    if let Some(next) = next {
+        unsafe { super::context_switch(current, next) };
+    }
+
+    arch::irq_restore(state);
+}
+
+/// Give the core to the next ready thread of the same priority, or to a
+/// more urgent one
+pub(super) fn yield_current() {
+    let state = arch::irq_save();
+    unsafe {
+        (*CURRENT_THREAD.load(Ordering::Relaxed)).timeslice = 0;
+    }
+    reschedule();
+    arch::irq_restore(state);
+}
+
+/// Switch to the thread an interrupt handler made ready
+///
+/// The ports call this when the interrupt handlers returned, with the state
+/// of the interrupted thread saved on its stack.
+pub fn thread_preempt() {
+    if NEED_RESCHED.load(Ordering::Relaxed) {
+        reschedule();
+    }
+}
+
+/// Charge `ticks` to the current thread, its timeslice and the CPU load,
+/// called from the tick interrupt
+pub fn thread_tick(ticks: u64) {
+    let current = CURRENT_THREAD.load(Ordering::Relaxed);
+    if current.is_null() {
+        return;
+    }
+    idle::account(ticks, current == IDLE_THREAD.load(Ordering::Relaxed));
+
+    let run_queue = run_queue().lock();
+    unsafe {
+        (*current).cpu_ticks += ticks;
+
+        let left = (*current).timeslice as u64;
+        (*current).timeslice = if ticks < left {
+            (left - ticks) as u32
+        } else {
+            0
+        };
+
+        if (*current).timeslice == 0 && run_queue.has_ready((*current).priority) {
+            request_preempt();
+        }
+    }
+}
@@ -0,0 +1,118 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Stack overflow detection
+//!
+//! A new stack is painted with `STACK_PAINT`, so the deepest word that
+//! changed gives its high watermark. The port may make the bottom
+//! `arch::STACK_GUARD_SIZE` bytes of the running thread's stack fault on
+//! access. The word right above them holds `STACK_CANARY`, which is checked
+//! every time the thread is switched out.
+
+#![deny(warnings)]
+
+use alloc::boxed::Box;
+use alloc::vec;
+use core::fmt;
+use core::sync::atomic::Ordering;
+
+use super::thread::Thread;
+use super::{thread_list, Error, ThreadHandle, CURRENT_THREAD};
+use crate::arch;
+
+/// What an unused stack word holds
+pub const STACK_PAINT: u64 = 0xa5a5_a5a5_a5a5_a5a5;
+
+/// Above the guard, it only changes when the stack overflowed
+pub const STACK_CANARY: u64 = 0x454c_4349_5452_4150; // 'PARTICLE'
+
+/// Index of the canary in a stack
+const CANARY_WORD: usize = arch::STACK_GUARD_SIZE / 8;
+
+/// A painted stack of `words` words, with its canary in place
+pub(super) fn new_stack(words: usize) -> Box<[u64]> {
+    let mut stack = vec![STACK_PAINT; words];
+    stack[CANARY_WORD] = STACK_CANARY;
+    stack.into_boxed_slice()
+}
+
+impl Thread {
+    /// Whether the canary survived, true for the bootstrap thread
+    pub(super) fn canary_intact(&self) -> bool {
+        self.stack
+            .as_ref()
+            .map_or(true, |stack| stack[CANARY_WORD] == STACK_CANARY)
+    }
+
+    /// The most bytes of stack the thread ever used, the whole stack above
+    /// the guard once the canary was overwritten
+    pub(super) fn stack_peak(&self) -> usize {
+        let stack = match self.stack {
+            Some(ref stack) => stack,
+            None => return 0,
+        };
+        let deepest = (CANARY_WORD..stack.len())
+            .find(|&i| {
+                let unused = if i == CANARY_WORD {
+                    STACK_CANARY
+                } else {
+                    STACK_PAINT
+                };
+                stack[i] != unused
+            })
+            .unwrap_or(stack.len());
+        (stack.len() - deepest) * 8
+    }
+}
+
+/// The most bytes of stack the thread ever used
+pub fn stack_high_watermark(handle: ThreadHandle) -> Result<usize, Error> {
+    let list = thread_list().lock();
+    let thread = list.get(handle.id).ok_or(Error::NotFound)?;
+    Ok(unsafe { thread.as_ref() }.stack_peak())
+}
+
+/// Stop the system if `thread` overflowed its stack, called when it is
+/// switched out
+pub(super) fn check_stack(thread: &Thread) {
+    if !thread.canary_intact() {
+        println!(
+            "\nstack overflow in thread {} ({}): canary overwritten, stack of {} bytes",
+            thread.id,
+            thread.name,
+            thread.stack_bounds().map_or(0, |(base, top)| top - base)
+        );
+        arch::arch_exit(false);
+    }
+}
+
+/// The stack the port guards while `thread` runs, 0 for none
+pub(super) fn guard_base(thread: &Thread) -> usize {
+    thread.stack_bounds().map_or(0, |(base, _)| base)
+}
+
+/// Name the running thread in a fault report, and whether it overflowed its
+/// stack
+///
+/// `addr` is the address of the faulting access when the port knows it,
+/// `overflow` whether the port knows the stack overflowed.
+pub fn fault_report(out: &mut dyn fmt::Write, addr: Option<usize>, overflow: bool) -> fmt::Result {
+    let thread = CURRENT_THREAD.load(Ordering::Relaxed);
+    if thread.is_null() {
+        return Ok(());
+    }
+    let thread = unsafe { &*thread };
+
+    write!(out, "in thread {} ({})", thread.id, thread.name)?;
+    if let Some((base, top)) = thread.stack_bounds() {
+        let canary_end = base + (CANARY_WORD + 1) * 8;
+        let in_guard = addr.map_or(false, |addr| addr >= base && addr < canary_end);
+        if overflow || in_guard || !thread.canary_intact() {
+            write!(out, ": stack overflow, stack of {} bytes", top - base)?;
+        }
+    }
+    writeln!(out)
+}
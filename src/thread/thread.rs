@@ -6,12 +6,42 @@
 
 #![deny(warnings)]
 
-//use alloc::sync::Arc;
-//use alloc::boxed::Box;
-//use alloc::vec::Vec;
-//use spin::Mutex;
+use alloc::boxed::Box;
+use core::fmt;
+use core::ptr;
 
-#[derive(Debug)]
+use super::wait::{WaitQueue, WakeReason};
+use crate::kernel::timer::Timer;
+
+/// Identifies a thread for as long as the kernel runs, ids are not reused
+pub type ThreadId = usize;
+
+/// What a thread runs, its return value is the exit code of the thread
+pub type ThreadEntry = fn(arg: usize) -> i32;
+
+/// Number of priority levels, higher values are more urgent
+pub const NUM_PRIORITIES: usize = 32;
+pub const LOWEST_PRIORITY: u8 = 0;
+pub const HIGHEST_PRIORITY: u8 = NUM_PRIORITIES as u8 - 1;
+pub const DEFAULT_PRIORITY: u8 = NUM_PRIORITIES as u8 / 2;
+
+/// Smallest stack `spawn()` accepts, on the hosted port the initial
+/// ucontext alone takes about 1K
+#[cfg(not(host))]
+pub const MIN_STACK_SIZE: usize = 256;
+#[cfg(host)]
+pub const MIN_STACK_SIZE: usize = 4 * 1024;
+
+/// A stack that runs the usual kernel code. The hosted port needs room for
+/// a ucontext and the signal frames delivered on it.
+#[cfg(not(host))]
+pub const DEFAULT_STACK_SIZE: usize = 1024;
+#[cfg(host)]
+pub const DEFAULT_STACK_SIZE: usize = 64 * 1024;
+
+const THREAD_MAGIC: u32 = 0x70617274; // 'part'
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ThreadState {
     Suspended = 0,
     Ready,
@@ -21,25 +51,237 @@ pub enum ThreadState {
     Death,
 }
 
+impl fmt::Display for ThreadState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            ThreadState::Suspended => "suspended",
+            ThreadState::Ready => "ready",
+            ThreadState::Running => "running",
+            ThreadState::Blocked => "blocked",
+            ThreadState::Sleeping => "sleeping",
+            ThreadState::Death => "dead",
+        };
+        f.pad(name)
+    }
+}
+
+/// What a blocked or sleeping thread waits for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockedOn {
+    Nothing,
+    /// The clock to reach this tick
+    Sleep(u64),
+    /// This thread to exit
+    Join(ThreadId),
+    /// The `kernel::sync` mutex at this address
+    Mutex(usize),
+    /// The `kernel::sync` semaphore at this address
+    Semaphore(usize),
+    /// The `kernel::ipc` queue at this address
+    Queue(usize),
+    /// The `kernel::sync` event group at this address
+    Events(usize),
+    /// The `kernel::sync` condition variable at this address
+    Condvar(usize),
+}
+
+impl fmt::Display for BlockedOn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BlockedOn::Nothing => Ok(()),
+            BlockedOn::Sleep(deadline) => write!(f, "sleep until {}", deadline),
+            BlockedOn::Join(id) => write!(f, "join {}", id),
+            BlockedOn::Mutex(addr) => write!(f, "mutex {:#x}", addr),
+            BlockedOn::Semaphore(addr) => write!(f, "semaphore {:#x}", addr),
+            BlockedOn::Queue(addr) => write!(f, "queue {:#x}", addr),
+            BlockedOn::Events(addr) => write!(f, "event group {:#x}", addr),
+            BlockedOn::Condvar(addr) => write!(f, "condvar {:#x}", addr),
+        }
+    }
+}
+
+impl ThreadState {
+    /// Whether a thread in this state may go to `next`
+    ///
+    /// Only a running thread sleeps, blocks or exits. A thread leaves the
+    /// Sleeping and Blocked states by being made ready again, and a thread
+    /// that exited stays dead.
+    pub fn can_become(self, next: ThreadState) -> bool {
+        use self::ThreadState::*;
+
+        match (self, next) {
+            (Suspended, Ready) => true,
+            (Ready, Running) | (Ready, Suspended) => true,
+            (Running, Ready)
+            | (Running, Suspended)
+            | (Running, Blocked)
+            | (Running, Sleeping)
+            | (Running, Death) => true,
+            (Blocked, Ready) | (Sleeping, Ready) => true,
+            _ => false,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Thread {
     /// The magic of this thread
-    magic: i32,
-    /// The priority of this thread
-    priority: i32,
+    magic: u32,
+    /// The id of this thread
+    pub(super) id: ThreadId,
+    /// The name of this thread
+    pub(super) name: &'static str,
+    /// The priority the thread is scheduled at, raised above
+    /// `base_priority` while it owns a wait queue a more urgent thread
+    /// waits on
+    pub(super) priority: u8,
+    /// The priority the thread was given
+    pub(super) base_priority: u8,
     /// The status of this thread
-    state: ThreadState,
-    // The name of this thread
-    //name: Arc<Mutex<Box<[u8]>>>,
+    pub(super) state: ThreadState,
+    /// Why the thread is not ready, while it is blocked or sleeping
+    pub(super) blocked_on: BlockedOn,
+    /// Saved stack pointer while the thread does not run
+    pub(super) sp: usize,
+    /// The stack, `None` for the bootstrap thread which runs on the boot stack
+    pub(super) stack: Option<Box<[u64]>>,
+    pub(super) entry: Option<ThreadEntry>,
+    pub(super) arg: usize,
+    /// What `entry` returned
+    pub(super) exit_code: i32,
+    /// Ticks left before a thread of the same priority gets the core
+    pub(super) timeslice: u32,
+    /// Ticks the thread ran
+    pub(super) cpu_ticks: u64,
+    /// How many times the thread was switched to
+    pub(super) switches: u64,
+    /// Links of the run queue or wait queue the thread is on
+    pub(super) queue_next: *mut Thread,
+    pub(super) queue_prev: *mut Thread,
+    /// Wakes the thread up from `sleep()`
+    pub(super) timer: Timer,
+    /// The thread blocked in `join()` on this one
+    pub(super) joiner: *mut Thread,
+    /// Nobody joins this thread, it is freed as soon as it exited
+    pub(super) detached: bool,
+    /// The wait queue the thread is blocked on
+    pub(super) wait_queue: *mut WaitQueue,
+    /// What the object the thread is blocked on needs to know about it
+    pub(super) wait_data: usize,
+    /// Why the thread last left a wait queue
+    pub(super) wake_reason: WakeReason,
+    /// The wait queues with waiters the thread owns, linked through them
+    pub(super) owned: *mut WaitQueue,
+    /// How many wait queues the thread owns, it may not exit before it gave
+    /// them up
+    pub(super) held: u32,
+    /// How many of the queues it owns have each ceiling
+    pub(super) ceilings: [u8; NUM_PRIORITIES],
 }
 
+// The queue links are only used with interrupts disabled
+unsafe impl Send for Thread {}
+unsafe impl Sync for Thread {}
+
 impl Thread {
-    pub fn new() -> Thread {
+    /// A thread that has yet to run `entry(arg)` on a new stack of
+    /// `stack_size` bytes
+    pub(super) fn new(
+        id: ThreadId,
+        name: &'static str,
+        priority: u8,
+        stack_size: usize,
+        entry: ThreadEntry,
+        arg: usize,
+    ) -> Thread {
+        // u64s keep the stack aligned for every architecture
+        let words = (stack_size + 7) / 8;
+        let stack = super::stack::new_stack(words);
+
         Thread {
-            magic: 0x70617274,  // 'part'
-            priority: 0,
+            magic: THREAD_MAGIC,
+            id,
+            name,
+            priority,
+            base_priority: priority,
             state: ThreadState::Suspended,
-            //name: Arc::new(Mutex::new(Vec::new().into_boxed_slice())),
+            blocked_on: BlockedOn::Nothing,
+            sp: 0,
+            stack: Some(stack),
+            entry: Some(entry),
+            arg,
+            exit_code: 0,
+            timeslice: 0,
+            cpu_ticks: 0,
+            switches: 0,
+            queue_next: ptr::null_mut(),
+            queue_prev: ptr::null_mut(),
+            timer: Timer::new(),
+            joiner: ptr::null_mut(),
+            detached: false,
+            wait_queue: ptr::null_mut(),
+            wait_data: 0,
+            wake_reason: WakeReason::Woken,
+            owned: ptr::null_mut(),
+            held: 0,
+            ceilings: [0; NUM_PRIORITIES],
         }
     }
+
+    /// The thread that was running on the boot stack all along
+    pub(super) fn bootstrap(id: ThreadId) -> Thread {
+        Thread {
+            magic: THREAD_MAGIC,
+            id,
+            name: "bootstrap",
+            priority: DEFAULT_PRIORITY,
+            base_priority: DEFAULT_PRIORITY,
+            state: ThreadState::Running,
+            blocked_on: BlockedOn::Nothing,
+            sp: 0,
+            stack: None,
+            entry: None,
+            arg: 0,
+            exit_code: 0,
+            timeslice: 0,
+            cpu_ticks: 0,
+            switches: 0,
+            queue_next: ptr::null_mut(),
+            queue_prev: ptr::null_mut(),
+            timer: Timer::new(),
+            joiner: ptr::null_mut(),
+            detached: false,
+            wait_queue: ptr::null_mut(),
+            wait_data: 0,
+            wake_reason: WakeReason::Woken,
+            owned: ptr::null_mut(),
+            held: 0,
+            ceilings: [0; NUM_PRIORITIES],
+        }
+    }
+
+    /// `[base, top)` of the stack of this thread
+    pub(super) fn stack_bounds(&self) -> Option<(usize, usize)> {
+        self.stack.as_ref().map(|stack| {
+            let base = stack.as_ptr() as usize;
+            (base, base + stack.len() * 8)
+        })
+    }
+
+    /// Move to `next`, a transition the state machine does not allow is a
+    /// kernel bug
+    pub(super) fn set_state(&mut self, next: ThreadState) {
+        assert!(
+            self.state.can_become(next),
+            "thread {}: {:?} -> {:?}",
+            self.id,
+            self.state,
+            next
+        );
+        self.state = next;
+    }
+
+    pub(super) fn check(&self) {
+        assert!(self.magic == THREAD_MAGIC, "thread {} corrupted", self.id);
+    }
 }
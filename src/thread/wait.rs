@@ -0,0 +1,411 @@
+// Copyright 2019 The Particle Authors
+//
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT
+
+//! Blocking on kernel objects
+//!
+//! A `WaitQueue` holds the threads blocked on an object, most urgent first
+//! or in the order they came. A queue may have an owner, the thread the
+//! waiters wait for, which then runs at the priority of its most urgent
+//! waiter. When the owner is itself blocked on a queue with an owner, that
+//! one inherits the priority too, all the way down the chain. A queue with
+//! a ceiling also keeps its owner at the ceiling priority at least.
+//!
+//! Everything here runs with interrupts disabled, which the lock of the
+//! object the queue belongs to takes care of. A thread blocks with
+//! `block()`, then releases that lock: on Cortex-M it only stops running
+//! once interrupts are enabled again. `wake_reason()` tells why it got the
+//! core back.
+
+#![deny(warnings)]
+
+use core::ptr;
+use core::sync::atomic::Ordering;
+
+use super::queue::ThreadQueue;
+use super::thread::{BlockedOn, Thread, ThreadState};
+use super::{sched, CURRENT_THREAD};
+use crate::arch;
+
+/// Why a blocked thread got the core back
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WakeReason {
+    /// The object it waited for woke it up
+    Woken,
+    /// The timeout passed first
+    TimedOut,
+    /// The object went away
+    Deleted,
+}
+
+/// Which waiter a `WaitQueue` wakes first
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    /// The most urgent one, the first to come among equals
+    Priority,
+    /// The first to come
+    Fifo,
+}
+
+/// Threads blocked on a kernel object
+pub struct WaitQueue {
+    waiters: ThreadQueue,
+    order: Order,
+    /// The thread the waiters wait for, it inherits their priority
+    owner: *mut Thread,
+    /// The next queue with waiters `owner` owns
+    next_owned: *mut WaitQueue,
+    /// The priority `owner` runs at, at least
+    ceiling: Option<u8>,
+}
+
+unsafe impl Send for WaitQueue {}
+
+impl Default for WaitQueue {
+    fn default() -> WaitQueue {
+        WaitQueue::new()
+    }
+}
+
+impl WaitQueue {
+    /// A queue waking the most urgent waiter first
+    pub const fn new() -> WaitQueue {
+        WaitQueue {
+            waiters: ThreadQueue::new(),
+            order: Order::Priority,
+            owner: ptr::null_mut(),
+            next_owned: ptr::null_mut(),
+            ceiling: None,
+        }
+    }
+
+    /// A queue waking the waiters in the order they came
+    pub const fn fifo() -> WaitQueue {
+        WaitQueue {
+            waiters: ThreadQueue::new(),
+            order: Order::Fifo,
+            owner: ptr::null_mut(),
+            next_owned: ptr::null_mut(),
+            ceiling: None,
+        }
+    }
+
+    /// A queue whose owner runs at `ceiling` at least
+    pub const fn with_ceiling(ceiling: u8) -> WaitQueue {
+        WaitQueue {
+            waiters: ThreadQueue::new(),
+            order: Order::Priority,
+            owner: ptr::null_mut(),
+            next_owned: ptr::null_mut(),
+            ceiling: Some(ceiling),
+        }
+    }
+
+    pub fn ceiling(&self) -> Option<u8> {
+        self.ceiling
+    }
+
+    /// Whether `thread` may own the queue, its priority must not be above
+    /// the ceiling
+    pub(crate) unsafe fn may_own(&self, thread: *mut Thread) -> bool {
+        self.ceiling
+            .map_or(true, |ceiling| (*thread).base_priority <= ceiling)
+    }
+
+    pub fn order(&self) -> Order {
+        self.order
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiters.is_empty()
+    }
+
+    pub(crate) fn owner(&self) -> *mut Thread {
+        self.owner
+    }
+
+    /// Hand the queue to `owner`, null for nobody
+    ///
+    /// The previous owner drops back to the priority the queues it still
+    /// owns give it, the new one inherits the priority of the waiters and
+    /// the ceiling. The owner is only linked to the queue while it has
+    /// waiters, which borrow the object and so keep it in place, the
+    /// ceiling is counted on the thread itself.
+    pub(crate) unsafe fn set_owner(&mut self, owner: *mut Thread) {
+        let previous = self.owner;
+        if previous == owner {
+            return;
+        }
+
+        if !previous.is_null() {
+            if self.linked() {
+                self.unlink();
+            }
+            self.owner = ptr::null_mut();
+            (*previous).held -= 1;
+            if let Some(ceiling) = self.ceiling {
+                (*previous).ceilings[ceiling as usize] -= 1;
+            }
+            update_priority(previous);
+        }
+
+        if !owner.is_null() {
+            self.owner = owner;
+            (*owner).held += 1;
+            if let Some(ceiling) = self.ceiling {
+                (*owner).ceilings[ceiling as usize] += 1;
+            }
+            if self.linked() {
+                self.link();
+            }
+            update_priority(owner);
+        }
+    }
+
+    /// Whether the queue is on the list of its owner: it has one, and
+    /// waiters
+    fn linked(&self) -> bool {
+        !self.owner.is_null() && !self.waiters.is_empty()
+    }
+
+    unsafe fn link(&mut self) {
+        self.next_owned = (*self.owner).owned;
+        (*self.owner).owned = self;
+    }
+
+    unsafe fn unlink(&mut self) {
+        let mut link = &mut (*self.owner).owned as *mut *mut WaitQueue;
+        while *link != self as *mut WaitQueue {
+            link = &mut (**link).next_owned;
+        }
+        *link = self.next_owned;
+        self.next_owned = ptr::null_mut();
+    }
+
+    /// The priority of the most urgent waiter
+    unsafe fn top_priority(&self) -> Option<u8> {
+        let front = self.waiters.front();
+        if front.is_null() {
+            None
+        } else {
+            Some((*front).priority)
+        }
+    }
+
+    unsafe fn insert(&mut self, thread: *mut Thread) {
+        let linked = self.linked();
+        match self.order {
+            Order::Priority => self.waiters.insert_by_priority(thread),
+            Order::Fifo => self.waiters.push_back(thread),
+        }
+        if !linked && self.linked() {
+            self.link();
+        }
+    }
+
+    /// Take `thread` off the queue, and its owner off its priority
+    unsafe fn remove(&mut self, thread: *mut Thread) {
+        self.waiters.remove(thread);
+        if !self.owner.is_null() && self.waiters.is_empty() {
+            self.unlink();
+        }
+        (*thread).wait_queue = ptr::null_mut();
+        (*thread).timer.cancel();
+        if !self.owner.is_null() {
+            update_priority(self.owner);
+        }
+    }
+}
+
+/// The control block of the thread calling this
+pub(crate) fn current_thread() -> *mut Thread {
+    CURRENT_THREAD.load(Ordering::Relaxed)
+}
+
+/// Block the current thread on `queue` until it is woken up, or `timeout`
+/// ticks passed
+///
+/// Without a queue the thread sleeps until the timeout. `data` is for the
+/// object, see `wake_matching()`. Called with interrupts disabled, the
+/// thread stops running at the latest when they are enabled again.
+pub(crate) unsafe fn block(
+    queue: *mut WaitQueue,
+    timeout: Option<u64>,
+    blocked_on: BlockedOn,
+    data: usize,
+) {
+    let current = current_thread();
+    if queue.is_null() {
+        (*current).set_state(ThreadState::Sleeping);
+    } else {
+        (*current).set_state(ThreadState::Blocked);
+    }
+    (*current).blocked_on = blocked_on;
+    (*current).wait_queue = queue;
+    (*current).wait_data = data;
+    (*current).wake_reason = WakeReason::Woken;
+    if let Some(ticks) = timeout {
+        (*current)
+            .timer
+            .set_oneshot(ticks, wait_timeout, current as usize);
+    }
+    if !queue.is_null() {
+        (*queue).insert(current);
+        if !(*queue).owner.is_null() {
+            update_priority((*queue).owner);
+        }
+    }
+
+    sched::reschedule();
+}
+
+/// Why the current thread left the wait queue it blocked on
+pub(crate) fn wake_reason() -> WakeReason {
+    unsafe { (*current_thread()).wake_reason }
+}
+
+/// Take the first waiter off `queue`, without making it ready
+pub(crate) unsafe fn dequeue(queue: *mut WaitQueue) -> Option<*mut Thread> {
+    let thread = (*queue).waiters.front();
+    if thread.is_null() {
+        None
+    } else {
+        (*queue).remove(thread);
+        Some(thread)
+    }
+}
+
+/// Make a thread taken off a wait queue ready, returns whether it should
+/// preempt the current thread
+pub(crate) unsafe fn ready(thread: *mut Thread, reason: WakeReason) -> bool {
+    (*thread).wake_reason = reason;
+    sched::make_ready(thread)
+}
+
+/// Make the first waiter of `queue` ready, returns whether it should
+/// preempt the current thread
+pub(crate) unsafe fn wake_one(queue: *mut WaitQueue, reason: WakeReason) -> bool {
+    match dequeue(queue) {
+        Some(thread) => ready(thread, reason),
+        None => false,
+    }
+}
+
+/// Make every waiter of `queue` ready, returns whether one of them should
+/// preempt the current thread
+pub(crate) unsafe fn wake_all(queue: *mut WaitQueue, reason: WakeReason) -> bool {
+    let mut preempt = false;
+    while let Some(thread) = dequeue(queue) {
+        preempt |= ready(thread, reason);
+    }
+    preempt
+}
+
+/// Make the waiters of `queue` that `wanted` picks ready, first to last,
+/// returns whether one of them should preempt the current thread
+///
+/// `wanted` gets the data each waiter blocked with.
+pub(crate) unsafe fn wake_matching<F>(
+    queue: *mut WaitQueue,
+    reason: WakeReason,
+    mut wanted: F,
+) -> bool
+where
+    F: FnMut(usize) -> bool,
+{
+    let mut preempt = false;
+    let mut thread = (*queue).waiters.front();
+    while !thread.is_null() {
+        let next = (*thread).queue_next;
+        if wanted((*thread).wait_data) {
+            (*queue).remove(thread);
+            preempt |= ready(thread, reason);
+        }
+        thread = next;
+    }
+    preempt
+}
+
+/// Let a more urgent thread that was woken up run, called from a thread
+/// once the lock of the object is released
+///
+/// The current thread also gives up the core if it dropped below a ready
+/// thread, because it gave up a queue it inherited a priority from.
+pub fn preempt(woke_urgent: bool) {
+    if woke_urgent {
+        sched::reschedule();
+    } else {
+        sched::thread_preempt();
+    }
+}
+
+/// `preempt()` for interrupt handlers, the preemption waits until they
+/// returned
+pub fn preempt_from_isr(woke_urgent: bool) {
+    if woke_urgent {
+        sched::request_preempt();
+    }
+}
+
+/// Timer callback of a blocked or sleeping thread, `arg` is its control
+/// block
+fn wait_timeout(_now: u64, arg: usize) {
+    let thread = arg as *mut Thread;
+    let state = arch::irq_save();
+    unsafe {
+        let queue = (*thread).wait_queue;
+        let waiting = if queue.is_null() {
+            (*thread).state == ThreadState::Sleeping
+        } else {
+            (*queue).remove(thread);
+            true
+        };
+        if waiting && ready(thread, WakeReason::TimedOut) {
+            sched::request_preempt();
+        }
+    }
+    arch::irq_restore(state);
+}
+
+/// Give `thread` the priority of its most urgent waiter, or the ceiling of a
+/// queue it owns, if that is above its own, and pass it on to the owner of
+/// the queue it is blocked on
+unsafe fn update_priority(mut thread: *mut Thread) {
+    loop {
+        let mut priority = (*thread).base_priority;
+        if let Some(ceiling) = (*thread).ceilings.iter().rposition(|&held| held > 0) {
+            priority = priority.max(ceiling as u8);
+        }
+        let mut queue = (*thread).owned;
+        while !queue.is_null() {
+            if let Some(top) = (*queue).top_priority() {
+                priority = priority.max(top);
+            }
+            queue = (*queue).next_owned;
+        }
+
+        if priority == (*thread).priority {
+            return;
+        }
+        if sched::set_priority(thread, priority) {
+            sched::request_preempt();
+        }
+
+        // Its place among the waiters changed, and so may the priority of
+        // the thread they wait for
+        let queue = (*thread).wait_queue;
+        if queue.is_null() {
+            return;
+        }
+        if (*queue).order == Order::Priority {
+            (*queue).waiters.remove(thread);
+            (*queue).waiters.insert_by_priority(thread);
+        }
+        thread = (*queue).owner;
+        if thread.is_null() {
+            return;
+        }
+    }
+}